@@ -1,11 +1,17 @@
 use std::env;
+use std::time::Duration;
 
 use anyhow::Result;
 use rdkafka::consumer::{Consumer, StreamConsumer};
 use rdkafka::message::Message;
+use shared::cloudevents;
 use shared::config::AppConfig;
+use shared::context::LabContext;
 use shared::create_consumer_props;
-use shared::event::Event;
+use shared::latency::LatencyTracker;
+use shared::logging::{self, LogFormat};
+use shared::shutdown::{self, Shutdown};
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -13,6 +19,8 @@ async fn main() -> Result<()> {
     let mut cfg_path = "shared/config.toml".to_string();
     let mut group_override: Option<String> = None;
     let mut profile = "lab1.keyed".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut latency_report_secs: u64 = 10; // 0 disables periodic reports
 
     while let Some(a) = args.next() {
         match a.as_str() {
@@ -31,10 +39,21 @@ async fn main() -> Result<()> {
                     profile = p;
                 }
             }
+            "--latency-report-secs" => {
+                if let Some(secs) = args.next() {
+                    latency_report_secs = secs.parse().unwrap_or(10);
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
             _ => {}
         }
     }
     let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
     let group_id = group_override
         .as_deref()
         .or(cfg.group_id.as_deref())
@@ -47,30 +66,78 @@ async fn main() -> Result<()> {
         ("auto.offset.reset", cfg.auto_offset_reset.to_string()),
     ];
 
-    let consumer: StreamConsumer = create_consumer_props(props)?;
+    let consumer: StreamConsumer<LabContext> = create_consumer_props(props)?;
     consumer.subscribe(&[&cfg.topic])?;
-    eprintln!(
-        "Consumer using config: {cfg_path} | group='{group_id}' | topic='{}'",
-        cfg.topic
+    info!(
+        config = %cfg_path,
+        group = group_id,
+        topic = %cfg.topic,
+        "Consumer started"
     );
 
+    let shutdown = Shutdown::listen();
+    let mut latency = LatencyTracker::new();
+    let mut report_every = tokio::time::interval(Duration::from_secs(latency_report_secs.max(1)));
+    report_every.tick().await; // the first tick completes immediately
+
     loop {
-        match consumer.recv().await {
-            Err(e) => eprintln!("Read error: {e}"),
+        let msg = tokio::select! {
+            msg = consumer.recv() => msg,
+            _ = report_every.tick(), if latency_report_secs > 0 => {
+                latency.report_window();
+                continue;
+            }
+            _ = shutdown.requested() => break,
+        };
+
+        match msg {
+            Err(e) => warn!(group = group_id, error = %e, "Read error"),
             Ok(m) => {
                 let key = m.key().and_then(|k| std::str::from_utf8(k).ok());
-                let payload = m.payload().unwrap_or_default();
                 let partition = m.partition();
                 let offset = m.offset();
+                let latency_ms = latency.record(&m).map(|us| us as f64 / 1_000.0);
 
-                match serde_json::from_slice::<Event>(payload) {
-                    Ok(ev) => println!(
-                        "partition={partition} @ offset={offset} key={:?} => {:?}",
-                        key, ev
+                match cloudevents::decode(&m) {
+                    Ok(decoded) => match decoded.attributes {
+                        Some(ce) => info!(
+                            group = group_id,
+                            partition,
+                            offset,
+                            key = ?key,
+                            latency_ms,
+                            ce_mode = ?decoded.mode,
+                            ce_id = %ce.id,
+                            ce_source = %ce.source,
+                            ce_type = %ce.ty,
+                            ce_time = ?ce.time.map(|t| t.to_rfc3339()),
+                            event = ?decoded.event,
+                            "Received"
+                        ),
+                        None => info!(
+                            group = group_id,
+                            partition,
+                            offset,
+                            key = ?key,
+                            latency_ms,
+                            event = ?decoded.event,
+                            "Received"
+                        ),
+                    },
+                    Err(e) => warn!(
+                        group = group_id,
+                        partition,
+                        offset,
+                        key = ?key,
+                        error = %e,
+                        "Non-JSON message"
                     ),
-                    Err(_) => eprintln!("Non-JSON message p{partition} @ {offset} key={:?}", key),
                 }
             }
         }
     }
+
+    latency.report_total();
+    shutdown::close_consumer(&consumer, group_id);
+    Ok(())
 }
@@ -1,18 +1,34 @@
 use anyhow::Result;
 use rdkafka::producer::future_producer::Delivery;
-use shared::config::AppConfig;
+use shared::cloudevents::{self, CloudEventAttributes};
+use shared::config::{AppConfig, PartitioningMode};
 use shared::create_producer_props;
 use shared::event::Event;
-use shared::record::create_future_record;
+use shared::ingest::{self, ColumnMapping, IngestError, InputFormat};
+use shared::latency;
+use shared::logging::{self, LogFormat};
+use shared::pipeline::{DeliveryPipeline, Report};
+use shared::record::{create_cloudevent_record, create_future_record};
+use shared::shutdown::{self, Shutdown};
 use std::env;
-use std::io::{self, BufRead};
-use std::time::Duration;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let mut args = env::args();
     let mut cfg_path = "shared/config.toml".to_string();
     let mut profile = "lab1.keyed".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut window: usize = 1;
+    let mut input_path: Option<String> = None;
+    let mut format: Option<InputFormat> = None;
+    let mut mapping = ColumnMapping::default();
+    let mut validate_only = false;
+    let mut progress_every = Duration::from_secs(5);
 
     while let Some(a) = args.next() {
         match a.as_str() {
@@ -26,59 +42,256 @@ async fn main() -> Result<()> {
                     profile = p;
                 }
             }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--window" => {
+                if let Some(n) = args.next() {
+                    window = n.parse().unwrap_or(1);
+                }
+            }
+            "--input" => {
+                if let Some(p) = args.next() {
+                    input_path = Some(p);
+                }
+            }
+            "--format" => {
+                if let Some(f) = args.next() {
+                    format = Some(f.parse()?);
+                }
+            }
+            "--map" => {
+                if let Some(spec) = args.next() {
+                    mapping.apply(&spec)?;
+                }
+            }
+            "--validate-only" => validate_only = true,
+            "--progress-secs" => {
+                if let Some(s) = args.next() {
+                    progress_every = s.parse().map(Duration::from_secs).unwrap_or(progress_every);
+                }
+            }
             _ => {}
         }
     }
 
     let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+
+    let format = format.unwrap_or_else(|| match &input_path {
+        Some(path) => InputFormat::from_path(Path::new(path)),
+        None => InputFormat::Text,
+    });
+    let mut rows = match &input_path {
+        Some(path) => ingest::spawn_reader(File::open(path)?, format, mapping.clone()),
+        None => ingest::spawn_reader(io::stdin(), format, mapping.clone()),
+    };
+    if validate_only {
+        return validate(&mut rows).await;
+    }
 
     let timeout = cfg.message_timeout_ms.unwrap_or(5000).to_string();
     let compression = cfg.compression.unwrap_or("lz4".to_string());
-    let props = &[
+    let linger = cfg.linger_ms.unwrap_or(5).to_string();
+    let mut props = vec![
         ("bootstrap.servers", cfg.bootstrap_servers),
-        ("compression.type", compression),
-        ("message.timeout.ms", timeout),
+        ("compression.type", compression.clone()),
+        ("message.timeout.ms", timeout.clone()),
+        ("linger.ms", linger.clone()),
     ];
+    if let Some(n) = cfg.max_in_flight {
+        props.push(("max.in.flight.requests.per.connection", n.to_string()));
+    }
+    if let Some(on) = cfg.enable_idempotence {
+        props.push(("enable.idempotence", on.to_string()));
+    }
+
+    let producer = create_producer_props(&props)?;
 
-    let producer = create_producer_props(props)?;
+    let ce_source = cfg
+        .cloudevents_source
+        .as_deref()
+        .unwrap_or(cloudevents::DEFAULT_SOURCE);
 
-    eprintln!(
-        "Producer started with {:?} partitioning. Using config: {}",
-        cfg.partitioning, cfg_path
+    info!(
+        config = %cfg_path,
+        partitioning = ?cfg.partitioning,
+        cloudevents = ?cfg.cloudevents,
+        compression = %compression,
+        linger_ms = %linger,
+        message_timeout_ms = %timeout,
+        max_in_flight = cfg.max_in_flight,
+        enable_idempotence = cfg.enable_idempotence,
+        window,
+        input = ?input_path,
+        format = ?format,
+        "Producer started"
     );
-    eprintln!("Enter: user_id action value (e.g. u1 click 42). Ctrl+D to exit.");
+    if input_path.is_none() && format == InputFormat::Text {
+        info!("Enter: user_id action value (e.g. u1 click 42). Ctrl+D to exit.");
+    }
 
-    for line in io::stdin().lock().lines() {
-        let line = line?;
-        let parts: Vec<_> = line.split_whitespace().collect();
-        if parts.len() < 3 {
-            eprintln!("Format: user_id action value");
-            continue;
+    // Per-record reports would drown a file replay; progress lines replace them.
+    let verbose = input_path.is_none();
+    let shutdown = Shutdown::listen();
+    let mut pipeline: DeliveryPipeline<String> = DeliveryPipeline::new(window);
+    let mut delivered: u64 = 0;
+    let mut failed: u64 = 0;
+    let mut invalid: u64 = 0;
+    let mut tally = |report: Report<String>| {
+        if log_report(&report, cfg.partitioning, verbose) {
+            delivered += 1;
+        } else {
+            failed += 1;
         }
+    };
+    let started = Instant::now();
+    let mut progress = tokio::time::interval(progress_every);
+    progress.tick().await; // the first tick completes immediately
+    let mut read: u64 = 0;
 
-        let evt = Event {
-            user_id: parts[0].to_string(),
-            action: parts[1].to_string(),
-            value: parts[2].parse().unwrap_or(0),
+    loop {
+        let row = tokio::select! {
+            row = rows.recv() => match row {
+                Some(Ok(row)) => row,
+                // Not about a row (e.g. a mapped column missing from the CSV header).
+                Some(Err(e)) if e.line().is_none() => return Err(e.into()),
+                Some(Err(e)) => {
+                    invalid += 1;
+                    warn!(line = e.line(), error = %e, "⚠️ Skipping invalid row");
+                    continue;
+                }
+                None => break,
+            },
+            // Report deliveries as they complete, also while waiting for input.
+            Some(report) = pipeline.next_report(), if pipeline.pending() > 0 => {
+                tally(report);
+                continue;
+            }
+            _ = progress.tick(), if !verbose => {
+                info!(
+                    read,
+                    invalid,
+                    pending = pipeline.pending(),
+                    elapsed_secs = started.elapsed().as_secs(),
+                    "📊 progress"
+                );
+                continue;
+            }
+            _ = shutdown.requested() => break,
         };
+        read += 1;
+        let evt: &Event = &row.event;
 
-        let payload = serde_json::to_vec(&evt)?;
+        let reports = match cfg.cloudevents {
+            Some(mode) => {
+                let attributes = CloudEventAttributes::for_event(ce_source, evt);
+                let encoded = cloudevents::encode(mode, &attributes, evt)?;
+                let record = create_cloudevent_record(
+                    Some(&row.key),
+                    &encoded,
+                    &cfg.topic,
+                    cfg.partitioning,
+                )?;
+                pipeline
+                    .send(
+                        &producer,
+                        row.apply(latency::stamp(record)),
+                        row.key.clone(),
+                    )
+                    .await
+            }
+            None => {
+                let payload = serde_json::to_vec(evt)?;
+                let record =
+                    create_future_record(Some(&row.key), &payload, &cfg.topic, cfg.partitioning)?;
+                pipeline
+                    .send(
+                        &producer,
+                        row.apply(latency::stamp(record)),
+                        row.key.clone(),
+                    )
+                    .await
+            }
+        };
+        reports.into_iter().for_each(&mut tally);
+    }
 
-        let record =
-            create_future_record(Some(&evt.user_id), &payload, &cfg.topic, cfg.partitioning)?;
+    if pipeline.pending() > 0 {
+        info!(
+            pending = pipeline.pending(),
+            "⏳ Waiting for outstanding deliveries"
+        );
+    }
+    pipeline
+        .drain(shutdown::FLUSH_TIMEOUT)
+        .await
+        .into_iter()
+        .for_each(&mut tally);
+    let undelivered = pipeline.pending();
+    if undelivered > 0 {
+        warn!(
+            undelivered,
+            timeout_ms = shutdown::FLUSH_TIMEOUT.as_millis() as u64,
+            "📤 Messages left undelivered"
+        );
+    }
+    let secs = started.elapsed().as_secs_f64();
+    info!(
+        delivered,
+        failed,
+        invalid,
+        undelivered,
+        queue_full = pipeline.queue_full(),
+        elapsed_secs = secs,
+        msgs_per_sec = (delivered as f64 / secs).round(),
+        "👋 Producer stopped"
+    );
 
-        let delivery = producer.send(record, Duration::from_secs(0)).await;
+    Ok(())
+}
 
-        match delivery {
-            Ok(Delivery {
-                partition, offset, ..
-            }) => eprintln!(
-                "✅ Sent p{partition} @ {offset} key_mode={:?}",
-                cfg.partitioning
-            ),
-            Err((e, _)) => eprintln!("❌ Delivery failed: {e}"),
+/// Reads every row without producing, reporting each invalid one.
+async fn validate(
+    rows: &mut tokio::sync::mpsc::Receiver<Result<ingest::IngestRecord, IngestError>>,
+) -> Result<()> {
+    let mut valid: u64 = 0;
+    let mut invalid: u64 = 0;
+    while let Some(row) = rows.recv().await {
+        match row {
+            Ok(_) => valid += 1,
+            Err(e) => {
+                invalid += 1;
+                warn!(line = e.line(), error = %e, "⚠️ Invalid row");
+            }
         }
     }
-
+    info!(valid, invalid, "🔎 Validation finished");
+    if invalid > 0 {
+        anyhow::bail!("{invalid} invalid row(s)");
+    }
     Ok(())
 }
+
+/// Logs a delivery report (successes only when `verbose`); returns whether
+/// the record was delivered.
+fn log_report(report: &Report<String>, key_mode: PartitioningMode, verbose: bool) -> bool {
+    match &report.result {
+        Ok(Delivery {
+            partition, offset, ..
+        }) => {
+            if verbose {
+                info!(partition, offset, key = %report.tag, key_mode = ?key_mode, "✅ Sent");
+            } else {
+                debug!(partition, offset, key = %report.tag, key_mode = ?key_mode, "✅ Sent");
+            }
+            true
+        }
+        Err(e) => {
+            error!(key = %report.tag, error = %e, "❌ Delivery failed");
+            false
+        }
+    }
+}
@@ -5,15 +5,20 @@ use anyhow::Result;
 use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
 use rdkafka::message::Message;
 use shared::config::AppConfig;
+use shared::context::LabContext;
 use shared::create_consumer_props;
 use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::shutdown::{self, Shutdown};
 use tokio::time::sleep;
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let mut args = env::args();
     let mut cfg_path = "shared/config.toml".to_string();
     let mut profile = "lab2.keyed".to_string();
+    let mut log_format: Option<LogFormat> = None;
     let mut group_override: Option<String> = None;
     let mut fail_mod: i64 = 5; // fail when value % fail_mod == 0 (if >0)
     let mut fail_action: Option<String> = None; // also fail if action matches
@@ -45,10 +50,16 @@ async fn main() -> Result<()> {
                     fail_action = Some(act);
                 }
             }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
             _ => {}
         }
     }
     let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
     let group_id = group_override
         .as_deref()
         .or(cfg.group_id.as_deref())
@@ -68,24 +79,25 @@ async fn main() -> Result<()> {
         ),
         ("auto.offset.reset", cfg.auto_offset_reset),
     ];
-    let consumer: StreamConsumer = create_consumer_props(props)?;
+    let consumer: StreamConsumer<LabContext> = create_consumer_props(props)?;
     consumer.subscribe(&[&cfg.topic])?;
-    eprintln!(
-        "Lab 2 consumer | cfg={cfg_path} | group='{group_id}' | topic='{}'",
-        cfg.topic
-    );
-    eprintln!(
-        "Fail rules: value % {} == 0 {}",
+    info!(
+        config = %cfg_path,
+        group = group_id,
+        topic = %cfg.topic,
         fail_mod,
-        fail_action
-            .as_ref()
-            .map(|a| format!("or action == '{a}'"))
-            .unwrap_or_default()
+        fail_action = ?fail_action,
+        "Lab 2 consumer started (fail when value % fail_mod == 0 or action == fail_action)"
     );
 
+    let shutdown = Shutdown::listen();
     loop {
-        match consumer.recv().await {
-            Err(e) => eprintln!("Read error: {e}"),
+        let msg = tokio::select! {
+            msg = consumer.recv() => msg,
+            _ = shutdown.requested() => break,
+        };
+        match msg {
+            Err(e) => warn!(group = group_id, error = %e, "Read error"),
             Ok(m) => {
                 let key = m.key().and_then(|k| std::str::from_utf8(k).ok());
                 let payload = m.payload().unwrap_or_default();
@@ -93,34 +105,52 @@ async fn main() -> Result<()> {
                 let offset = m.offset();
 
                 match serde_json::from_slice::<Event>(payload) {
-                    Err(_) => {
-                        eprintln!("❌ Non-JSON p{partition} @ {offset} key={key:?} -> NO COMMIT")
-                    }
+                    Err(_) => warn!(
+                        group = group_id,
+                        partition,
+                        offset,
+                        key = ?key,
+                        "❌ Non-JSON -> NO COMMIT"
+                    ),
                     Ok(ev) => {
                         let mut fail = false;
                         if fail_mod > 0 && ev.value % fail_mod == 0 {
                             fail = true;
                         }
-                        if let Some(a) = &fail_action {
-                            if ev.action == *a {
-                                fail = true;
-                            }
+                        if let Some(a) = &fail_action
+                            && ev.action == *a
+                        {
+                            fail = true;
                         }
 
                         if fail {
-                            eprintln!(
-                                "❌ Simulated failure p{partition} @ {offset} key={key:?} => {:?}",
-                                ev
+                            warn!(
+                                group = group_id,
+                                partition,
+                                offset,
+                                key = ?key,
+                                event = ?ev,
+                                "❌ Simulated failure"
                             );
                             sleep(Duration::from_millis(200)).await;
                         } else {
                             consumer.store_offset(&cfg.topic, partition, offset)?; // mark as processed
                             consumer.commit_message(&m, CommitMode::Sync)?; // commit offset
-                            println!("✅ COMMIT p{partition} @ {offset} key={key:?} => {:?}", ev);
+                            info!(
+                                group = group_id,
+                                partition,
+                                offset,
+                                key = ?key,
+                                event = ?ev,
+                                "✅ COMMIT"
+                            );
                         }
                     }
                 }
             }
         }
     }
+
+    shutdown::close_consumer(&consumer, group_id);
+    Ok(())
 }
@@ -8,7 +8,10 @@ use rdkafka::message::Message;
 use shared::config::AppConfig;
 use shared::create_consumer_props;
 use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::shutdown::{self, Shutdown};
 use tokio::time::sleep;
+use tracing::{info, warn};
 
 fn instance_id() -> String {
     // Allow override via ENV; otherwise use PID for uniqueness
@@ -23,6 +26,7 @@ async fn main() -> Result<()> {
     let mut args = env::args();
     let mut cfg_path = "shared/config.toml".to_string();
     let mut profile = "lab3.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
     let mut group_override: Option<String> = None;
 
     while let Some(a) = args.next() {
@@ -42,10 +46,16 @@ async fn main() -> Result<()> {
                     group_override = Some(g);
                 }
             }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
             _ => {}
         }
     }
     let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
     let group_id = group_override
         .as_deref()
         .or(cfg.group_id.as_deref())
@@ -70,9 +80,12 @@ async fn main() -> Result<()> {
     let consumer = Arc::new(create_consumer_props(props)?);
     consumer.subscribe(&[&cfg.topic])?;
 
-    eprintln!(
-        "[{id}] started | profile={profile} | group={group_id} | topic='{}'",
-        cfg.topic
+    info!(
+        instance = %id,
+        profile = %profile,
+        group = group_id,
+        topic = %cfg.topic,
+        "Consumer started"
     );
 
     // Periodically print current assignment so rebalances are visible.
@@ -87,17 +100,22 @@ async fn main() -> Result<()> {
                         .into_iter()
                         .map(|tp| format!("p{}", tp.partition()))
                         .collect();
-                    eprintln!("[{id2}] assignment: [{}]", parts.join(", "));
+                    info!(instance = %id2, "assignment: [{}]", parts.join(", "));
                 }
-                Err(e) => eprintln!("[{id2}] assignment error: {e}"),
+                Err(e) => warn!(instance = %id2, error = %e, "assignment error"),
             }
             sleep(Duration::from_secs(5)).await;
         }
     });
 
+    let shutdown = Shutdown::listen();
     loop {
-        match consumer.recv().await {
-            Err(e) => eprintln!("[{id}] read error: {e}"),
+        let msg = tokio::select! {
+            msg = consumer.recv() => msg,
+            _ = shutdown.requested() => break,
+        };
+        match msg {
+            Err(e) => warn!(instance = %id, group = group_id, error = %e, "read error"),
             Ok(m) => {
                 let key = m.key().and_then(|k| std::str::from_utf8(k).ok());
                 let payload = m.payload().unwrap_or_default();
@@ -105,10 +123,28 @@ async fn main() -> Result<()> {
                 let o = m.offset();
 
                 match serde_json::from_slice::<Event>(payload) {
-                    Ok(ev) => println!("[{id}] p{p} @ {o} key={key:?} => {:?}", ev),
-                    Err(_) => eprintln!("[{id}] Non-JSON p{p} @ {o} key={key:?}"),
+                    Ok(ev) => info!(
+                        instance = %id,
+                        group = group_id,
+                        partition = p,
+                        offset = o,
+                        key = ?key,
+                        event = ?ev,
+                        "Received"
+                    ),
+                    Err(_) => warn!(
+                        instance = %id,
+                        group = group_id,
+                        partition = p,
+                        offset = o,
+                        key = ?key,
+                        "Non-JSON"
+                    ),
                 }
             }
         }
     }
+
+    shutdown::close_consumer(consumer.as_ref(), group_id);
+    Ok(())
 }
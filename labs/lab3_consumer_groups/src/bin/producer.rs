@@ -3,16 +3,20 @@ use rdkafka::producer::future_producer::Delivery;
 use shared::config::AppConfig;
 use shared::create_producer_props;
 use shared::event::Event;
+use shared::input;
+use shared::logging::{self, LogFormat};
 use shared::record::create_future_record;
+use shared::shutdown::{self, Shutdown};
 use std::env;
-use std::io::{self, BufRead};
 use std::time::Duration;
+use tracing::{error, info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let mut args = env::args();
     let mut cfg_path = "shared/config.toml".to_string();
     let mut profile = "lab3.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
 
     while let Some(a) = args.next() {
         match a.as_str() {
@@ -26,11 +30,17 @@ async fn main() -> Result<()> {
                     profile = p;
                 }
             }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
             _ => {}
         }
     }
 
     let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
 
     let timeout = cfg.message_timeout_ms.unwrap_or(5000).to_string();
     let compression = cfg.compression.unwrap_or("lz4".to_string());
@@ -42,17 +52,29 @@ async fn main() -> Result<()> {
 
     let producer = create_producer_props(props)?;
 
-    eprintln!(
-        "Producer (Lab 2) using {cfg_path} | partitioning={:?}",
-        cfg.partitioning
+    info!(
+        config = %cfg_path,
+        partitioning = ?cfg.partitioning,
+        "Producer (Lab 3) started"
     );
-    eprintln!("Enter: user_id action value (e.g. u1 click 42). Ctrl+D to exit.");
+    info!("Enter: user_id action value (e.g. u1 click 42). Ctrl+D to exit.");
 
-    for line in io::stdin().lock().lines() {
-        let line = line?;
+    let shutdown = Shutdown::listen();
+    let mut lines = input::stdin_lines();
+    let mut delivered: u64 = 0;
+    let mut failed: u64 = 0;
+
+    loop {
+        let line = tokio::select! {
+            line = lines.recv() => match line {
+                Some(line) => line?,
+                None => break,
+            },
+            _ = shutdown.requested() => break,
+        };
         let parts: Vec<_> = line.split_whitespace().collect();
         if parts.len() < 3 {
-            eprintln!("Format: user_id action value");
+            warn!(line = %line, "Format: user_id action value");
             continue;
         }
 
@@ -72,13 +94,25 @@ async fn main() -> Result<()> {
         match delivery {
             Ok(Delivery {
                 partition, offset, ..
-            }) => eprintln!(
-                "✅ Sent p{partition} @ {offset} key_mode={:?}",
-                cfg.partitioning
-            ),
-            Err((e, _)) => eprintln!("❌ Delivery failed: {e}"),
+            }) => {
+                delivered += 1;
+                info!(
+                    partition,
+                    offset,
+                    key = %evt.user_id,
+                    key_mode = ?cfg.partitioning,
+                    "✅ Sent"
+                )
+            }
+            Err((e, _)) => {
+                failed += 1;
+                error!(key = %evt.user_id, error = %e, "❌ Delivery failed")
+            }
         }
     }
 
+    let undelivered = shutdown::flush_producer(&producer, shutdown::FLUSH_TIMEOUT);
+    info!(delivered, failed, undelivered, "👋 Producer stopped");
+
     Ok(())
 }
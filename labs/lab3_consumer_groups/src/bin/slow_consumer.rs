@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::{Message, OwnedMessage};
+use shared::config::{AppConfig, PartitioningMode};
+use shared::context::LabContext;
+use shared::create_consumer_with_context;
+use shared::event::Event;
+use shared::flow::{Backlog, FlowControl, Watermarks};
+use shared::logging::{self, LogFormat};
+use shared::metrics::Metrics;
+use shared::mock::{Brokers, Prefill};
+use shared::record::create_future_record;
+use shared::shutdown::{self, Shutdown};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// How often the poll loop reports that it is still polling.
+const REPORT_EVERY: Duration = Duration::from_secs(2);
+/// Lowest `session.timeout.ms` brokers accept by default.
+const SESSION_TIMEOUT_MS: u64 = 6000;
+
+/// How the consumer copes with a slow downstream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Process each record inside the poll loop: no poll while it runs.
+    Blocking,
+    /// Hand records to a stage per partition and pause the saturated ones.
+    Pause,
+}
+
+/// Processing stage of one partition: its records, one at a time, in order.
+struct Stage {
+    tx: mpsc::UnboundedSender<OwnedMessage>,
+    backlog: Backlog,
+    task: JoinHandle<()>,
+}
+
+type SlowConsumer = StreamConsumer<LabContext>;
+
+/// The slow downstream: sleeps, then stores the offset for the next auto-commit.
+async fn process(consumer: &SlowConsumer, m: &OwnedMessage, delay: Duration) {
+    tokio::time::sleep(delay).await;
+    // Fails once the partition was revoked: the new owner processes it again.
+    if let Err(e) = consumer.store_offset(m.topic(), m.partition(), m.offset()) {
+        warn!(partition = m.partition(), offset = m.offset(), error = %e, "Offset not stored");
+    }
+}
+
+fn spawn_stage(
+    consumer: Arc<SlowConsumer>,
+    mut rx: mpsc::UnboundedReceiver<OwnedMessage>,
+    backlog: Backlog,
+    delay: Duration,
+    processed: Arc<AtomicU64>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(m) = rx.recv().await {
+            process(&consumer, &m, delay).await;
+            backlog.done();
+            processed.fetch_add(1, Ordering::Relaxed);
+            info!(
+                partition = m.partition(),
+                offset = m.offset(),
+                backlog = backlog.len(),
+                "✅ PROCESSED"
+            );
+        }
+    })
+}
+
+/// Writes `count` events to `topic` for the mock run.
+async fn prefill(bootstrap_servers: &str, topic: &str, count: u64) -> Result<()> {
+    let mut prefill = Prefill::new(bootstrap_servers)?;
+    for i in 0..count {
+        let evt = Event {
+            user_id: format!("u{}", i % 6),
+            action: "click".to_string(),
+            value: i as i64,
+        };
+        let payload = serde_json::to_vec(&evt)?;
+        let record =
+            create_future_record(Some(&evt.user_id), &payload, topic, PartitioningMode::Keyed)?;
+        prefill.send(record).await;
+    }
+    prefill.finish().await?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab3.slow".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut group_override: Option<String> = None;
+    let mut mode = Mode::Pause;
+    let mut delay = Duration::from_secs(2);
+    let mut max_poll_ms: u64 = 10_000;
+    let mut watermarks = Watermarks { high: 3, low: 1 };
+    let mut mock = false;
+    let mut partitions: i32 = 3;
+    let mut count: u64 = 30;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--group-id" => {
+                if let Some(g) = args.next() {
+                    group_override = Some(g);
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--mode" => match args.next().as_deref() {
+                Some("blocking") => mode = Mode::Blocking,
+                Some("pause") => mode = Mode::Pause,
+                other => bail!("--mode expects `blocking` or `pause`, got {other:?}"),
+            },
+            "--process-ms" => {
+                if let Some(ms) = args.next() {
+                    delay = Duration::from_millis(ms.parse().unwrap_or(2000));
+                }
+            }
+            "--max-poll-ms" => {
+                if let Some(ms) = args.next() {
+                    max_poll_ms = ms.parse().unwrap_or(10_000);
+                }
+            }
+            "--high" => {
+                if let Some(n) = args.next() {
+                    watermarks.high = n.parse().unwrap_or(3);
+                }
+            }
+            "--low" => {
+                if let Some(n) = args.next() {
+                    watermarks.low = n.parse().unwrap_or(1);
+                }
+            }
+            "--mock" => mock = true,
+            "--partitions" => {
+                if let Some(n) = args.next() {
+                    partitions = n.parse().unwrap_or(3);
+                }
+            }
+            "--count" => {
+                if let Some(n) = args.next() {
+                    count = n.parse().unwrap_or(30);
+                }
+            }
+            _ => {}
+        }
+    }
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    let group_id = group_override
+        .or(cfg.group_id.clone())
+        .unwrap_or("lab3-slow".to_string());
+
+    let brokers = Brokers::new(mock, &cfg, partitions)?;
+    let bootstrap_servers = brokers.bootstrap_servers();
+    if brokers.mock().is_some() {
+        prefill(&bootstrap_servers, &cfg.topic, count).await?;
+    }
+
+    // Offsets are stored once processed and committed by the auto-commit.
+    let metrics = Metrics::new();
+    let props = &[
+        ("bootstrap.servers", bootstrap_servers),
+        ("group.id", group_id.clone()),
+        ("enable.auto.commit", cfg.enable_auto_commit.to_string()),
+        ("enable.auto.offset.store", "false".to_string()),
+        ("auto.offset.reset", cfg.auto_offset_reset.clone()),
+        ("max.poll.interval.ms", max_poll_ms.to_string()),
+        // librdkafka requires max.poll.interval.ms >= session.timeout.ms.
+        (
+            "session.timeout.ms",
+            SESSION_TIMEOUT_MS.min(max_poll_ms).to_string(),
+        ),
+    ];
+    let (revocations_tx, mut revocations) = mpsc::unbounded_channel();
+    let consumer = Arc::new(create_consumer_with_context(
+        props,
+        LabContext::new(Arc::clone(&metrics)).with_revocations(revocations_tx),
+    )?);
+    consumer.subscribe(&[&cfg.topic])?;
+
+    info!(
+        group = group_id,
+        topic = %cfg.topic,
+        mode = ?mode,
+        process_ms = delay.as_millis() as u64,
+        max_poll_ms,
+        watermarks = ?watermarks,
+        mock,
+        "Slow consumer (Lab 3) started"
+    );
+
+    let shutdown = Shutdown::listen();
+    let mut flow = FlowControl::new();
+    let mut stages: HashMap<(String, i32), Stage> = HashMap::new();
+    let processed = Arc::new(AtomicU64::new(0));
+    let mut report = tokio::time::interval(REPORT_EVERY);
+
+    while !(mock && processed.load(Ordering::Relaxed) >= count) {
+        tokio::select! {
+            // Signals first: a saturated partition is paused before the next record.
+            biased;
+            Some(signal) = flow.next_signal() => flow.apply(consumer.as_ref(), signal),
+            _ = shutdown.requested() => break,
+            Some(revoked) = revocations.recv() => {
+                // Their records are delivered again to the next owner, which
+                // gets a fresh stage and an unpaused partition.
+                for (topic, partition) in revoked {
+                    flow.forget(consumer.as_ref(), &topic, partition);
+                    if let Some(stage) = stages.remove(&(topic, partition)) {
+                        stage.task.abort();
+                    }
+                }
+            }
+            msg = consumer.recv() => {
+                let m = match msg {
+                    Ok(m) => m.detach(),
+                    Err(e) => {
+                        warn!(group = group_id, error = %e, "Read error");
+                        continue;
+                    }
+                };
+                match mode {
+                    Mode::Blocking => {
+                        info!(partition = m.partition(), offset = m.offset(), "📥 Received, processing in the poll loop");
+                        process(&consumer, &m, delay).await;
+                        processed.fetch_add(1, Ordering::Relaxed);
+                        info!(partition = m.partition(), offset = m.offset(), "✅ PROCESSED");
+                    }
+                    Mode::Pause => {
+                        let key = (m.topic().to_string(), m.partition());
+                        let stage = stages.entry(key).or_insert_with(|| {
+                            let (tx, rx) = mpsc::unbounded_channel();
+                            let backlog = Backlog::new(m.topic(), m.partition(), watermarks, flow.backpressure());
+                            let task = spawn_stage(Arc::clone(&consumer), rx, backlog.clone(), delay, Arc::clone(&processed));
+                            Stage { tx, backlog, task }
+                        });
+                        stage.backlog.push();
+                        info!(partition = m.partition(), offset = m.offset(), backlog = stage.backlog.len(), "📥 Received, handed to the stage");
+                        let _ = stage.tx.send(m);
+                    }
+                }
+            }
+            _ = report.tick() => {
+                let paused: Vec<i32> = flow.paused().into_iter().map(|(_, p)| p).collect();
+                let backlog: usize = stages.values().map(|s| s.backlog.len()).sum();
+                info!(
+                    paused = ?paused,
+                    backlog,
+                    processed = processed.load(Ordering::Relaxed),
+                    rebalances = metrics.rebalances(),
+                    "📊 Still polling"
+                );
+            }
+        }
+    }
+
+    // Records still in a stage stay uncommitted: they are delivered again.
+    for stage in stages.into_values() {
+        stage.task.abort();
+        let _ = stage.task.await;
+    }
+    info!(
+        processed = processed.load(Ordering::Relaxed),
+        pauses = flow.pauses(),
+        rebalances = metrics.rebalances(),
+        "🏁 Slow consumer stopped"
+    );
+    shutdown::close_consumer(consumer.as_ref(), &group_id);
+    Ok(())
+}
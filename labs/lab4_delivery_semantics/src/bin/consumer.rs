@@ -1,4 +1,5 @@
 use std::env;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -6,21 +7,41 @@ use anyhow::Result;
 use rdkafka::consumer::{CommitMode, Consumer};
 use rdkafka::message::Message;
 use shared::config::AppConfig;
-use shared::create_consumer_props;
+use shared::context::LabContext;
+use shared::create_consumer_with_context;
+use shared::dedup::{Deduplicator, IdSource};
 use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::metrics::{self, Metrics};
+use shared::offsets::{FlushPolicy, OffsetTracker, SharedOffsetTracker};
+use shared::shutdown::{self, Shutdown};
+use shared::state::{DiskStore, MemoryStore, Offsets, StateStore};
+use shared::telemetry::{self, TraceExport};
+use shared::util::parse_duration;
 use tokio::time::sleep;
+use tracing::{error, info, info_span, warn};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum CommitModeCli {
-    Pre,  // commit before processing (at-most-once pattern)
-    Post, // commit after processing (at-least-once pattern)
-    Txn,  // placeholder (transactions), not fully implemented in this lab
+    Pre,   // commit before processing (at-most-once pattern)
+    Post,  // commit after processing (at-least-once pattern)
+    Batch, // like post, but commits completed offsets in batches via OffsetTracker
+    Txn,   // placeholder (transactions), not fully implemented in this lab
+}
+
+/// Where the dedup store keeps the ids of processed messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DedupStoreCli {
+    Memory, // forgotten on restart
+    Disk,   // survives restarts
 }
 
 fn parse_commit_mode(s: &str) -> CommitModeCli {
     match s {
         "pre" => CommitModeCli::Pre,
         "post" => CommitModeCli::Post,
+        "batch" => CommitModeCli::Batch,
         "txn" | "transactional" => CommitModeCli::Txn,
         _ => CommitModeCli::Post,
     }
@@ -31,10 +52,21 @@ async fn main() -> Result<()> {
     let mut args = env::args();
     let mut cfg_path = "shared/config.toml".to_string();
     let mut profile = "lab4.atleastonce".to_string();
+    let mut log_format: Option<LogFormat> = None;
     let mut group_override: Option<String> = None;
     let mut commit_mode = CommitModeCli::Post;
     let mut fail_mod: i64 = 0; // 0 disables failure-by-mod
     let mut crash_after: i64 = -1; // -1 disables crash-after counter
+    let mut flush_policy = FlushPolicy::default();
+    let mut metrics_addr: Option<String> = None;
+    let mut otlp_endpoint: Option<String> = None;
+    let mut trace_file: Option<String> = None;
+    let mut crash_after_process: i64 = 0; // 0 disables the crash between processing and commit
+    let mut dedup_store: Option<DedupStoreCli> = None;
+    let mut dedup_id = IdSource::Header("message-id".to_string());
+    let mut dedup_ttl = Duration::from_secs(24 * 3600);
+    let mut dedup_max: usize = 100_000;
+    let mut dedup_path = PathBuf::from("lab4-dedup.redb");
 
     while let Some(a) = args.next() {
         match a.as_str() {
@@ -68,10 +100,85 @@ async fn main() -> Result<()> {
                     crash_after = ca.parse().unwrap_or(-1);
                 }
             }
+            "--crash-after-process" => {
+                if let Some(n) = args.next() {
+                    crash_after_process = n.parse().unwrap_or(0);
+                }
+            }
+            "--dedup" => match args.next().as_deref() {
+                Some("memory") => dedup_store = Some(DedupStoreCli::Memory),
+                Some("disk") => dedup_store = Some(DedupStoreCli::Disk),
+                other => anyhow::bail!("--dedup expects `memory` or `disk`, got {other:?}"),
+            },
+            "--dedup-id" => {
+                if let Some(id) = args.next() {
+                    dedup_id = id.parse()?;
+                }
+            }
+            "--dedup-ttl" => {
+                if let Some(ttl) = args.next() {
+                    dedup_ttl = parse_duration(&ttl)?;
+                }
+            }
+            "--dedup-max" => {
+                if let Some(n) = args.next() {
+                    dedup_max = n.parse().unwrap_or(dedup_max);
+                }
+            }
+            "--dedup-path" => {
+                if let Some(p) = args.next() {
+                    dedup_path = PathBuf::from(p);
+                }
+            }
+            "--commit-every" => {
+                if let Some(n) = args.next() {
+                    flush_policy.max_completed = n.parse().unwrap_or(flush_policy.max_completed);
+                }
+            }
+            "--commit-interval-ms" => {
+                if let Some(ms) = args.next() {
+                    flush_policy.max_interval = ms
+                        .parse()
+                        .map(Duration::from_millis)
+                        .unwrap_or(flush_policy.max_interval);
+                }
+            }
+            "--metrics-addr" => {
+                if let Some(addr) = args.next() {
+                    metrics_addr = Some(addr);
+                }
+            }
+            "--otlp-endpoint" => {
+                if let Some(e) = args.next() {
+                    otlp_endpoint = Some(e);
+                }
+            }
+            "--trace-file" => {
+                if let Some(f) = args.next() {
+                    trace_file = Some(f);
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
             _ => {}
         }
     }
     let cfg = AppConfig::from_file(&cfg_path, &profile);
+    let trace_export = TraceExport::from_options(
+        otlp_endpoint.or(cfg.otlp_endpoint.clone()),
+        trace_file.or(cfg.trace_file.clone()),
+    );
+    let tracer_provider = trace_export
+        .as_ref()
+        .map(|export| telemetry::init_tracer_provider("lab4-consumer", export))
+        .transpose()?;
+    logging::init_with_tracer(
+        log_format.or(cfg.log_format).unwrap_or_default(),
+        tracer_provider.as_ref().map(telemetry::tracer),
+    );
     let group_id = group_override
         .as_deref()
         .or(cfg.group_id.as_deref())
@@ -80,6 +187,8 @@ async fn main() -> Result<()> {
     let enable_auto_offset_store = cfg
         .enable_auto_offset_store
         .expect("enable_auto_offset_store config was expected");
+    let statistics_interval = cfg.statistics_interval_ms.unwrap_or(0).to_string();
+    let metrics_addr = metrics_addr.or(cfg.metrics_addr);
 
     let props = &[
         ("bootstrap.servers", cfg.bootstrap_servers),
@@ -90,20 +199,78 @@ async fn main() -> Result<()> {
             enable_auto_offset_store.to_string(),
         ),
         ("auto.offset.reset", cfg.auto_offset_reset),
+        ("statistics.interval.ms", statistics_interval),
     ];
-    let consumer = Arc::new(create_consumer_props(props)?);
+    let metrics = Metrics::new();
+    let offsets: SharedOffsetTracker = OffsetTracker::shared(flush_policy);
+    let mut context = LabContext::new(Arc::clone(&metrics));
+    if commit_mode == CommitModeCli::Batch {
+        context = context.with_offset_tracker(Arc::clone(&offsets));
+    }
+    let consumer = Arc::new(create_consumer_with_context(props, context)?);
     consumer.subscribe(&[&cfg.topic])?;
 
-    eprintln!(
-        "Lab4 consumer| profile={profile} | group={group_id} | topic='{}' | mode={commit_mode:?} | fail_mod={fail_mod} | crash_after={crash_after}",
-        cfg.topic
+    if let Some(addr) = metrics_addr {
+        metrics::spawn_server(addr, Arc::clone(&metrics));
+    }
+
+    // Idempotent consumer: only `post` records what it processed before committing.
+    let mut dedup = match dedup_store {
+        Some(_) if commit_mode != CommitModeCli::Post => {
+            warn!(mode = ?commit_mode, "--dedup only applies to --commit-mode post, ignored");
+            None
+        }
+        Some(DedupStoreCli::Memory) => {
+            let store: Box<dyn StateStore> = Box::new(MemoryStore::new());
+            Some(Deduplicator::new(store, dedup_ttl, dedup_max)?)
+        }
+        Some(DedupStoreCli::Disk) => {
+            let store: Box<dyn StateStore> = Box::new(DiskStore::open(&dedup_path)?);
+            Some(Deduplicator::new(store, dedup_ttl, dedup_max)?)
+        }
+        None => None,
+    };
+    if let Some(d) = &dedup {
+        info!(
+            store = ?dedup_store,
+            id = ?dedup_id,
+            ttl = ?dedup_ttl,
+            max = dedup_max,
+            remembered = d.len(),
+            "🧾 Deduplication enabled"
+        );
+    }
+
+    info!(
+        profile = %profile,
+        group = group_id,
+        topic = %cfg.topic,
+        mode = ?commit_mode,
+        fail_mod,
+        crash_after,
+        crash_after_process,
+        flush_policy = ?flush_policy,
+        trace_export = ?trace_export,
+        "Lab4 consumer started"
     );
 
     let mut processed_ok_count: i64 = 0;
+    let mut flush_tick = tokio::time::interval(flush_policy.max_interval);
+    let shutdown = Shutdown::listen();
 
     loop {
-        match consumer.recv().await {
-            Err(e) => eprintln!("Read error: {e}"),
+        let msg = tokio::select! {
+            msg = consumer.recv() => msg,
+            // Time-based flush also when no messages arrive.
+            _ = flush_tick.tick(), if commit_mode == CommitModeCli::Batch => {
+                flush_offsets(&offsets, consumer.as_ref(), &metrics, group_id, false)?;
+                continue;
+            }
+            _ = shutdown.requested() => break,
+        };
+
+        match msg {
+            Err(e) => warn!(group = group_id, error = %e, "Read error"),
             Ok(m) => {
                 let key = m.key().and_then(|k| std::str::from_utf8(k).ok());
                 let payload = m.payload().unwrap_or_default();
@@ -113,10 +280,18 @@ async fn main() -> Result<()> {
                 // Crash switch (before doing anything else): simulate "crash after N successes"
                 if crash_after >= 0
                     && processed_ok_count >= crash_after
-                    && commit_mode == CommitModeCli::Post
+                    && matches!(commit_mode, CommitModeCli::Post | CommitModeCli::Batch)
                 {
-                    eprintln!(
-                        "💥 CRASHING BEFORE COMMIT (post) after {processed_ok_count} processed message(s)"
+                    let crash_point = if commit_mode == CommitModeCli::Batch {
+                        "💥 CRASHING BEFORE FLUSH (batch): completed offsets since the last commit are not committed"
+                    } else {
+                        "💥 CRASHING BEFORE COMMIT (post)"
+                    };
+                    error!(
+                        group = group_id,
+                        mode = ?commit_mode,
+                        processed = processed_ok_count,
+                        "{crash_point}"
                     );
                     std::process::exit(1);
                 }
@@ -125,7 +300,13 @@ async fn main() -> Result<()> {
                 let ev = match serde_json::from_slice::<Event>(payload) {
                     Ok(ev) => ev,
                     Err(_) => {
-                        eprintln!("❌ Non-JSON p{p} @ {o} key={key:?} -> skipping");
+                        warn!(
+                            group = group_id,
+                            partition = p,
+                            offset = o,
+                            key = ?key,
+                            "❌ Non-JSON -> skipping"
+                        );
                         continue;
                     }
                 };
@@ -133,50 +314,248 @@ async fn main() -> Result<()> {
                 // Decide if this message should "fail"
                 let should_fail = fail_mod > 0 && ev.value % fail_mod == 0;
 
+                // Continue the producer's trace: its send span is the remote parent.
+                let receive_span = info_span!(
+                    "kafka.receive",
+                    otel.kind = "consumer",
+                    messaging.system = "kafka",
+                    messaging.destination.name = %m.topic(),
+                    messaging.consumer.group.name = group_id,
+                    messaging.kafka.partition = p,
+                    messaging.kafka.offset = o,
+                    messaging.kafka.message.key = ?key,
+                    mode = ?commit_mode,
+                );
+                let _ = receive_span.set_parent(telemetry::extract_context(&m));
+
                 match commit_mode {
                     CommitModeCli::Pre => {
                         // At-most-once: commit first, then process
+                        let commit_span = info_span!(parent: &receive_span, "commit");
                         consumer.store_offset(m.topic(), m.partition(), m.offset())?;
                         consumer.commit_message(&m, CommitMode::Sync)?;
-                        eprintln!("✅ COMMIT (pre) p{p} @ {o}");
+                        metrics.inc_committed();
+                        info!(
+                            parent: &commit_span,
+                            group = group_id,
+                            mode = ?commit_mode,
+                            partition = p,
+                            offset = o,
+                            "✅ COMMIT (pre)"
+                        );
+                        drop(commit_span);
                         // Now "process"
+                        let process_span = info_span!(parent: &receive_span, "process");
                         if should_fail {
-                            eprintln!("❌ PROCESSING FAILED p{p} @ {o} key={key:?} => {:?}", ev);
+                            metrics.inc_failed();
+                            warn!(
+                                parent: &process_span,
+                                group = group_id,
+                                mode = ?commit_mode,
+                                partition = p,
+                                offset = o,
+                                key = ?key,
+                                event = ?ev,
+                                "❌ PROCESSING FAILED"
+                            );
                             // already committed -> message won't be redelivered (loss)
                         } else {
                             processed_ok_count += 1;
-                            println!("✅ PROCESSED (pre) p{p} @ {o} key={key:?} => {:?}", ev);
+                            metrics.inc_processed();
+                            info!(
+                                parent: &process_span,
+                                group = group_id,
+                                mode = ?commit_mode,
+                                partition = p,
+                                offset = o,
+                                key = ?key,
+                                event = ?ev,
+                                "✅ PROCESSED (pre)"
+                            );
                         }
                     }
                     CommitModeCli::Post => {
                         // At-least-once: process first, then commit
+                        let id = dedup.as_ref().and_then(|_| dedup_id.id_of(&m));
+                        if let (Some(d), Some(id)) = (&dedup, &id)
+                            && d.seen(id)?
+                        {
+                            // Processed before, but its offset was not committed: skip it.
+                            metrics.inc_duplicates();
+                            info!(
+                                parent: &receive_span,
+                                group = group_id,
+                                partition = p,
+                                offset = o,
+                                key = ?key,
+                                id = %id,
+                                "♻️ DUPLICATE skipped"
+                            );
+                            consumer.store_offset(m.topic(), m.partition(), m.offset())?;
+                            consumer.commit_message(&m, CommitMode::Sync)?;
+                            metrics.inc_committed();
+                            continue;
+                        }
+                        if dedup.is_some() && id.is_none() {
+                            warn!(partition = p, offset = o, source = ?dedup_id, "No message id, processed without deduplication");
+                        }
+                        let process_span = info_span!(parent: &receive_span, "process");
                         if should_fail {
-                            eprintln!("❌ PROCESSING FAILED p{p} @ {o} key={key:?} => {:?}", ev);
+                            metrics.inc_failed();
+                            warn!(
+                                parent: &process_span,
+                                group = group_id,
+                                mode = ?commit_mode,
+                                partition = p,
+                                offset = o,
+                                key = ?key,
+                                event = ?ev,
+                                "❌ PROCESSING FAILED"
+                            );
                             // do NOT store/commit -> message will be redelivered
                             // small delay to make logs readable
                             sleep(Duration::from_millis(150)).await;
                         } else {
                             processed_ok_count += 1;
-                            println!("✅ PROCESSED (post) p{p} @ {o} key={key:?} => {:?}", ev);
+                            metrics.inc_processed();
+                            info!(
+                                parent: &process_span,
+                                group = group_id,
+                                mode = ?commit_mode,
+                                partition = p,
+                                offset = o,
+                                key = ?key,
+                                event = ?ev,
+                                "✅ PROCESSED (post)"
+                            );
+                            drop(process_span);
+                            if let (Some(d), Some(id)) = (&mut dedup, &id) {
+                                d.record(id)?;
+                                // Durable before the commit: a crash in between must not forget it.
+                                let next = Offsets::from([((m.topic().to_string(), p), o + 1)]);
+                                d.checkpoint(&next)?;
+                            }
+                            if crash_after_process > 0 && processed_ok_count >= crash_after_process
+                            {
+                                error!(
+                                    group = group_id,
+                                    partition = p,
+                                    offset = o,
+                                    processed = processed_ok_count,
+                                    "💥 CRASHING AFTER PROCESSING, BEFORE COMMIT"
+                                );
+                                std::process::exit(1);
+                            }
+                            let commit_span = info_span!(parent: &receive_span, "commit");
                             consumer.store_offset(m.topic(), m.partition(), m.offset())?;
                             consumer.commit_message(&m, CommitMode::Sync)?;
-                            eprintln!("✅ COMMIT (post) p{p} @ {o}");
+                            metrics.inc_committed();
+                            info!(
+                                parent: &commit_span,
+                                group = group_id,
+                                mode = ?commit_mode,
+                                partition = p,
+                                offset = o,
+                                "✅ COMMIT (post)"
+                            );
+                        }
+                    }
+                    CommitModeCli::Batch => {
+                        // At-least-once, batched: only offsets whose every predecessor
+                        // completed are committed.
+                        offsets.lock().unwrap().track(m.topic(), p, o);
+                        let process_span = info_span!(parent: &receive_span, "process");
+                        if should_fail {
+                            metrics.inc_failed();
+                            warn!(
+                                parent: &process_span,
+                                group = group_id,
+                                mode = ?commit_mode,
+                                partition = p,
+                                offset = o,
+                                key = ?key,
+                                event = ?ev,
+                                "❌ PROCESSING FAILED (skipped)"
+                            );
+                            // Completed anyway, as `post` commits past it: a record that is
+                            // never completed would hold its partition's commits forever.
+                            offsets.lock().unwrap().complete(m.topic(), p, o);
+                            sleep(Duration::from_millis(150)).await;
+                        } else {
+                            processed_ok_count += 1;
+                            metrics.inc_processed();
+                            offsets.lock().unwrap().complete(m.topic(), p, o);
+                            info!(
+                                parent: &process_span,
+                                group = group_id,
+                                mode = ?commit_mode,
+                                partition = p,
+                                offset = o,
+                                key = ?key,
+                                event = ?ev,
+                                "✅ PROCESSED (batch)"
+                            );
                         }
+                        drop(process_span);
+                        flush_offsets(&offsets, consumer.as_ref(), &metrics, group_id, false)?;
                     }
                     CommitModeCli::Txn => {
                         // Placeholder: true EOS requires transactional producer and
                         // sending offsets to the transaction with group metadata.
                         // Kept as a stub for now.
-                        println!(
-                            "(txn mode not fully implemented in this lab) p{p} @ {o} key={key:?} => {:?}",
-                            ev
+                        info!(
+                            parent: &receive_span,
+                            group = group_id,
+                            mode = ?commit_mode,
+                            partition = p,
+                            offset = o,
+                            key = ?key,
+                            event = ?ev,
+                            "(txn mode not fully implemented in this lab)"
                         );
                         // Behave like post for demo:
+                        metrics.inc_processed();
+                        let _commit = info_span!(parent: &receive_span, "commit");
                         consumer.store_offset(m.topic(), m.partition(), m.offset())?;
                         consumer.commit_message(&m, CommitMode::Sync)?;
+                        metrics.inc_committed();
                     }
                 }
             }
         }
     }
+
+    if commit_mode == CommitModeCli::Batch {
+        flush_offsets(&offsets, consumer.as_ref(), &metrics, group_id, true)?;
+    }
+    shutdown::close_consumer(consumer.as_ref(), group_id);
+    Ok(())
+}
+
+/// Commits what the tracker allows when its flush policy says so, or always with `force`.
+fn flush_offsets<C: Consumer<LabContext>>(
+    offsets: &SharedOffsetTracker,
+    consumer: &C,
+    metrics: &Metrics,
+    group_id: &str,
+    force: bool,
+) -> Result<()> {
+    let mut tracker = offsets.lock().unwrap();
+    if !force && !tracker.should_flush() {
+        return Ok(());
+    }
+    let committable = tracker.committable();
+    let committed = tracker.flush(consumer)?;
+    if committed > 0 {
+        metrics.inc_committed();
+        for e in committable.elements() {
+            info!(
+                group = group_id,
+                partition = e.partition(),
+                next_offset = ?e.offset(),
+                "✅ COMMIT (batch)"
+            );
+        }
+    }
+    Ok(())
 }
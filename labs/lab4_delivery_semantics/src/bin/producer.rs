@@ -1,18 +1,32 @@
 use anyhow::Result;
+use rdkafka::message::{Header, OwnedHeaders};
 use rdkafka::producer::future_producer::Delivery;
 use shared::config::AppConfig;
-use shared::create_producer_props;
+use shared::context::LabContext;
+use shared::create_producer_with_context;
 use shared::event::Event;
+use shared::input;
+use shared::logging::{self, LogFormat};
+use shared::metrics::{self, Metrics};
 use shared::record::create_future_record;
+use shared::shutdown::{self, Shutdown};
+use shared::telemetry::{self, TraceExport};
 use std::env;
-use std::io::{self, BufRead};
+use std::sync::Arc;
 use std::time::Duration;
+use tracing::{Instrument, error, field, info, info_span, warn};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let mut args = env::args();
     let mut cfg_path = "shared/config.toml".to_string();
     let mut profile = "lab4.atleastonce".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut metrics_addr: Option<String> = None;
+    let mut otlp_endpoint: Option<String> = None;
+    let mut trace_file: Option<String> = None;
+    let mut duplicate = false;
 
     while let Some(a) = args.next() {
         match a.as_str() {
@@ -26,37 +40,93 @@ async fn main() -> Result<()> {
                     profile = p;
                 }
             }
+            "--metrics-addr" => {
+                if let Some(addr) = args.next() {
+                    metrics_addr = Some(addr);
+                }
+            }
+            "--otlp-endpoint" => {
+                if let Some(e) = args.next() {
+                    otlp_endpoint = Some(e);
+                }
+            }
+            "--trace-file" => {
+                if let Some(f) = args.next() {
+                    trace_file = Some(f);
+                }
+            }
+            "--duplicate" => duplicate = true,
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
             _ => {}
         }
     }
 
     let cfg = AppConfig::from_file(&cfg_path, &profile);
+    let trace_export = TraceExport::from_options(
+        otlp_endpoint.or(cfg.otlp_endpoint.clone()),
+        trace_file.or(cfg.trace_file.clone()),
+    );
+    let tracer_provider = trace_export
+        .as_ref()
+        .map(|export| telemetry::init_tracer_provider("lab4-producer", export))
+        .transpose()?;
+    logging::init_with_tracer(
+        log_format.or(cfg.log_format).unwrap_or_default(),
+        tracer_provider.as_ref().map(telemetry::tracer),
+    );
 
     let timeout = cfg.message_timeout_ms.unwrap_or(5000).to_string();
     let compression = cfg.compression.unwrap_or("lz4".to_string());
+    let statistics_interval = cfg.statistics_interval_ms.unwrap_or(0).to_string();
+    let metrics_addr = metrics_addr.or(cfg.metrics_addr);
     let mut props: Vec<(&str, String)> = vec![
         ("bootstrap.servers", cfg.bootstrap_servers),
         ("compression.type", compression),
         ("message.timeout.ms", timeout),
+        ("statistics.interval.ms", statistics_interval),
     ];
 
     if profile == "lab4.exactlyonce" {
         props.push(("transactional.id", "lab4-producer-tx".into()));
     }
 
-    let producer = create_producer_props(&props)?;
+    let metrics = Metrics::new();
+    let producer = create_producer_with_context(&props, LabContext::new(Arc::clone(&metrics)))?;
 
-    eprintln!(
-        "Producer (Lab 4) using {cfg_path} | profile={profile} | partitioning={:?}",
-        cfg.partitioning
+    if let Some(addr) = metrics_addr {
+        metrics::spawn_server(addr, Arc::clone(&metrics));
+    }
+
+    info!(
+        config = %cfg_path,
+        profile = %profile,
+        partitioning = ?cfg.partitioning,
+        trace_export = ?trace_export,
+        duplicate,
+        "Producer (Lab 4) started"
     );
-    eprintln!("Enter: user_id action value (e.g. u1 click 42). Ctrl+D to exit.");
+    info!("Enter: user_id action value (e.g. u1 click 42). Ctrl+D to exit.");
+
+    let shutdown = Shutdown::listen();
+    let mut lines = input::stdin_lines();
+    let mut delivered: u64 = 0;
+    let mut failed: u64 = 0;
 
-    for line in io::stdin().lock().lines() {
-        let line = line?;
+    loop {
+        let line = tokio::select! {
+            line = lines.recv() => match line {
+                Some(line) => line?,
+                None => break,
+            },
+            _ = shutdown.requested() => break,
+        };
         let parts: Vec<_> = line.split_whitespace().collect();
         if parts.len() < 3 {
-            eprintln!("Format: user_id action value");
+            warn!(line = %line, "Format: user_id action value");
             continue;
         }
 
@@ -68,21 +138,68 @@ async fn main() -> Result<()> {
 
         let payload = serde_json::to_vec(&evt)?;
 
-        let record =
-            create_future_record(Some(&evt.user_id), &payload, &cfg.topic, cfg.partitioning)?;
+        // Consumers deduplicate on this id; `--duplicate` resends the record with
+        // the same id, like an application retrying after an ambiguous timeout.
+        let message_id = uuid::Uuid::new_v4().to_string();
+        for _ in 0..if duplicate { 2 } else { 1 } {
+            // Covers send -> broker ack; its context travels in the record headers.
+            let send_span = info_span!(
+                "kafka.send",
+                otel.kind = "producer",
+                messaging.system = "kafka",
+                messaging.destination.name = %cfg.topic,
+                messaging.kafka.message.key = %evt.user_id,
+                messaging.kafka.partition = field::Empty,
+                messaging.kafka.offset = field::Empty,
+            );
+            let headers = telemetry::inject_context(&send_span.context(), OwnedHeaders::new())
+                .insert(Header {
+                    key: "message-id",
+                    value: Some(&message_id),
+                });
 
-        let delivery = producer.send(record, Duration::from_secs(0)).await;
+            let record =
+                create_future_record(Some(&evt.user_id), &payload, &cfg.topic, cfg.partitioning)?
+                    .headers(headers);
 
-        match delivery {
-            Ok(Delivery {
-                partition, offset, ..
-            }) => eprintln!(
-                "✅ Sent p{partition} @ {offset} key_mode={:?}",
-                cfg.partitioning
-            ),
-            Err((e, _)) => eprintln!("❌ Delivery failed: {e}"),
+            let delivery = producer
+                .send(record, Duration::from_secs(0))
+                .instrument(send_span.clone())
+                .await;
+            let _entered = send_span.enter();
+
+            match delivery {
+                Ok(Delivery {
+                    partition, offset, ..
+                }) => {
+                    send_span.record("messaging.kafka.partition", partition);
+                    send_span.record("messaging.kafka.offset", offset);
+                    delivered += 1;
+                    metrics.inc_processed();
+                    info!(
+                        partition,
+                        offset,
+                        key = %evt.user_id,
+                        message_id = %message_id,
+                        key_mode = ?cfg.partitioning,
+                        "✅ Sent"
+                    )
+                }
+                Err((e, _)) => {
+                    failed += 1;
+                    metrics.inc_failed();
+                    error!(key = %evt.user_id, error = %e, "❌ Delivery failed")
+                }
+            }
         }
     }
 
+    let undelivered = shutdown::flush_producer(&producer, shutdown::FLUSH_TIMEOUT);
+    info!(delivered, failed, undelivered, "👋 Producer stopped");
+
+    if let Some(provider) = tracer_provider {
+        provider.shutdown()?;
+    }
+
     Ok(())
 }
@@ -0,0 +1,276 @@
+use anyhow::Result;
+use rdkafka::client::ClientContext;
+use rdkafka::config::RDKafkaLogLevel;
+use rdkafka::consumer::{BaseConsumer, Consumer, ConsumerContext, Rebalance};
+use rdkafka::error::{KafkaError, KafkaResult};
+use rdkafka::message::Message;
+use rdkafka::statistics::Statistics;
+use rdkafka::{Offset, TopicPartitionList};
+use rusqlite::{Connection, OptionalExtension, params};
+use shared::config::AppConfig;
+use shared::context::LabContext;
+use shared::create_consumer_with_context;
+use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::metrics::Metrics;
+use shared::shutdown::{self, Shutdown};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    topic     TEXT    NOT NULL,
+    partition INTEGER NOT NULL,
+    offset    INTEGER NOT NULL,
+    user_id   TEXT    NOT NULL,
+    action    TEXT    NOT NULL,
+    value     INTEGER NOT NULL,
+    PRIMARY KEY (topic, partition, offset)
+);
+CREATE TABLE IF NOT EXISTS offsets (
+    topic       TEXT    NOT NULL,
+    partition   INTEGER NOT NULL,
+    next_offset INTEGER NOT NULL,
+    PRIMARY KEY (topic, partition)
+);
+";
+
+fn open(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+/// Next offset to read from `topic`/`partition`, as stored with the results.
+fn stored_offset(conn: &Connection, topic: &str, partition: i32) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "SELECT next_offset FROM offsets WHERE topic = ?1 AND partition = ?2",
+        params![topic, partition],
+        |r| r.get(0),
+    )
+    .optional()
+}
+
+/// Consumer context of the sink: [`LabContext`] logging and metrics, plus
+/// starting every assigned partition at the offset stored in the database.
+///
+/// The offsets are set in the assignment itself, before librdkafka applies it,
+/// so no record is fetched from any other position first. Partitions the
+/// database knows nothing about keep the default: `auto.offset.reset`, since
+/// the group never commits.
+struct SinkContext {
+    lab: LabContext,
+    db: Mutex<Connection>,
+}
+
+impl ClientContext for SinkContext {
+    fn log(&self, level: RDKafkaLogLevel, fac: &str, log_message: &str) {
+        self.lab.log(level, fac, log_message);
+    }
+
+    fn stats(&self, statistics: Statistics) {
+        self.lab.stats(statistics);
+    }
+
+    fn error(&self, error: KafkaError, reason: &str) {
+        self.lab.error(error, reason);
+    }
+}
+
+impl ConsumerContext for SinkContext {
+    fn pre_rebalance(&self, _base_consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        let Rebalance::Assign(assigned) = rebalance else {
+            return;
+        };
+        let db = self.db.lock().unwrap();
+        for mut elem in assigned.elements() {
+            let (topic, partition) = (elem.topic().to_string(), elem.partition());
+            match stored_offset(&db, &topic, partition) {
+                Ok(Some(next)) => match elem.set_offset(Offset::Offset(next)) {
+                    Ok(()) => info!(topic, partition, offset = next, "⏩ SEEK to stored offset"),
+                    Err(e) => error!(topic, partition, error = %e, "❌ Seek failed"),
+                },
+                Ok(None) => info!(
+                    topic,
+                    partition, "⏩ No stored offset, auto.offset.reset applies"
+                ),
+                // Starting anywhere else could skip or repeat records: better to stop.
+                Err(e) => {
+                    error!(topic, partition, error = %e, "❌ Reading the stored offset failed");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    fn post_rebalance(&self, _base_consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        // A rebalance revokes, then assigns: count it once.
+        if matches!(rebalance, Rebalance::Assign(_)) {
+            self.lab.metrics.inc_rebalances();
+        }
+    }
+
+    fn commit_callback(&self, result: KafkaResult<()>, offsets: &TopicPartitionList) {
+        self.lab.commit_callback(result, offsets);
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab4.sink".to_string();
+    let mut group_override: Option<String> = None;
+    let mut log_format: Option<LogFormat> = None;
+    let mut db_path = PathBuf::from("lab4-sink.db");
+    let mut crash_after: u64 = 0; // 0 disables
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--group-id" => {
+                if let Some(g) = args.next() {
+                    group_override = Some(g);
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--db" => {
+                if let Some(p) = args.next() {
+                    db_path = PathBuf::from(p);
+                }
+            }
+            "--crash-after" => {
+                if let Some(n) = args.next() {
+                    crash_after = n.parse().unwrap_or(0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    let group_id = group_override
+        .or(cfg.group_id.clone())
+        .unwrap_or("lab4-sink".to_string());
+
+    let mut conn = open(&db_path)?;
+    let context = SinkContext {
+        lab: LabContext::new(Metrics::new()),
+        db: Mutex::new(open(&db_path)?),
+    };
+    // The database is the only record of progress: Kafka never gets a commit.
+    let props = &[
+        ("bootstrap.servers", cfg.bootstrap_servers.clone()),
+        ("group.id", group_id.clone()),
+        ("enable.auto.commit", "false".to_string()),
+        ("enable.auto.offset.store", "false".to_string()),
+        ("auto.offset.reset", cfg.auto_offset_reset.clone()),
+    ];
+    let consumer = create_consumer_with_context(props, context)?;
+    consumer.subscribe(&[&cfg.topic])?;
+
+    let rows: i64 = conn.query_row("SELECT COUNT(*) FROM events", [], |r| r.get(0))?;
+    info!(
+        profile = %profile,
+        group = group_id,
+        topic = %cfg.topic,
+        db = %db_path.display(),
+        rows,
+        crash_after,
+        "Lab4 sink started"
+    );
+
+    let shutdown = Shutdown::listen();
+    let mut written: u64 = 0;
+    let mut skipped: u64 = 0;
+
+    loop {
+        let m = tokio::select! {
+            msg = consumer.recv() => match msg {
+                Ok(m) => m,
+                Err(e) => {
+                    warn!(group = group_id, error = %e, "Read error");
+                    continue;
+                }
+            },
+            _ = shutdown.requested() => break,
+        };
+        let (topic, p, o) = (m.topic(), m.partition(), m.offset());
+        let key = m.key().and_then(|k| std::str::from_utf8(k).ok());
+        let event = m
+            .payload()
+            .and_then(|payload| serde_json::from_slice::<Event>(payload).ok());
+
+        // The result and the position after it commit together, or neither does.
+        let tx = conn.transaction()?;
+        if let Some(ev) = &event {
+            tx.execute(
+                "INSERT INTO events (topic, partition, offset, user_id, action, value)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![topic, p, o, ev.user_id, ev.action, ev.value],
+            )?;
+        }
+        tx.execute(
+            "INSERT INTO offsets (topic, partition, next_offset) VALUES (?1, ?2, ?3)
+             ON CONFLICT (topic, partition) DO UPDATE SET next_offset = excluded.next_offset",
+            params![topic, p, o + 1],
+        )?;
+        tx.commit()?;
+
+        let Some(ev) = event else {
+            skipped += 1;
+            warn!(group = group_id, partition = p, offset = o, key = ?key, "❌ Non-JSON -> skipping");
+            continue;
+        };
+        written += 1;
+        info!(
+            group = group_id,
+            partition = p,
+            offset = o,
+            key = ?key,
+            event = ?ev,
+            "💾 WRITTEN with offset"
+        );
+        if crash_after > 0 && written >= crash_after {
+            // With a Kafka commit, this is where at-least-once would replay the record.
+            error!(
+                group = group_id,
+                partition = p,
+                offset = o,
+                written,
+                "💥 CRASHING AFTER WRITE"
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let rows: i64 = conn.query_row("SELECT COUNT(*) FROM events", [], |r| r.get(0))?;
+    let mut stmt = conn.prepare("SELECT partition, next_offset FROM offsets WHERE topic = ?1")?;
+    let stored = stmt
+        .query_map([&cfg.topic], |r| {
+            Ok((r.get::<_, i32>(0)?, r.get::<_, i64>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    info!(written, skipped, rows, stored = ?stored, "🏁 Sink stopped");
+    shutdown::close_consumer(&consumer, &group_id);
+    Ok(())
+}
@@ -0,0 +1,428 @@
+use anyhow::Result;
+use lab5_performance::prefill;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::{Offset, TopicPartitionList};
+use shared::config::AppConfig;
+use shared::context::LabContext;
+use shared::create_consumer_with_context;
+use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::metrics::Metrics;
+use shared::mock::Brokers;
+use shared::shutdown::{self, Shutdown};
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::timeout;
+use tracing::{info, warn};
+
+/// How long to wait for the first message (group join + fetch) of a run.
+const FIRST_MESSAGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy)]
+enum Strategy {
+    /// `commit_message(Sync)` after every message.
+    SyncPerMessage,
+    /// `commit_message(Async)` after every message.
+    AsyncPerMessage,
+    /// Synchronous commit of the latest offsets every N messages.
+    Batch(usize),
+    /// Synchronous commit of the latest offsets at most once per interval.
+    Time(Duration),
+    /// `store_offset` per message; librdkafka auto-commits stored offsets every interval.
+    AutoStore(Duration),
+}
+
+impl Strategy {
+    fn parse(name: &str, batch_size: usize, interval: Duration) -> Option<Self> {
+        match name {
+            "sync" => Some(Strategy::SyncPerMessage),
+            "async" => Some(Strategy::AsyncPerMessage),
+            "batch" => Some(Strategy::Batch(batch_size.max(1))),
+            "time" => Some(Strategy::Time(interval)),
+            "auto" => Some(Strategy::AutoStore(interval)),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Strategy::SyncPerMessage => "sync",
+            Strategy::AsyncPerMessage => "async",
+            Strategy::Batch(_) => "batch",
+            Strategy::Time(_) => "time",
+            Strategy::AutoStore(_) => "auto",
+        }
+    }
+}
+
+struct Outcome {
+    strategy: Strategy,
+    messages: u64,
+    elapsed: Duration,
+    commits: u64,
+    commit_errors: u64,
+}
+
+impl Outcome {
+    fn msgs_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.messages as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Consumes up to `limit` messages from the beginning of `topic` with a fresh
+/// consumer group, committing with `strategy`.
+///
+/// The clock starts at the first message, so group join and the initial fetch
+/// are not part of the measurement; it stops once the last message is processed
+/// and committed (the idle timeout that ends a run is not counted either).
+async fn run(
+    strategy: Strategy,
+    bootstrap_servers: &str,
+    topic: &str,
+    group_id: &str,
+    limit: u64,
+    idle: Duration,
+    shutdown: &Shutdown,
+) -> Result<Outcome> {
+    let metrics = Metrics::new();
+    let auto_commit_interval = match strategy {
+        Strategy::AutoStore(d) => d,
+        _ => Duration::from_secs(5),
+    };
+    let props = &[
+        ("bootstrap.servers", bootstrap_servers.to_string()),
+        ("group.id", group_id.to_string()),
+        ("auto.offset.reset", "earliest".to_string()),
+        (
+            "enable.auto.commit",
+            matches!(strategy, Strategy::AutoStore(_)).to_string(),
+        ),
+        ("enable.auto.offset.store", "false".to_string()),
+        (
+            "auto.commit.interval.ms",
+            auto_commit_interval.as_millis().to_string(),
+        ),
+    ];
+    let consumer: StreamConsumer<LabContext> =
+        create_consumer_with_context(props, LabContext::new(Arc::clone(&metrics)))?;
+    consumer.subscribe(&[topic])?;
+    info!(strategy = ?strategy, group = group_id, limit, "▶️ strategy started");
+
+    // Next offset to commit per partition, for the batch and time strategies.
+    let mut pending: BTreeMap<i32, i64> = BTreeMap::new();
+    let mut messages: u64 = 0;
+    let mut first: Option<Instant> = None;
+    let mut last: Option<Instant> = None;
+    let mut last_commit = Instant::now();
+
+    let commit_pending = |pending: &mut BTreeMap<i32, i64>| -> Result<()> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let mut tpl = TopicPartitionList::new();
+        for (&partition, &next) in pending.iter() {
+            tpl.add_partition_offset(topic, partition, Offset::Offset(next))?;
+        }
+        consumer.commit(&tpl, CommitMode::Sync)?;
+        metrics.inc_committed();
+        pending.clear();
+        Ok(())
+    };
+
+    while messages < limit {
+        let wait = if first.is_some() {
+            idle
+        } else {
+            FIRST_MESSAGE_TIMEOUT
+        };
+        let received = tokio::select! {
+            received = timeout(wait, consumer.recv()) => received,
+            _ = shutdown.requested() => break,
+        };
+        let m = match received {
+            Err(_) => {
+                info!(strategy = strategy.name(), messages, "⌛ No more messages");
+                break;
+            }
+            Ok(Err(e)) => {
+                warn!(strategy = strategy.name(), error = %e, "Read error");
+                continue;
+            }
+            Ok(Ok(m)) => m,
+        };
+        if first.is_none() {
+            first = Some(Instant::now());
+            last_commit = Instant::now();
+        }
+
+        // The "processing": decode the payload like the other labs do.
+        let _ = serde_json::from_slice::<Event>(m.payload().unwrap_or_default());
+        messages += 1;
+
+        match strategy {
+            Strategy::SyncPerMessage => {
+                consumer.commit_message(&m, CommitMode::Sync)?;
+                metrics.inc_committed();
+            }
+            Strategy::AsyncPerMessage => {
+                consumer.commit_message(&m, CommitMode::Async)?;
+                metrics.inc_committed();
+            }
+            Strategy::Batch(n) => {
+                pending.insert(m.partition(), m.offset() + 1);
+                if messages.is_multiple_of(n as u64) {
+                    commit_pending(&mut pending)?;
+                }
+            }
+            Strategy::Time(interval) => {
+                pending.insert(m.partition(), m.offset() + 1);
+                if last_commit.elapsed() >= interval {
+                    commit_pending(&mut pending)?;
+                    last_commit = Instant::now();
+                }
+            }
+            Strategy::AutoStore(_) => consumer.store_offset_from_message(&m)?,
+        }
+        last = Some(Instant::now());
+    }
+    // Whatever the batch/time strategies still hold is committed before stopping the clock.
+    if !pending.is_empty() {
+        commit_pending(&mut pending)?;
+        last = Some(Instant::now());
+    }
+    let elapsed = match (first, last) {
+        (Some(f), Some(l)) => l - f,
+        _ => Duration::ZERO,
+    };
+
+    drain_commit_results(&consumer, &metrics, strategy, idle).await;
+    let (acks, commit_errors) = metrics.commit_results();
+    let commits = match strategy {
+        // Nothing is committed by the application; count what librdkafka committed.
+        Strategy::AutoStore(_) => acks,
+        _ => metrics.committed(),
+    };
+    shutdown::close_consumer(&consumer, group_id);
+    Ok(Outcome {
+        strategy,
+        messages,
+        elapsed,
+        commits,
+        commit_errors,
+    })
+}
+
+/// Keeps polling (outside of the measurement) so results of async commits and
+/// the last auto-commit reach `LabContext::commit_callback`; librdkafka only
+/// serves those callbacks from `recv`.
+async fn drain_commit_results(
+    consumer: &StreamConsumer<LabContext>,
+    metrics: &Metrics,
+    strategy: Strategy,
+    idle: Duration,
+) {
+    let deadline = Instant::now()
+        + match strategy {
+            Strategy::AsyncPerMessage => idle,
+            Strategy::AutoStore(interval) => interval + Duration::from_millis(500),
+            _ => return,
+        };
+    loop {
+        let (acks, errors) = metrics.commit_results();
+        if matches!(strategy, Strategy::AsyncPerMessage) && acks + errors >= metrics.committed() {
+            return;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return;
+        }
+        // Messages past the limit are ignored: the run is over.
+        let _ = timeout(left, consumer.recv()).await;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab5.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut group_override: Option<String> = None;
+    let mut strategy_names = "sync,async,batch,time,auto".to_string();
+    let mut messages: u64 = 10_000;
+    let mut batch_size: usize = 100;
+    let mut commit_interval = Duration::from_millis(1_000);
+    let mut idle = Duration::from_secs(3);
+    let mut prefill_count: u64 = 0;
+    let mut mock = false;
+    let mut partitions: i32 = 6;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--group-id" => {
+                if let Some(g) = args.next() {
+                    group_override = Some(g);
+                }
+            }
+            "--strategies" => {
+                if let Some(s) = args.next() {
+                    strategy_names = s;
+                }
+            }
+            "--messages" => {
+                if let Some(v) = args.next() {
+                    messages = v.parse().unwrap_or(messages);
+                }
+            }
+            "--batch-size" => {
+                if let Some(v) = args.next() {
+                    batch_size = v.parse().unwrap_or(batch_size);
+                }
+            }
+            "--commit-interval-ms" => {
+                if let Some(v) = args.next() {
+                    commit_interval = v
+                        .parse()
+                        .map(Duration::from_millis)
+                        .unwrap_or(commit_interval);
+                }
+            }
+            "--idle-ms" => {
+                if let Some(v) = args.next() {
+                    idle = v.parse().map(Duration::from_millis).unwrap_or(idle);
+                }
+            }
+            "--prefill" => {
+                if let Some(v) = args.next() {
+                    prefill_count = v.parse().unwrap_or(0);
+                }
+            }
+            "--mock" => mock = true,
+            "--partitions" => {
+                if let Some(v) = args.next() {
+                    partitions = v.parse().unwrap_or(partitions);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+
+    let strategies: Vec<Strategy> = strategy_names
+        .split(',')
+        .filter_map(|name| {
+            let s = Strategy::parse(name.trim(), batch_size, commit_interval);
+            if s.is_none() {
+                warn!(strategy = name, "Unknown strategy, skipping");
+            }
+            s
+        })
+        .collect();
+
+    let brokers = Brokers::new(mock, &cfg, partitions)?;
+    let bootstrap_servers = brokers.bootstrap_servers();
+    // A mock cluster starts empty: fill it with what the benchmark will read.
+    if mock && prefill_count == 0 {
+        prefill_count = messages;
+    }
+    if prefill_count > 0 {
+        prefill(&bootstrap_servers, &cfg, prefill_count, 1_000).await?;
+    }
+
+    let base_group = group_override
+        .as_deref()
+        .or(cfg.group_id.as_deref())
+        .unwrap_or("lab5-bench")
+        .to_string();
+    let run_id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+
+    info!(
+        bootstrap_servers = %bootstrap_servers,
+        topic = %cfg.topic,
+        strategies = %strategy_names,
+        messages,
+        batch_size,
+        commit_interval_ms = commit_interval.as_millis() as u64,
+        "🚀 consumer benchmark started"
+    );
+
+    let shutdown = Shutdown::listen();
+    let mut outcomes = Vec::new();
+    for strategy in strategies {
+        if shutdown.is_requested() {
+            break;
+        }
+        // A fresh group per run, so every strategy reads the same records from the start.
+        let group_id = format!("{base_group}-{}-{run_id}", strategy.name());
+        let outcome = run(
+            strategy,
+            &bootstrap_servers,
+            &cfg.topic,
+            &group_id,
+            messages,
+            idle,
+            &shutdown,
+        )
+        .await?;
+        info!(
+            strategy = strategy.name(),
+            messages = outcome.messages,
+            elapsed_secs = outcome.elapsed.as_secs_f64(),
+            msgs_per_sec = outcome.msgs_per_sec().round(),
+            commits = outcome.commits,
+            commit_errors = outcome.commit_errors,
+            "🏁 strategy finished"
+        );
+        outcomes.push(outcome);
+    }
+
+    let fastest = outcomes
+        .iter()
+        .map(Outcome::msgs_per_sec)
+        .fold(0.0_f64, f64::max);
+    for o in &outcomes {
+        let relative = if fastest > 0.0 {
+            o.msgs_per_sec() / fastest
+        } else {
+            0.0
+        };
+        info!(
+            strategy = ?o.strategy,
+            msgs_per_sec = o.msgs_per_sec().round(),
+            commits = o.commits,
+            commit_errors = o.commit_errors,
+            vs_fastest_pct = (relative * 100.0).round(),
+            "📋 summary"
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,411 @@
+use anyhow::Result;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use hdrhistogram::Histogram;
+use rand::Rng;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use shared::config::AppConfig;
+use shared::create_producer_props;
+use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::mock::Brokers;
+use shared::record::create_future_record;
+use shared::shutdown::{self, Shutdown};
+use std::env;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// Highest ack latency tracked (1 minute, in microseconds).
+const MAX_ACK_US: u64 = 60_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Skew {
+    Uniform,
+    Zipf(f64),
+}
+
+/// Picks keys `user-0..user-N`. With `Zipf(s)` key `k` is drawn with
+/// probability proportional to `1 / (k + 1)^s`, so `user-0` is the hottest.
+struct KeyPicker {
+    keys: Vec<String>,
+    /// Cumulative distribution over `keys`; empty for uniform.
+    cdf: Vec<f64>,
+}
+
+impl KeyPicker {
+    fn new(cardinality: usize, skew: Skew) -> Self {
+        let keys = (0..cardinality.max(1))
+            .map(|k| format!("user-{k}"))
+            .collect();
+        let cdf = match skew {
+            Skew::Uniform => Vec::new(),
+            Skew::Zipf(s) => {
+                let weights: Vec<f64> = (1..=cardinality.max(1))
+                    .map(|rank| 1.0 / (rank as f64).powf(s))
+                    .collect();
+                let total: f64 = weights.iter().sum();
+                weights
+                    .iter()
+                    .scan(0.0, |acc, w| {
+                        *acc += w / total;
+                        Some(*acc)
+                    })
+                    .collect()
+            }
+        };
+        Self { keys, cdf }
+    }
+
+    fn pick(&self, rng: &mut impl Rng) -> &str {
+        let idx = if self.cdf.is_empty() {
+            rng.random_range(0..self.keys.len())
+        } else {
+            let u: f64 = rng.random();
+            self.cdf
+                .partition_point(|&c| c < u)
+                .min(self.keys.len() - 1)
+        };
+        &self.keys[idx]
+    }
+}
+
+/// JSON-encoded `Event` padded (through `action`) to at least `size` bytes.
+fn payload(user_id: &str, value: i64, size: usize) -> serde_json::Result<Vec<u8>> {
+    let mut evt = Event {
+        user_id: user_id.to_string(),
+        action: "load".to_string(),
+        value,
+    };
+    let bytes = serde_json::to_vec(&evt)?;
+    if bytes.len() >= size {
+        return Ok(bytes);
+    }
+    evt.action.push_str(&"x".repeat(size - bytes.len()));
+    serde_json::to_vec(&evt)
+}
+
+struct Stats {
+    start: Instant,
+    sent: u64,
+    acked: u64,
+    failed: u64,
+    queue_full: u64,
+    bytes: u64,
+    ack_latency: Histogram<u64>,
+    window_start: Instant,
+    window_acked: u64,
+    window_bytes: u64,
+}
+
+impl Stats {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            sent: 0,
+            acked: 0,
+            failed: 0,
+            queue_full: 0,
+            bytes: 0,
+            ack_latency: Histogram::new_with_bounds(1, MAX_ACK_US, 3)
+                .expect("valid histogram bounds"),
+            window_start: now,
+            window_acked: 0,
+            window_bytes: 0,
+        }
+    }
+
+    fn on_delivery(&mut self, ok: bool, bytes: usize, latency: Duration) {
+        if !ok {
+            self.failed += 1;
+            return;
+        }
+        self.acked += 1;
+        self.bytes += bytes as u64;
+        self.window_acked += 1;
+        self.window_bytes += bytes as u64;
+        let us = (latency.as_micros() as u64).clamp(1, MAX_ACK_US);
+        self.ack_latency
+            .record(us)
+            .expect("latency is clamped to the trackable range");
+    }
+
+    fn report_window(&mut self, in_flight: usize) {
+        let secs = self.window_start.elapsed().as_secs_f64();
+        info!(
+            msgs_per_sec = (self.window_acked as f64 / secs).round(),
+            mb_per_sec = round2(mb(self.window_bytes) / secs),
+            sent = self.sent,
+            acked = self.acked,
+            failed = self.failed,
+            in_flight,
+            "📊 progress"
+        );
+        self.window_start = Instant::now();
+        self.window_acked = 0;
+        self.window_bytes = 0;
+    }
+
+    fn report_total(&self, undelivered: usize) {
+        let secs = self.start.elapsed().as_secs_f64();
+        let ms = |us: u64| us as f64 / 1_000.0;
+        let h = &self.ack_latency;
+        info!(
+            elapsed_secs = round2(secs),
+            sent = self.sent,
+            acked = self.acked,
+            failed = self.failed,
+            undelivered,
+            queue_full = self.queue_full,
+            msgs_per_sec = (self.acked as f64 / secs).round(),
+            mb_per_sec = round2(mb(self.bytes) / secs),
+            "🏁 loadgen finished"
+        );
+        if !h.is_empty() {
+            info!(
+                p50_ms = ms(h.value_at_quantile(0.50)),
+                p95_ms = ms(h.value_at_quantile(0.95)),
+                p99_ms = ms(h.value_at_quantile(0.99)),
+                p999_ms = ms(h.value_at_quantile(0.999)),
+                max_ms = ms(h.max()),
+                "⏱️ ack latency"
+            );
+        }
+    }
+}
+
+fn mb(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+fn round2(v: f64) -> f64 {
+    (v * 100.0).round() / 100.0
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab5.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut rate: u64 = 0;
+    let mut count: u64 = 100_000;
+    let mut duration: Option<Duration> = None;
+    let mut keys: usize = 1_000;
+    let mut skew = Skew::Uniform;
+    let mut zipf_exponent: f64 = 1.0;
+    let mut payload_bytes: usize = 256;
+    let mut concurrency: usize = 1_000;
+    let mut report_every = Duration::from_secs(1);
+    let mut mock = false;
+    let mut partitions: i32 = 6;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--rate" => {
+                if let Some(v) = args.next() {
+                    rate = v.parse().unwrap_or(0);
+                }
+            }
+            "--count" => {
+                if let Some(v) = args.next() {
+                    count = v.parse().unwrap_or(count);
+                }
+            }
+            "--duration-secs" => {
+                if let Some(v) = args.next() {
+                    duration = v.parse().ok().map(Duration::from_secs);
+                }
+            }
+            "--keys" => {
+                if let Some(v) = args.next() {
+                    keys = v.parse().unwrap_or(keys);
+                }
+            }
+            "--skew" => {
+                if let Some(v) = args.next() {
+                    skew = match v.as_str() {
+                        "zipf" => Skew::Zipf(zipf_exponent),
+                        _ => Skew::Uniform,
+                    };
+                }
+            }
+            "--zipf-exponent" => {
+                if let Some(v) = args.next() {
+                    zipf_exponent = v.parse().unwrap_or(zipf_exponent);
+                }
+            }
+            "--payload-bytes" => {
+                if let Some(v) = args.next() {
+                    payload_bytes = v.parse().unwrap_or(payload_bytes);
+                }
+            }
+            "--concurrency" => {
+                if let Some(v) = args.next() {
+                    concurrency = v.parse().unwrap_or(concurrency).max(1);
+                }
+            }
+            "--report-secs" => {
+                if let Some(v) = args.next() {
+                    report_every = Duration::from_secs(v.parse().unwrap_or(1).max(1));
+                }
+            }
+            "--mock" => mock = true,
+            "--partitions" => {
+                if let Some(v) = args.next() {
+                    partitions = v.parse().unwrap_or(partitions);
+                }
+            }
+            _ => {}
+        }
+    }
+    // `--zipf-exponent` may come after `--skew zipf`.
+    if let Skew::Zipf(_) = skew {
+        skew = Skew::Zipf(zipf_exponent);
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+
+    let brokers = Brokers::new(mock, &cfg, partitions)?;
+    let bootstrap_servers = brokers.bootstrap_servers();
+
+    let timeout = cfg.message_timeout_ms.unwrap_or(5000).to_string();
+    let compression = cfg.compression.clone().unwrap_or("lz4".to_string());
+    let linger = cfg.linger_ms.unwrap_or(5).to_string();
+    let props = &[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("compression.type", compression.clone()),
+        ("message.timeout.ms", timeout.clone()),
+        ("linger.ms", linger.clone()),
+    ];
+    let producer = create_producer_props(props)?;
+
+    info!(
+        bootstrap_servers = %bootstrap_servers,
+        topic = %cfg.topic,
+        partitioning = ?cfg.partitioning,
+        rate = if rate == 0 { "max".to_string() } else { rate.to_string() },
+        count,
+        duration_secs = duration.map(|d| d.as_secs()),
+        keys,
+        skew = ?skew,
+        payload_bytes,
+        concurrency,
+        compression = %compression,
+        linger_ms = %linger,
+        "🚀 loadgen started"
+    );
+
+    let picker = KeyPicker::new(keys, skew);
+    let mut rng = rand::rng();
+    let mut stats = Stats::new();
+    let mut in_flight = FuturesUnordered::new();
+    let mut last_report = Instant::now();
+    let deadline = duration.map(|d| stats.start + d);
+    let shutdown = Shutdown::listen();
+
+    while stats.sent < count
+        && deadline.is_none_or(|d| Instant::now() < d)
+        && !shutdown.is_requested()
+    {
+        if rate > 0 {
+            let due = stats.start + Duration::from_secs_f64(stats.sent as f64 / rate as f64);
+            if due > Instant::now() {
+                // Collect acks while waiting for the next send slot.
+                tokio::select! {
+                    _ = tokio::time::sleep_until(due.into()) => {}
+                    _ = shutdown.requested() => break,
+                    Some((ok, bytes, latency)) = in_flight.next(), if !in_flight.is_empty() => {
+                        stats.on_delivery(ok, bytes, latency);
+                        continue;
+                    }
+                }
+            }
+        }
+
+        while in_flight.len() >= concurrency {
+            if let Some((ok, bytes, latency)) = in_flight.next().await {
+                stats.on_delivery(ok, bytes, latency);
+            }
+        }
+
+        let key = picker.pick(&mut rng);
+        let bytes = payload(key, stats.sent as i64, payload_bytes)?;
+        let record = create_future_record(Some(key), &bytes, &cfg.topic, cfg.partitioning)?;
+
+        match producer.send_result(record) {
+            Ok(delivery) => {
+                let size = bytes.len();
+                let sent_at = Instant::now();
+                in_flight.push(async move {
+                    let ok = match delivery.await {
+                        Ok(Ok(_)) => true,
+                        Ok(Err((e, _))) => {
+                            warn!(error = %e, "❌ Delivery failed");
+                            false
+                        }
+                        Err(_) => false,
+                    };
+                    (ok, size, sent_at.elapsed())
+                });
+                stats.sent += 1;
+            }
+            Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
+                // librdkafka's local queue is full: let some deliveries complete first.
+                stats.queue_full += 1;
+                match in_flight.next().await {
+                    Some((ok, bytes, latency)) => stats.on_delivery(ok, bytes, latency),
+                    None => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+            Err((e, _)) => {
+                error!(error = %e, "❌ Enqueue failed");
+                stats.sent += 1;
+                stats.failed += 1;
+            }
+        }
+
+        if last_report.elapsed() >= report_every {
+            stats.report_window(in_flight.len());
+            last_report = Instant::now();
+        }
+    }
+
+    info!(
+        in_flight = in_flight.len(),
+        "⏳ Waiting for outstanding deliveries"
+    );
+    let drain = async {
+        while let Some((ok, bytes, latency)) = in_flight.next().await {
+            stats.on_delivery(ok, bytes, latency);
+        }
+    };
+    let _ = tokio::time::timeout(shutdown::FLUSH_TIMEOUT, drain).await;
+    let undelivered = in_flight.len();
+    if undelivered > 0 {
+        warn!(
+            undelivered,
+            timeout_ms = shutdown::FLUSH_TIMEOUT.as_millis() as u64,
+            "📤 Messages left undelivered"
+        );
+    }
+    stats.report_total(undelivered);
+
+    Ok(())
+}
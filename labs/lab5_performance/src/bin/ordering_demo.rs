@@ -0,0 +1,255 @@
+use anyhow::Result;
+use rdkafka::mocking::MockCluster;
+use rdkafka::producer::DefaultProducerContext;
+use rdkafka::types::{RDKafkaApiKey, RDKafkaRespErr};
+use shared::config::{AppConfig, PartitioningMode};
+use shared::create_producer_props;
+use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::mock::start_mock_cluster;
+use shared::pipeline::{DeliveryPipeline, Report};
+use shared::record::create_future_record;
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// One producer configuration to try.
+#[derive(Debug, Clone, Copy)]
+struct Scenario {
+    max_in_flight: u32,
+    idempotence: bool,
+}
+
+impl Scenario {
+    fn parse(s: &str) -> Option<Self> {
+        let (n, idempotence) = match s.strip_suffix("+idempotence") {
+            Some(n) => (n, true),
+            None => (s, false),
+        };
+        Some(Self {
+            max_in_flight: n.parse().ok()?,
+            idempotence,
+        })
+    }
+
+    fn name(&self) -> String {
+        if self.idempotence {
+            format!("{}+idempotence", self.max_in_flight)
+        } else {
+            self.max_in_flight.to_string()
+        }
+    }
+}
+
+/// Delivery reports arrive in send order, so within a partition their offsets
+/// must grow; a lower offset means a later record was written first.
+#[derive(Default)]
+struct OrderCheck {
+    /// Highest offset reported so far, per partition.
+    last_offset: HashMap<i32, i64>,
+    delivered: u64,
+    failed: u64,
+    reordered: u64,
+}
+
+impl OrderCheck {
+    fn observe(&mut self, report: Report<Event>) {
+        let Ok(d) = report.result else {
+            self.failed += 1;
+            return;
+        };
+        self.delivered += 1;
+        let highest = self.last_offset.entry(d.partition).or_insert(d.offset);
+        if d.offset < *highest {
+            self.reordered += 1;
+            warn!(
+                partition = d.partition,
+                offset = d.offset,
+                earlier_record_offset = *highest,
+                key = %report.tag.user_id,
+                value = report.tag.value,
+                "🔀 written before a record sent earlier"
+            );
+        }
+        *highest = (*highest).max(d.offset);
+    }
+}
+
+/// Fails the next produce request with a retriable error, so the producer has
+/// to retry a batch while later ones may already be in flight.
+fn inject_error(cluster: &MockCluster<'static, DefaultProducerContext>) {
+    cluster.request_errors(
+        RDKafkaApiKey::Produce,
+        &[RDKafkaRespErr::RD_KAFKA_RESP_ERR_NOT_ENOUGH_REPLICAS],
+    );
+}
+
+async fn run(
+    scenario: Scenario,
+    cfg: &AppConfig,
+    messages: u64,
+    keys: u64,
+    window: usize,
+    error_every: u64,
+    partitions: i32,
+) -> Result<OrderCheck> {
+    // A fresh cluster per scenario: empty topic, no leftover injected errors.
+    let cluster = start_mock_cluster(3, &cfg.topic, partitions)?;
+    // Latency keeps several requests in flight per connection.
+    cluster.broker_round_trip_time(-1, Duration::from_millis(20))?;
+
+    let props = &[
+        ("bootstrap.servers", cluster.bootstrap_servers()),
+        ("linger.ms", "1".to_string()),
+        (
+            "max.in.flight.requests.per.connection",
+            scenario.max_in_flight.to_string(),
+        ),
+        ("enable.idempotence", scenario.idempotence.to_string()),
+    ];
+    let producer = create_producer_props(props)?;
+    let mut pipeline: DeliveryPipeline<Event> = DeliveryPipeline::new(window);
+    let mut check = OrderCheck::default();
+    let start = Instant::now();
+
+    for i in 0..messages {
+        if error_every > 0 && i > 0 && i.is_multiple_of(error_every) {
+            inject_error(&cluster);
+        }
+        let evt = Event {
+            user_id: format!("user-{}", i % keys.max(1)),
+            action: "ordering".to_string(),
+            value: i as i64,
+        };
+        let payload = serde_json::to_vec(&evt)?;
+        let record = create_future_record(
+            Some(&evt.user_id),
+            &payload,
+            &cfg.topic,
+            PartitioningMode::Keyed,
+        )?;
+        for report in pipeline.send(&producer, record, evt.clone()).await {
+            check.observe(report);
+        }
+    }
+    while let Some(report) = pipeline.next_report().await {
+        check.observe(report);
+    }
+
+    info!(
+        max_in_flight = scenario.max_in_flight,
+        idempotence = scenario.idempotence,
+        delivered = check.delivered,
+        failed = check.failed,
+        reordered = check.reordered,
+        elapsed_secs = start.elapsed().as_secs_f64(),
+        "🏁 scenario finished"
+    );
+    Ok(check)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab5.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut scenarios = vec![
+        Scenario::parse("5").unwrap(),
+        Scenario::parse("1").unwrap(),
+        Scenario::parse("5+idempotence").unwrap(),
+    ];
+    let mut messages: u64 = 5_000;
+    let mut keys: u64 = 10;
+    let mut window: usize = 1_000;
+    let mut error_every: u64 = 250;
+    let mut partitions: i32 = 3;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--scenarios" => {
+                if let Some(list) = args.next() {
+                    scenarios = list.split(',').filter_map(Scenario::parse).collect();
+                }
+            }
+            "--messages" => {
+                if let Some(v) = args.next() {
+                    messages = v.parse().unwrap_or(messages);
+                }
+            }
+            "--keys" => {
+                if let Some(v) = args.next() {
+                    keys = v.parse().unwrap_or(keys);
+                }
+            }
+            "--window" => {
+                if let Some(v) = args.next() {
+                    window = v.parse().unwrap_or(window);
+                }
+            }
+            "--error-every" => {
+                if let Some(v) = args.next() {
+                    error_every = v.parse().unwrap_or(error_every);
+                }
+            }
+            "--partitions" => {
+                if let Some(v) = args.next() {
+                    partitions = v.parse().unwrap_or(partitions);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    info!(
+        scenarios = %scenarios.iter().map(Scenario::name).collect::<Vec<_>>().join(","),
+        messages,
+        keys,
+        window,
+        error_every,
+        "🚀 ordering demo started"
+    );
+
+    let mut results = Vec::new();
+    for scenario in scenarios {
+        let check = run(
+            scenario,
+            &cfg,
+            messages,
+            keys,
+            window,
+            error_every,
+            partitions,
+        )
+        .await?;
+        results.push((scenario, check));
+    }
+    for (scenario, check) in &results {
+        info!(
+            max_in_flight = scenario.max_in_flight,
+            idempotence = scenario.idempotence,
+            delivered = check.delivered,
+            reordered = check.reordered,
+            "📋 summary"
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,295 @@
+use anyhow::Result;
+use lab5_performance::prefill;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::{Message, OwnedMessage};
+use shared::config::AppConfig;
+use shared::context::LabContext;
+use shared::create_consumer_with_context;
+use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::metrics::Metrics;
+use shared::mock::Brokers;
+use shared::offsets::{FlushPolicy, OffsetTracker, SharedOffsetTracker};
+use shared::parallel::{ParallelConfig, ParallelDispatcher};
+use shared::shutdown::{self, Shutdown};
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Checks that each key's `value`s are handled in increasing order, which is
+/// what the producers (and `prefill`) write.
+#[derive(Default)]
+struct OrderCheck {
+    last_value: Mutex<HashMap<String, i64>>,
+    violations: AtomicU64,
+}
+
+impl OrderCheck {
+    fn observe(&self, evt: &Event) {
+        let mut last = self.last_value.lock().unwrap();
+        let previous = last.insert(evt.user_id.clone(), evt.value);
+        if previous.is_some_and(|p| p >= evt.value) {
+            self.violations.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                key = %evt.user_id,
+                previous = ?previous,
+                value = evt.value,
+                "🔀 out-of-order value for key"
+            );
+        }
+    }
+}
+
+fn flush_offsets(
+    offsets: &SharedOffsetTracker,
+    consumer: &StreamConsumer<LabContext>,
+    metrics: &Metrics,
+    force: bool,
+) -> Result<()> {
+    let mut tracker = offsets.lock().unwrap();
+    if (force || tracker.should_flush()) && tracker.flush(consumer)? > 0 {
+        metrics.inc_committed();
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab5.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut group_override: Option<String> = None;
+    let mut config = ParallelConfig::default();
+    let mut flush_policy = FlushPolicy::default();
+    let mut work = Duration::from_millis(20);
+    let mut limit: u64 = 0;
+    let mut prefill_count: u64 = 0;
+    let mut keys: u64 = 100;
+    let mut mock = false;
+    let mut partitions: i32 = 6;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--group-id" => {
+                if let Some(g) = args.next() {
+                    group_override = Some(g);
+                }
+            }
+            "--workers" => {
+                if let Some(v) = args.next() {
+                    config.workers = v.parse().unwrap_or(config.workers);
+                }
+            }
+            "--queue-capacity" => {
+                if let Some(v) = args.next() {
+                    config.queue_capacity = v.parse().unwrap_or(config.queue_capacity);
+                }
+            }
+            "--max-in-flight" => {
+                if let Some(v) = args.next() {
+                    config.max_in_flight = v.parse().unwrap_or(config.max_in_flight);
+                }
+            }
+            "--work-ms" => {
+                if let Some(v) = args.next() {
+                    work = v.parse().map(Duration::from_millis).unwrap_or(work);
+                }
+            }
+            "--commit-every" => {
+                if let Some(n) = args.next() {
+                    flush_policy.max_completed = n.parse().unwrap_or(flush_policy.max_completed);
+                }
+            }
+            "--commit-interval-ms" => {
+                if let Some(ms) = args.next() {
+                    flush_policy.max_interval = ms
+                        .parse()
+                        .map(Duration::from_millis)
+                        .unwrap_or(flush_policy.max_interval);
+                }
+            }
+            "--messages" => {
+                if let Some(v) = args.next() {
+                    limit = v.parse().unwrap_or(0);
+                }
+            }
+            "--prefill" => {
+                if let Some(v) = args.next() {
+                    prefill_count = v.parse().unwrap_or(0);
+                }
+            }
+            "--keys" => {
+                if let Some(v) = args.next() {
+                    keys = v.parse().unwrap_or(keys);
+                }
+            }
+            "--mock" => mock = true,
+            "--partitions" => {
+                if let Some(v) = args.next() {
+                    partitions = v.parse().unwrap_or(partitions);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+
+    let brokers = Brokers::new(mock, &cfg, partitions)?;
+    let bootstrap_servers = brokers.bootstrap_servers();
+    if mock && prefill_count == 0 {
+        prefill_count = 2_000;
+    }
+    if prefill_count > 0 {
+        prefill(&bootstrap_servers, &cfg, prefill_count, keys).await?;
+        if limit == 0 {
+            limit = prefill_count;
+        }
+    }
+
+    let group_id = group_override
+        .as_deref()
+        .or(cfg.group_id.as_deref())
+        .map(|g| format!("{g}-parallel"))
+        .unwrap_or("lab5-parallel".to_string());
+    let props = &[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("group.id", group_id.clone()),
+        ("auto.offset.reset", cfg.auto_offset_reset.clone()),
+        ("enable.auto.commit", "false".to_string()),
+        ("enable.auto.offset.store", "false".to_string()),
+    ];
+    let metrics = Metrics::new();
+    let offsets = OffsetTracker::shared(flush_policy);
+    let context = LabContext::new(Arc::clone(&metrics)).with_offset_tracker(Arc::clone(&offsets));
+    let consumer: StreamConsumer<LabContext> = create_consumer_with_context(props, context)?;
+    consumer.subscribe(&[&cfg.topic])?;
+
+    let order = Arc::new(OrderCheck::default());
+    let handler = {
+        let order = Arc::clone(&order);
+        move |m: OwnedMessage| {
+            let order = Arc::clone(&order);
+            async move {
+                let evt: Event = serde_json::from_slice(m.payload().unwrap_or_default())?;
+                // Simulated processing time, e.g. a call to another service.
+                tokio::time::sleep(work).await;
+                order.observe(&evt);
+                Ok(())
+            }
+        }
+    };
+    let mut dispatcher = ParallelDispatcher::new(config, Arc::clone(&offsets), handler);
+
+    info!(
+        bootstrap_servers = %bootstrap_servers,
+        topic = %cfg.topic,
+        group = %group_id,
+        workers = config.workers,
+        queue_capacity = config.queue_capacity,
+        max_in_flight = config.max_in_flight,
+        work_ms = work.as_millis() as u64,
+        messages = limit,
+        flush_policy = ?flush_policy,
+        "🚀 parallel consumer started"
+    );
+
+    let mut report_every = tokio::time::interval(Duration::from_secs(1));
+    report_every.tick().await; // the first tick completes immediately
+    let shutdown = Shutdown::listen();
+    let mut first: Option<Instant> = None;
+    let mut completed: u64 = 0;
+    let mut failed: u64 = 0;
+    let mut window_completed: u64 = 0;
+
+    while limit == 0 || completed + failed < limit {
+        tokio::select! {
+            Some(c) = dispatcher.next_completion() => {
+                match &c.result {
+                    Ok(()) => {
+                        completed += 1;
+                        window_completed += 1;
+                        metrics.inc_processed();
+                    }
+                    Err(e) => {
+                        failed += 1;
+                        metrics.inc_failed();
+                        warn!(
+                            partition = c.partition,
+                            offset = c.offset,
+                            worker = c.worker,
+                            error = %e,
+                            "❌ PROCESSING FAILED (partition commits held at this offset)"
+                        );
+                    }
+                }
+                dispatcher.on_completion(&consumer, &c);
+                flush_offsets(&offsets, &consumer, &metrics, false)?;
+            }
+            msg = consumer.recv() => match msg {
+                Ok(m) => {
+                    first.get_or_insert_with(Instant::now);
+                    dispatcher.dispatch(&consumer, m.detach());
+                }
+                Err(e) => warn!(error = %e, "Read error"),
+            },
+            _ = report_every.tick() => {
+                info!(
+                    msgs_per_sec = window_completed,
+                    completed,
+                    failed,
+                    in_flight = dispatcher.in_flight(),
+                    paused_partitions = dispatcher.paused_partitions(),
+                    "📊 progress"
+                );
+                window_completed = 0;
+                flush_offsets(&offsets, &consumer, &metrics, false)?;
+            }
+            _ = shutdown.requested() => break,
+        }
+    }
+
+    info!(in_flight = dispatcher.in_flight(), "⏳ Draining workers");
+    let drained = dispatcher.finish().await;
+    completed += drained.completed as u64;
+    failed += drained.failed as u64;
+    flush_offsets(&offsets, &consumer, &metrics, true)?;
+
+    let secs = first.map(|f| f.elapsed().as_secs_f64()).unwrap_or_default();
+    info!(
+        completed,
+        failed,
+        dropped = drained.dropped,
+        elapsed_secs = secs,
+        msgs_per_sec = if secs > 0.0 {
+            (completed as f64 / secs).round()
+        } else {
+            0.0
+        },
+        order_violations = order.violations.load(Ordering::Relaxed),
+        commits = metrics.committed(),
+        "🏁 parallel consumer finished"
+    );
+    shutdown::close_consumer(&consumer, &group_id);
+    Ok(())
+}
@@ -0,0 +1,70 @@
+//! Helpers shared by the lab 5 binaries.
+
+use std::time::Instant;
+
+use anyhow::Result;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use shared::config::AppConfig;
+use shared::create_producer_props;
+use shared::event::Event;
+use shared::record::create_future_record;
+use tracing::info;
+
+/// Produces `count` events to the profile's topic so a benchmark has something
+/// to read. Keys cycle through `user-0..user-{keys}` and `value` is the sequence
+/// number, so values grow per key.
+pub async fn prefill(
+    bootstrap_servers: &str,
+    cfg: &AppConfig,
+    count: u64,
+    keys: u64,
+) -> Result<()> {
+    let props = &[
+        ("bootstrap.servers", bootstrap_servers.to_string()),
+        (
+            "compression.type",
+            cfg.compression.clone().unwrap_or("lz4".to_string()),
+        ),
+        ("linger.ms", cfg.linger_ms.unwrap_or(5).to_string()),
+    ];
+    let producer = create_producer_props(props)?;
+    let start = Instant::now();
+    let mut in_flight = FuturesUnordered::new();
+    let mut failed: u64 = 0;
+
+    for i in 0..count {
+        let evt = Event {
+            user_id: format!("user-{}", i % keys.max(1)),
+            action: "prefill".to_string(),
+            value: i as i64,
+        };
+        let payload = serde_json::to_vec(&evt)?;
+        let record =
+            create_future_record(Some(&evt.user_id), &payload, &cfg.topic, cfg.partitioning)?;
+        let delivery = producer
+            .send_result(record)
+            .map_err(|(e, _)| anyhow::anyhow!(e))?;
+        in_flight.push(delivery);
+        while in_flight.len() >= 10_000 {
+            if let Some(result) = in_flight.next().await
+                && !matches!(result, Ok(Ok(_)))
+            {
+                failed += 1;
+            }
+        }
+    }
+    while let Some(result) = in_flight.next().await {
+        if !matches!(result, Ok(Ok(_))) {
+            failed += 1;
+        }
+    }
+    info!(
+        count,
+        failed,
+        elapsed_secs = start.elapsed().as_secs_f64(),
+        topic = %cfg.topic,
+        "📦 Topic pre-filled"
+    );
+    Ok(())
+}
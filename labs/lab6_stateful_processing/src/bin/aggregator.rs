@@ -0,0 +1,373 @@
+use anyhow::{Result, bail};
+use lab6_stateful_processing::{
+    PartitionState, Rebalanced, StateContext, check_copartitioned, restore_partitions,
+};
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::producer::{FutureRecord, Producer};
+use shared::config::{AppConfig, PartitioningMode};
+use shared::context::LabContext;
+use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::mock::{Brokers, Prefill};
+use shared::pipeline::{DeliveryPipeline, Report};
+use shared::record::create_future_record;
+use shared::shutdown::{self, Shutdown};
+use shared::{create_consumer_props, create_consumer_with_context, create_producer_props};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+const ACTIONS: [&str; 3] = ["view", "click", "purchase"];
+
+/// Input partition and offset of the record a changelog update came from.
+type Source = (i32, i64);
+
+/// Produces `count` events with a few actions and varying values, so the
+/// totals have something to show.
+async fn prefill(bootstrap_servers: &str, topic: &str, count: u64) -> Result<()> {
+    let mut prefill = Prefill::new(bootstrap_servers)?;
+    for i in 0..count {
+        let evt = Event {
+            user_id: format!("user-{}", i % 20),
+            action: ACTIONS[(i / 20) as usize % ACTIONS.len()].to_string(),
+            value: (i as i64 * 37) % 100,
+        };
+        let payload = serde_json::to_vec(&evt)?;
+        let record =
+            create_future_record(Some(&evt.user_id), &payload, topic, PartitioningMode::Keyed)?;
+        prefill.send(record).await;
+    }
+    prefill.finish().await?;
+    info!(count, topic, "🧪 Mock topic pre-filled");
+    Ok(())
+}
+
+/// Restores or drops partition state after the assignment changed.
+async fn on_rebalance(
+    rebalanced: Rebalanced,
+    states: &mut HashMap<i32, PartitionState>,
+    restorer: &StreamConsumer<LabContext>,
+    changelog: &str,
+) -> Result<()> {
+    match rebalanced {
+        Rebalanced::Assigned(partitions) => {
+            let started = Instant::now();
+            for (p, (state, records)) in
+                restore_partitions(restorer, changelog, &partitions).await?
+            {
+                info!(
+                    partition = p,
+                    changelog_records = records,
+                    users = state.users.len(),
+                    applied_offset = ?state.applied,
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "♻️ State restored from changelog"
+                );
+                states.insert(p, state);
+            }
+        }
+        Rebalanced::Revoked(partitions) => {
+            for p in partitions {
+                if let Some(state) = states.remove(&p) {
+                    info!(
+                        partition = p,
+                        users = state.users.len(),
+                        "🗑️ State dropped (partition revoked)"
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stores the input offset once its changelog update is delivered. Returns
+/// `false` on a failed delivery.
+fn on_report(
+    report: Report<Source>,
+    consumer: &StreamConsumer<StateContext>,
+    topic: &str,
+    updates: &mut u64,
+) -> bool {
+    let (partition, offset) = report.tag;
+    match report.result {
+        Ok(_) => {
+            *updates += 1;
+            // Fails when the partition was revoked meanwhile; its next owner
+            // restores this update and skips the input record.
+            if let Err(e) = consumer.store_offset(topic, partition, offset) {
+                debug!(partition, offset, error = %e, "Offset not stored");
+            }
+            true
+        }
+        Err(e) => {
+            error!(partition, offset, error = %e, "❌ Changelog write failed, stopping");
+            false
+        }
+    }
+}
+
+/// Reads every assigned partition's changelog again and compares it with the
+/// state built while processing.
+async fn verify_restore(
+    states: &HashMap<i32, PartitionState>,
+    restorer: &StreamConsumer<LabContext>,
+    changelog: &str,
+) -> Result<()> {
+    let partitions: Vec<i32> = states.keys().copied().collect();
+    for (p, (restored, _)) in restore_partitions(restorer, changelog, &partitions).await? {
+        if restored != states[&p] {
+            bail!("state restored from {changelog}[{p}] differs from the live state");
+        }
+    }
+    info!(
+        partitions = states.len(),
+        "🔍 Verified: state restored from the changelog matches the live state"
+    );
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab6.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut group_override: Option<String> = None;
+    let mut changelog_override: Option<String> = None;
+    let mut show: usize = 10;
+    let mut mock = false;
+    let mut messages: u64 = 2_000;
+    let mut partitions: i32 = 3;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--group-id" => {
+                if let Some(g) = args.next() {
+                    group_override = Some(g);
+                }
+            }
+            "--changelog-topic" => {
+                if let Some(t) = args.next() {
+                    changelog_override = Some(t);
+                }
+            }
+            "--show" => {
+                if let Some(n) = args.next() {
+                    show = n.parse().unwrap_or(show);
+                }
+            }
+            "--mock" => mock = true,
+            "--messages" => {
+                if let Some(n) = args.next() {
+                    messages = n.parse().unwrap_or(messages);
+                }
+            }
+            "--partitions" => {
+                if let Some(n) = args.next() {
+                    partitions = n.parse().unwrap_or(partitions);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    let group_id = group_override
+        .or(cfg.group_id.clone())
+        .unwrap_or("lab6-aggregator".to_string());
+    // Kafka Streams names changelogs `<application.id>-<store>-changelog`.
+    let changelog = changelog_override.unwrap_or(format!("{group_id}-user-totals-changelog"));
+
+    let brokers = Brokers::new(mock, &cfg, partitions)?;
+    let bootstrap_servers = brokers.bootstrap_servers();
+    if let Some(cluster) = brokers.mock() {
+        cluster.create_topic(&changelog, partitions, 1)?;
+        prefill(&bootstrap_servers, &cfg.topic, messages).await?;
+    }
+    let limit = if mock { messages } else { 0 };
+
+    let producer_props = &[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        (
+            "compression.type",
+            cfg.compression.clone().unwrap_or("lz4".to_string()),
+        ),
+        ("linger.ms", cfg.linger_ms.unwrap_or(5).to_string()),
+        // A retried update must not land after a newer one of the same user.
+        ("enable.idempotence", "true".to_string()),
+    ];
+    let producer = create_producer_props(producer_props)?;
+    // Changelog partition N holds the state of input partition N.
+    let partition_count = check_copartitioned(producer.client(), &cfg.topic, &changelog)?;
+
+    let consumer_props = &[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("group.id", group_id.clone()),
+        ("auto.offset.reset", cfg.auto_offset_reset.clone()),
+        // librdkafka commits only what `on_report` stored: input whose update is in the changelog.
+        ("enable.auto.commit", "true".to_string()),
+        ("enable.auto.offset.store", "false".to_string()),
+        // Only the partitions that move are revoked, so the others keep their state.
+        (
+            "partition.assignment.strategy",
+            "cooperative-sticky".to_string(),
+        ),
+    ];
+    let (context, mut rebalances) =
+        StateContext::new(LabContext::default(), &cfg.topic, producer.clone());
+    let consumer: StreamConsumer<StateContext> =
+        create_consumer_with_context(consumer_props, context)?;
+    consumer.subscribe(&[&cfg.topic])?;
+
+    let restorer = create_consumer_props(&[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("group.id", format!("{group_id}-restore")),
+        ("enable.auto.commit", "false".to_string()),
+    ])?;
+
+    info!(
+        bootstrap_servers = %bootstrap_servers,
+        topic = %cfg.topic,
+        changelog = %changelog,
+        partitions = partition_count,
+        group = %group_id,
+        "🚀 aggregator started"
+    );
+
+    let shutdown = Shutdown::listen();
+    let mut states: HashMap<i32, PartitionState> = HashMap::new();
+    let mut pipeline: DeliveryPipeline<Source> = DeliveryPipeline::new(1_000);
+    let mut progress = tokio::time::interval(Duration::from_secs(5));
+    progress.tick().await; // the first tick completes immediately
+    let mut read: u64 = 0;
+    let mut updates: u64 = 0;
+    let mut replayed: u64 = 0;
+    let mut invalid: u64 = 0;
+    let mut healthy = true;
+
+    while healthy && (limit == 0 || read < limit) {
+        tokio::select! {
+            msg = consumer.recv() => {
+                let m = match msg {
+                    Ok(m) => m,
+                    Err(e) => {
+                        warn!(error = %e, "Read error");
+                        continue;
+                    }
+                };
+                // The assignment may have changed while `recv` polled.
+                while let Ok(rebalanced) = rebalances.try_recv() {
+                    on_rebalance(rebalanced, &mut states, &restorer, &changelog).await?;
+                }
+                read += 1;
+                let (partition, offset) = (m.partition(), m.offset());
+                let evt: Event = match serde_json::from_slice(m.payload().unwrap_or_default()) {
+                    Ok(evt) => evt,
+                    Err(e) => {
+                        invalid += 1;
+                        warn!(partition, offset, error = %e, "Non-JSON, skipped");
+                        continue;
+                    }
+                };
+                let Some(state) = states.get_mut(&partition) else {
+                    warn!(partition, offset, "Record of a partition without state, skipped");
+                    continue;
+                };
+                let Some(user) = state.apply(offset, &evt) else {
+                    replayed += 1;
+                    debug!(partition, offset, "Already in the changelog, skipped");
+                    continue;
+                };
+                debug!(partition, offset, user = %user.user_id, totals = ?user.actions, "🧮 updated");
+                let payload = serde_json::to_vec(user)?;
+                let record = FutureRecord::to(&changelog)
+                    .partition(partition)
+                    .key(&user.user_id)
+                    .payload(&payload);
+                for report in pipeline.send(&producer, record, (partition, offset)).await {
+                    if healthy {
+                        healthy = on_report(report, &consumer, &cfg.topic, &mut updates);
+                    }
+                }
+            }
+            Some(rebalanced) = rebalances.recv() => {
+                on_rebalance(rebalanced, &mut states, &restorer, &changelog).await?;
+            }
+            Some(report) = pipeline.next_report(), if pipeline.pending() > 0 => {
+                if healthy {
+                    healthy = on_report(report, &consumer, &cfg.topic, &mut updates);
+                }
+            }
+            _ = progress.tick() => {
+                let mut owned: Vec<i32> = states.keys().copied().collect();
+                owned.sort();
+                let users: usize = states.values().map(|s| s.users.len()).sum();
+                let assigned = consumer.assignment().map(|a| a.count()).unwrap_or(0);
+                info!(partitions = ?owned, assigned, users, read, updates, replayed, pending = pipeline.pending(), "📊 progress");
+            }
+            _ = shutdown.requested() => break,
+        }
+    }
+
+    // Offsets are stored in send order, so after a failure nothing later may be stored.
+    for report in pipeline.drain(shutdown::FLUSH_TIMEOUT).await {
+        if healthy {
+            healthy = on_report(report, &consumer, &cfg.topic, &mut updates);
+        }
+    }
+    info!(
+        read,
+        updates,
+        replayed,
+        invalid,
+        undelivered = pipeline.pending(),
+        "🏁 aggregator stopped"
+    );
+    shutdown::close_consumer(&consumer, &group_id);
+
+    let totals: BTreeMap<&str, _> = states
+        .values()
+        .flat_map(|s| s.users.values())
+        .map(|u| (u.user_id.as_str(), &u.actions))
+        .collect();
+    for (user, actions) in totals.iter().take(show) {
+        for (action, t) in actions.iter() {
+            info!(
+                user,
+                action,
+                count = t.count,
+                sum = t.sum,
+                min = t.min,
+                max = t.max,
+                "📋 totals"
+            );
+        }
+    }
+
+    if mock {
+        verify_restore(&states, &restorer, &changelog).await?;
+    }
+    if !healthy {
+        bail!("aggregation stopped after a failed changelog write");
+    }
+    Ok(())
+}
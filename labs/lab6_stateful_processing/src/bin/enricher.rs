@@ -0,0 +1,482 @@
+use anyhow::{Context, Result, bail};
+use lab6_stateful_processing::{
+    EnrichedEvent, Rebalanced, StateContext, TablePartition, UserProfile, check_copartitioned,
+};
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::producer::Producer;
+use rdkafka::{Offset, TopicPartitionList};
+use shared::config::{AppConfig, PartitioningMode};
+use shared::context::LabContext;
+use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::mock::{Brokers, Prefill};
+use shared::pipeline::{DeliveryPipeline, Report};
+use shared::read_to_end::{self, ReadToEnd};
+use shared::record::create_future_record;
+use shared::shutdown::{self, Shutdown};
+use shared::{create_consumer_props, create_consumer_with_context, create_producer_props};
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// How long to wait for table watermarks and records while loading.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+const ACTIONS: [&str; 3] = ["view", "click", "purchase"];
+
+/// Input partition and offset of the record an enriched event came from.
+type Source = (i32, i64);
+
+/// Produces the `key|profile` lines of `users_file` to the table topic and
+/// `count` events of `u1`..`u8` to the input topic, keyed by `user_id` unless
+/// `unkeyed`. Returns the ids of the users with a profile.
+async fn prefill(
+    bootstrap_servers: &str,
+    topic: &str,
+    table_topic: &str,
+    users_file: &Path,
+    count: u64,
+    unkeyed: bool,
+) -> Result<HashSet<String>> {
+    let mut prefill = Prefill::new(bootstrap_servers)?;
+    let text = fs::read_to_string(users_file)
+        .with_context(|| format!("cannot read {}", users_file.display()))?;
+    let mut users = HashSet::new();
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let Some((key, profile)) = line.split_once('|') else {
+            bail!("{}: `{line}` is not `key|profile`", users_file.display());
+        };
+        users.insert(key.to_string());
+        let record = create_future_record(
+            Some(key),
+            profile.as_bytes(),
+            table_topic,
+            PartitioningMode::Keyed,
+        )?;
+        prefill.send(record).await;
+    }
+    let mode = if unkeyed {
+        PartitioningMode::RoundRobin
+    } else {
+        PartitioningMode::Keyed
+    };
+    for i in 0..count {
+        let evt = Event {
+            user_id: format!("u{}", i % 8 + 1),
+            action: ACTIONS[(i / 8) as usize % ACTIONS.len()].to_string(),
+            value: (i as i64 * 37) % 100,
+        };
+        let payload = serde_json::to_vec(&evt)?;
+        let record = create_future_record(Some(&evt.user_id), &payload, topic, mode)?;
+        prefill.send(record).await;
+    }
+    prefill.finish().await?;
+    info!(
+        users = users.len(),
+        events = count,
+        unkeyed,
+        "🧪 Mock topics pre-filled"
+    );
+    Ok(users)
+}
+
+/// The partitions of the table topic that match the assigned input
+/// partitions, materialized locally.
+struct Table {
+    topic: String,
+    partitions: BTreeMap<i32, TablePartition>,
+}
+
+impl Table {
+    /// Every table partition, at the position to continue reading from.
+    fn assignment(&self) -> Result<TopicPartitionList> {
+        let mut tpl = TopicPartitionList::new();
+        for (&p, partition) in &self.partitions {
+            let offset = partition.position.map_or(Offset::Beginning, Offset::Offset);
+            tpl.add_partition_offset(&self.topic, p, offset)?;
+        }
+        Ok(tpl)
+    }
+
+    fn apply<M: Message>(&mut self, m: &M) -> Result<()> {
+        let partition = self
+            .partitions
+            .get_mut(&m.partition())
+            .expect("only table partitions of the assignment are read");
+        partition.apply(m.offset(), m.key(), m.payload())
+    }
+}
+
+/// Loads or drops table partitions after the assignment changed. A newly
+/// assigned partition is read up to its high watermark before any input
+/// record of that partition is joined.
+async fn on_rebalance(
+    rebalanced: Rebalanced,
+    table: &mut Table,
+    reader: &StreamConsumer<LabContext>,
+) -> Result<()> {
+    match rebalanced {
+        Rebalanced::Assigned(partitions) => {
+            let started = Instant::now();
+            let ends = read_to_end::ends(reader, &table.topic, &partitions, LOAD_TIMEOUT)?;
+            for &p in &partitions {
+                table.partitions.insert(p, TablePartition::default());
+            }
+            // Partitions loaded before keep their position and are read too.
+            reader.assign(&table.assignment()?)?;
+            let mut loading = ReadToEnd::assigned(reader, ends, LOAD_TIMEOUT);
+            while let Some(m) = loading
+                .next()
+                .await
+                .with_context(|| format!("loading {} failed", table.topic))?
+            {
+                table.apply(&m)?;
+            }
+            for p in partitions {
+                info!(
+                    partition = p,
+                    rows = table.partitions[&p].rows.len(),
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "📚 Table partition loaded"
+                );
+            }
+        }
+        Rebalanced::Revoked(partitions) => {
+            for p in partitions {
+                if let Some(partition) = table.partitions.remove(&p) {
+                    info!(
+                        partition = p,
+                        rows = partition.rows.len(),
+                        "🗑️ Table partition dropped (partition revoked)"
+                    );
+                }
+            }
+            if table.partitions.is_empty() {
+                reader.unassign()?;
+            } else {
+                reader.assign(&table.assignment()?)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stores the input offset once its enriched event is delivered. Returns
+/// `false` on a failed delivery.
+fn on_report(
+    report: Report<Source>,
+    consumer: &StreamConsumer<StateContext>,
+    topic: &str,
+    emitted: &mut u64,
+) -> bool {
+    let (partition, offset) = report.tag;
+    match report.result {
+        Ok(_) => {
+            *emitted += 1;
+            if let Err(e) = consumer.store_offset(topic, partition, offset) {
+                debug!(partition, offset, error = %e, "Offset not stored");
+            }
+            true
+        }
+        Err(e) => {
+            error!(partition, offset, error = %e, "❌ Enriched event not delivered, stopping");
+            false
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab6.join".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut group_override: Option<String> = None;
+    let mut table_topic = "users".to_string();
+    let mut output_topic = "demo.enriched".to_string();
+    let mut inner = false;
+    let mut mock = false;
+    let mut messages: u64 = 1_000;
+    let mut partitions: i32 = 3;
+    let mut users_partitions: Option<i32> = None;
+    let mut users_file = PathBuf::from("labs/lab6_stateful_processing/fixtures/users.txt");
+    let mut unkeyed = false;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--group-id" => {
+                if let Some(g) = args.next() {
+                    group_override = Some(g);
+                }
+            }
+            "--table-topic" => {
+                if let Some(t) = args.next() {
+                    table_topic = t;
+                }
+            }
+            "--output-topic" => {
+                if let Some(t) = args.next() {
+                    output_topic = t;
+                }
+            }
+            "--join" => match args.next().as_deref() {
+                Some("left") => inner = false,
+                Some("inner") => inner = true,
+                other => bail!("--join expects `left` or `inner`, got {other:?}"),
+            },
+            "--mock" => mock = true,
+            "--messages" => {
+                if let Some(n) = args.next() {
+                    messages = n.parse().unwrap_or(messages);
+                }
+            }
+            "--partitions" => {
+                if let Some(n) = args.next() {
+                    partitions = n.parse().unwrap_or(partitions);
+                }
+            }
+            "--users-partitions" => {
+                if let Some(n) = args.next() {
+                    users_partitions = n.parse().ok();
+                }
+            }
+            "--users-file" => {
+                if let Some(p) = args.next() {
+                    users_file = PathBuf::from(p);
+                }
+            }
+            "--unkeyed-events" => unkeyed = true,
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    let group_id = group_override
+        .or(cfg.group_id.clone())
+        .unwrap_or("lab6-enricher".to_string());
+
+    let brokers = Brokers::new(mock, &cfg, partitions)?;
+    let bootstrap_servers = brokers.bootstrap_servers();
+    let mut known_users = HashSet::new();
+    if let Some(cluster) = brokers.mock() {
+        cluster.create_topic(&table_topic, users_partitions.unwrap_or(partitions), 1)?;
+        cluster.create_topic(&output_topic, partitions, 1)?;
+        known_users = prefill(
+            &bootstrap_servers,
+            &cfg.topic,
+            &table_topic,
+            &users_file,
+            messages,
+            unkeyed,
+        )
+        .await?;
+    }
+    let limit = if mock { messages } else { 0 };
+
+    let producer_props = &[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        (
+            "compression.type",
+            cfg.compression.clone().unwrap_or("lz4".to_string()),
+        ),
+        ("linger.ms", cfg.linger_ms.unwrap_or(5).to_string()),
+        ("enable.idempotence", "true".to_string()),
+    ];
+    let producer = create_producer_props(producer_props)?;
+    // Table partition N holds the profiles of the users whose events are in input partition N.
+    let partition_count = check_copartitioned(producer.client(), &cfg.topic, &table_topic)?;
+
+    let consumer_props = &[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("group.id", group_id.clone()),
+        ("auto.offset.reset", cfg.auto_offset_reset.clone()),
+        // librdkafka commits only what `on_report` stored: input whose enriched event is delivered.
+        ("enable.auto.commit", "true".to_string()),
+        ("enable.auto.offset.store", "false".to_string()),
+        // Only the partitions that move are revoked, so the others keep their table.
+        (
+            "partition.assignment.strategy",
+            "cooperative-sticky".to_string(),
+        ),
+    ];
+    let (context, mut rebalances) =
+        StateContext::new(LabContext::default(), &cfg.topic, producer.clone());
+    let consumer: StreamConsumer<StateContext> =
+        create_consumer_with_context(consumer_props, context)?;
+    consumer.subscribe(&[&cfg.topic])?;
+
+    // Reads the table partitions by assignment: the table is always read from
+    // the beginning, so it has no offsets to commit.
+    let reader = create_consumer_props(&[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("group.id", format!("{group_id}-table")),
+        ("enable.auto.commit", "false".to_string()),
+    ])?;
+    let mut table = Table {
+        topic: table_topic.clone(),
+        partitions: BTreeMap::new(),
+    };
+
+    info!(
+        bootstrap_servers = %bootstrap_servers,
+        topic = %cfg.topic,
+        table = %table_topic,
+        output_topic = %output_topic,
+        join = if inner { "inner" } else { "left" },
+        partitions = partition_count,
+        group = %group_id,
+        "🚀 enricher started"
+    );
+
+    let shutdown = Shutdown::listen();
+    let mut pipeline: DeliveryPipeline<Source> = DeliveryPipeline::new(1_000);
+    let mut progress = tokio::time::interval(Duration::from_secs(5));
+    progress.tick().await; // the first tick completes immediately
+    let mut read: u64 = 0;
+    let mut matched: u64 = 0;
+    let mut unmatched: u64 = 0;
+    let mut missed_known: u64 = 0;
+    let mut table_updates: u64 = 0;
+    let mut emitted: u64 = 0;
+    let mut invalid: u64 = 0;
+    let mut healthy = true;
+
+    while healthy && (limit == 0 || read < limit) {
+        tokio::select! {
+            msg = consumer.recv() => {
+                let m = match msg {
+                    Ok(m) => m,
+                    Err(e) => {
+                        warn!(error = %e, "Read error");
+                        continue;
+                    }
+                };
+                // The assignment may have changed while `recv` polled.
+                while let Ok(rebalanced) = rebalances.try_recv() {
+                    on_rebalance(rebalanced, &mut table, &reader).await?;
+                }
+                read += 1;
+                let (partition, offset) = (m.partition(), m.offset());
+                let event: Event = match serde_json::from_slice(m.payload().unwrap_or_default()) {
+                    Ok(evt) => evt,
+                    Err(e) => {
+                        invalid += 1;
+                        warn!(partition, offset, error = %e, "Non-JSON, skipped");
+                        continue;
+                    }
+                };
+                let Some(rows) = table.partitions.get(&partition).map(|t| &t.rows) else {
+                    warn!(partition, offset, "Record of a partition without table, skipped");
+                    continue;
+                };
+                // Only this partition's rows are looked at: co-partitioning is what
+                // guarantees the profile, if any, is here.
+                let user: Option<UserProfile> = rows.get(&event.user_id).cloned();
+                if user.is_some() {
+                    matched += 1;
+                } else {
+                    unmatched += 1;
+                    if known_users.contains(&event.user_id) {
+                        missed_known += 1;
+                    }
+                    debug!(partition, offset, user = %event.user_id, "No profile in this partition");
+                    if inner {
+                        // Not stored: the next delivered record stores a later offset.
+                        continue;
+                    }
+                }
+                let enriched = EnrichedEvent { event, user };
+                let payload = serde_json::to_vec(&enriched)?;
+                let record = create_future_record(
+                    Some(&enriched.event.user_id),
+                    &payload,
+                    &output_topic,
+                    PartitioningMode::Keyed,
+                )?;
+                for report in pipeline.send(&producer, record, (partition, offset)).await {
+                    if healthy {
+                        healthy = on_report(report, &consumer, &cfg.topic, &mut emitted);
+                    }
+                }
+            }
+            msg = reader.recv() => {
+                let m = msg?;
+                table.apply(&m)?;
+                table_updates += 1;
+                debug!(partition = m.partition(), offset = m.offset(), "📝 Table row updated");
+            }
+            Some(rebalanced) = rebalances.recv() => {
+                on_rebalance(rebalanced, &mut table, &reader).await?;
+            }
+            Some(report) = pipeline.next_report(), if pipeline.pending() > 0 => {
+                if healthy {
+                    healthy = on_report(report, &consumer, &cfg.topic, &mut emitted);
+                }
+            }
+            _ = progress.tick() => {
+                let rows: usize = table.partitions.values().map(|t| t.rows.len()).sum();
+                let owned: Vec<i32> = table.partitions.keys().copied().collect();
+                info!(partitions = ?owned, rows, read, matched, unmatched, table_updates, pending = pipeline.pending(), "📊 progress");
+            }
+            _ = shutdown.requested() => break,
+        }
+    }
+
+    // Offsets are stored in send order, so after a failure nothing later may be stored.
+    for report in pipeline.drain(shutdown::FLUSH_TIMEOUT).await {
+        if healthy {
+            healthy = on_report(report, &consumer, &cfg.topic, &mut emitted);
+        }
+    }
+    info!(
+        read,
+        matched,
+        unmatched,
+        table_updates,
+        emitted,
+        invalid,
+        undelivered = pipeline.pending(),
+        "🏁 enricher stopped"
+    );
+    shutdown::close_consumer(&consumer, &group_id);
+
+    if mock {
+        if missed_known > 0 {
+            warn!(
+                missed = missed_known,
+                "🔍 Events of users with a profile found none: their profile is in another partition"
+            );
+            if !unkeyed {
+                bail!(
+                    "the join missed {missed_known} profiles although both topics are keyed by user_id"
+                );
+            }
+        } else {
+            info!("🔍 Every event of a user with a profile was joined with it");
+        }
+    }
+    if !healthy {
+        bail!("enrichment stopped after a failed delivery");
+    }
+    Ok(())
+}
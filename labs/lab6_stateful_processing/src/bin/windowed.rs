@@ -0,0 +1,463 @@
+use anyhow::{Context, Result, bail};
+use chrono::DateTime;
+use lab6_stateful_processing::{Rebalanced, RevokeHook, StateContext};
+use rdkafka::TopicPartitionList;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::producer::FutureRecord;
+use shared::config::{AppConfig, PartitioningMode};
+use shared::context::LabContext;
+use shared::event::Event;
+use shared::ingest::{self, ColumnMapping, InputFormat};
+use shared::logging::{self, LogFormat};
+use shared::mock::{Brokers, Prefill};
+use shared::pipeline::{DeliveryPipeline, Report};
+use shared::record::create_future_record;
+use shared::shutdown::{self, Shutdown};
+use shared::util::parse_duration_ms;
+use shared::windowing::{Added, EmitMode, WindowResult, WindowSpec, WindowStore};
+use shared::{create_consumer_with_context, create_producer_props};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+/// `HH:MM:SS.mmm` of an epoch-millisecond timestamp, for the logs.
+fn time(ms: i64) -> String {
+    DateTime::from_timestamp_millis(ms)
+        .map(|t| t.format("%H:%M:%S%.3f").to_string())
+        .unwrap_or(ms.to_string())
+}
+
+fn describe(r: &WindowResult) -> String {
+    format!("[{} – {}]", time(r.window.start), time(r.window.end))
+}
+
+/// Produces the records of a JSON Lines fixture, with their timestamps, and
+/// returns how many there were.
+async fn prefill(bootstrap_servers: &str, topic: &str, input: &Path) -> Result<u64> {
+    let mut prefill = Prefill::new(bootstrap_servers)?;
+    let mut mapping = ColumnMapping::default();
+    mapping.apply("timestamp=ts")?;
+    let file = File::open(input).with_context(|| format!("cannot open {}", input.display()))?;
+    let mut rows = ingest::spawn_reader(file, InputFormat::JsonLines, mapping);
+    while let Some(row) = rows.recv().await {
+        let row = row?;
+        let payload = serde_json::to_vec(&row.event)?;
+        let record =
+            create_future_record(Some(&row.key), &payload, topic, PartitioningMode::Keyed)?;
+        prefill.send(row.apply(record)).await;
+    }
+    let count = prefill.finish().await?;
+    info!(count, topic, input = %input.display(), "🧪 Mock topic pre-filled");
+    Ok(count)
+}
+
+/// Window state of the assigned partitions, with a snapshot file per partition.
+struct Stores {
+    dir: PathBuf,
+    group_id: String,
+    spec: WindowSpec,
+    grace: i64,
+    by_partition: HashMap<i32, WindowStore>,
+}
+
+impl Stores {
+    fn path(&self, partition: i32) -> PathBuf {
+        self.dir
+            .join(format!("{}-p{partition}.json", self.group_id))
+    }
+
+    fn on_rebalance(&mut self, rebalanced: Rebalanced) -> Result<()> {
+        // Revoked partitions were saved and dropped in the rebalance callback.
+        let Rebalanced::Assigned(partitions) = rebalanced else {
+            return Ok(());
+        };
+        for p in partitions {
+            let path = self.path(p);
+            let store = WindowStore::load(&path, self.spec, self.grace)
+                .with_context(|| format!("remove {} to start over", path.display()))?;
+            info!(
+                partition = p,
+                open_windows = store.open_windows(),
+                stream_time = %store.stream_time().map(time).unwrap_or_default(),
+                applied_offset = ?store.applied(),
+                "♻️ Windows loaded from snapshot"
+            );
+            self.by_partition.insert(p, store);
+        }
+        Ok(())
+    }
+
+    /// Saves the snapshots of `partitions`, then commits the offset after the
+    /// last record each one includes.
+    fn save<C: Consumer<StateContext>>(
+        &self,
+        consumer: &C,
+        topic: &str,
+        partitions: &[i32],
+    ) -> Result<()> {
+        let mut tpl = TopicPartitionList::new();
+        for p in partitions {
+            let Some(store) = self.by_partition.get(p) else {
+                continue;
+            };
+            store.save(&self.path(*p))?;
+            if let Some(applied) = store.applied() {
+                tpl.add_partition_offset(topic, *p, rdkafka::Offset::Offset(applied + 1))?;
+            }
+        }
+        if tpl.count() > 0 {
+            consumer.commit(&tpl, CommitMode::Sync)?;
+            debug!(partitions = tpl.count(), "💾 checkpoint");
+        }
+        Ok(())
+    }
+}
+
+/// The window stores and the results sent from them, shared by the processing
+/// loop and the rebalance callback.
+///
+/// A snapshot is saved, and its offset committed, only once every result sent
+/// before it is delivered: with `--emit close` a closed window is gone from the
+/// store, so a result lost after the commit could never be sent again.
+struct Windows {
+    stores: Stores,
+    results: DeliveryPipeline<()>,
+    emitted: u64,
+}
+
+impl Windows {
+    /// Counts delivered results; an undelivered one is fatal.
+    fn collect(&mut self, reports: Vec<Report<()>>) -> Result<()> {
+        for report in reports {
+            if let Err(e) = report.result {
+                bail!("a result was not delivered: {e}");
+            }
+            self.emitted += 1;
+        }
+        Ok(())
+    }
+
+    fn ensure_delivered(&self) -> Result<()> {
+        match self.results.pending() {
+            0 => Ok(()),
+            n => bail!("{n} results are still not delivered"),
+        }
+    }
+
+    /// Waits for every result in flight, then saves and commits every partition.
+    async fn checkpoint(
+        &mut self,
+        consumer: &StreamConsumer<StateContext>,
+        topic: &str,
+    ) -> Result<()> {
+        let reports = self.results.drain(shutdown::FLUSH_TIMEOUT).await;
+        self.collect(reports)?;
+        self.ensure_delivered()?;
+        let partitions: Vec<i32> = self.stores.by_partition.keys().copied().collect();
+        self.stores.save(consumer, topic, &partitions)
+    }
+
+    /// Called from the rebalance callback, right after the producer flush: every
+    /// result has its report. Saves and commits the revoked partitions, then
+    /// drops them.
+    fn revoke(
+        &mut self,
+        consumer: &BaseConsumer<StateContext>,
+        topic: &str,
+        partitions: &[i32],
+    ) -> Result<()> {
+        let reports = self.results.completed();
+        self.collect(reports)?;
+        self.ensure_delivered()?;
+        self.stores.save(consumer, topic, partitions)?;
+        for p in partitions {
+            if let Some(store) = self.stores.by_partition.remove(p) {
+                info!(
+                    partition = p,
+                    open_windows = store.open_windows(),
+                    "🗑️ Windows saved and dropped (partition revoked)"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab6.windows".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut group_override: Option<String> = None;
+    let mut spec: WindowSpec = "tumbling:1m".parse()?;
+    let mut grace: i64 = 10_000;
+    let mut emit = EmitMode::Update;
+    let mut output_topic = "demo.windows".to_string();
+    let mut state_dir = PathBuf::from("lab6-state");
+    let mut checkpoint_secs: u64 = 5;
+    let mut mock = false;
+    let mut input = PathBuf::from("labs/lab6_stateful_processing/fixtures/late-events.jsonl");
+    let mut partitions: i32 = 3;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--group-id" => {
+                if let Some(g) = args.next() {
+                    group_override = Some(g);
+                }
+            }
+            "--window" => {
+                if let Some(w) = args.next() {
+                    spec = w.parse()?;
+                }
+            }
+            "--grace" => {
+                if let Some(g) = args.next() {
+                    grace = parse_duration_ms(&g)?;
+                }
+            }
+            "--emit" => {
+                if let Some(e) = args.next() {
+                    emit = e.parse()?;
+                }
+            }
+            "--output-topic" => {
+                if let Some(t) = args.next() {
+                    output_topic = t;
+                }
+            }
+            "--state-dir" => {
+                if let Some(d) = args.next() {
+                    state_dir = PathBuf::from(d);
+                }
+            }
+            "--checkpoint-secs" => {
+                if let Some(n) = args.next() {
+                    checkpoint_secs = n.parse().unwrap_or(checkpoint_secs);
+                }
+            }
+            "--mock" => mock = true,
+            "--input" => {
+                if let Some(p) = args.next() {
+                    input = PathBuf::from(p);
+                }
+            }
+            "--partitions" => {
+                if let Some(n) = args.next() {
+                    partitions = n.parse().unwrap_or(partitions);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    let group_id = group_override
+        .or(cfg.group_id.clone())
+        .unwrap_or("lab6-windows".to_string());
+
+    let brokers = Brokers::new(mock, &cfg, partitions)?;
+    let bootstrap_servers = brokers.bootstrap_servers();
+    let mut limit = 0;
+    if let Some(cluster) = brokers.mock() {
+        cluster.create_topic(&output_topic, partitions, 1)?;
+        limit = prefill(&bootstrap_servers, &cfg.topic, &input).await?;
+        // Snapshots of an earlier mock run would not match this cluster's offsets.
+        state_dir = env::temp_dir().join(format!("lab6-windows-mock-{}", std::process::id()));
+    }
+
+    fs::create_dir_all(&state_dir)?;
+    let windows = Arc::new(Mutex::new(Windows {
+        stores: Stores {
+            dir: state_dir.clone(),
+            group_id: group_id.clone(),
+            spec,
+            grace,
+            by_partition: HashMap::new(),
+        },
+        results: DeliveryPipeline::new(1_000),
+        emitted: 0,
+    }));
+    let on_revoke: RevokeHook = {
+        let windows = Arc::clone(&windows);
+        let topic = cfg.topic.clone();
+        Arc::new(move |consumer, partitions| {
+            // The processing loop never holds the lock while it polls the
+            // consumer, and the callback only runs inside that poll.
+            let mut windows = windows
+                .try_lock()
+                .context("window state locked during a rebalance")?;
+            windows.revoke(consumer, &topic, partitions)
+        })
+    };
+
+    let producer = create_producer_props(&[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("enable.idempotence", "true".to_string()),
+    ])?;
+    let consumer_props = &[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("group.id", group_id.clone()),
+        ("auto.offset.reset", cfg.auto_offset_reset.clone()),
+        // Offsets are committed with the snapshots, see `Stores::save`.
+        ("enable.auto.commit", "false".to_string()),
+        (
+            "partition.assignment.strategy",
+            "cooperative-sticky".to_string(),
+        ),
+    ];
+    let (context, mut rebalances) =
+        StateContext::new(LabContext::default(), &cfg.topic, producer.clone());
+    let consumer: StreamConsumer<StateContext> =
+        create_consumer_with_context(consumer_props, context.with_revoke_hook(on_revoke))?;
+    consumer.subscribe(&[&cfg.topic])?;
+
+    info!(
+        topic = %cfg.topic,
+        output_topic = %output_topic,
+        window = ?spec,
+        grace_ms = grace,
+        emit = ?emit,
+        state_dir = %state_dir.display(),
+        group = %group_id,
+        "🚀 windowed aggregation started"
+    );
+
+    let shutdown = Shutdown::listen();
+    let mut checkpoint = tokio::time::interval(Duration::from_secs(checkpoint_secs.max(1)));
+    checkpoint.tick().await; // the first tick completes immediately
+    let (mut read, mut late, mut dropped) = (0u64, 0u64, 0u64);
+
+    while limit == 0 || read < limit {
+        tokio::select! {
+            msg = consumer.recv() => {
+                let m = match msg {
+                    Ok(m) => m,
+                    Err(e) => {
+                        warn!(error = %e, "Read error");
+                        continue;
+                    }
+                };
+                let mut windows = windows.lock().await;
+                // The assignment may have changed while `recv` polled.
+                while let Ok(rebalanced) = rebalances.try_recv() {
+                    windows.stores.on_rebalance(rebalanced)?;
+                }
+                read += 1;
+                let (partition, offset) = (m.partition(), m.offset());
+                let (Some(ts), Ok(evt)) = (
+                    m.timestamp().to_millis(),
+                    serde_json::from_slice::<Event>(m.payload().unwrap_or_default()),
+                ) else {
+                    warn!(partition, offset, "Record without timestamp or not an Event, skipped");
+                    continue;
+                };
+                let Some(store) = windows.stores.by_partition.get_mut(&partition) else {
+                    warn!(partition, offset, "Record of a partition without state, skipped");
+                    continue;
+                };
+                let stream_time = store.stream_time();
+                let mut results = Vec::new();
+                match store.add(offset, &evt.user_id, ts, evt.value) {
+                    Added::Accepted { updated, late: is_late } => {
+                        if is_late {
+                            late += 1;
+                            info!(
+                                key = %evt.user_id,
+                                ts = %time(ts),
+                                stream_time = %stream_time.map(time).unwrap_or_default(),
+                                "⏰ Late record accepted (its window is still open)"
+                            );
+                        }
+                        if emit == EmitMode::Update {
+                            results.extend(updated);
+                        }
+                    }
+                    Added::Dropped => {
+                        dropped += 1;
+                        warn!(
+                            key = %evt.user_id,
+                            ts = %time(ts),
+                            stream_time = %stream_time.map(time).unwrap_or_default(),
+                            "🗑️ Late record dropped (its windows are closed)"
+                        );
+                    }
+                    Added::Replayed => debug!(partition, offset, "Already in the snapshot, skipped"),
+                }
+                for closed in store.close_expired() {
+                    info!(key = %closed.key, window = %describe(&closed), "🔒 Window closed");
+                    if emit == EmitMode::Close {
+                        results.push(closed);
+                    }
+                }
+                for r in results {
+                    info!(
+                        key = %r.key,
+                        window = %describe(&r),
+                        count = r.totals.count,
+                        sum = r.totals.sum,
+                        min = r.totals.min,
+                        max = r.totals.max,
+                        closed = r.closed,
+                        "📤 result"
+                    );
+                    let payload = serde_json::to_vec(&r)?;
+                    let record = FutureRecord::to(&output_topic).key(&r.key).payload(&payload);
+                    let reports = windows.results.send(&producer, record, ()).await;
+                    windows.collect(reports)?;
+                }
+                let reports = windows.results.completed();
+                windows.collect(reports)?;
+            }
+            Some(rebalanced) = rebalances.recv() => windows.lock().await.stores.on_rebalance(rebalanced)?,
+            _ = checkpoint.tick() => windows.lock().await.checkpoint(&consumer, &cfg.topic).await?,
+            _ = shutdown.requested() => break,
+        }
+    }
+
+    let (emitted, open_windows) = {
+        let mut windows = windows.lock().await;
+        windows.checkpoint(&consumer, &cfg.topic).await?;
+        let open_windows: usize = windows
+            .stores
+            .by_partition
+            .values()
+            .map(WindowStore::open_windows)
+            .sum();
+        (windows.emitted, open_windows)
+    };
+    info!(
+        read,
+        late, dropped, emitted, open_windows, "🏁 windowed aggregation stopped"
+    );
+    shutdown::close_consumer(&consumer, &group_id);
+    // Closing runs the last revocation, which still saves into the state dir.
+    drop(consumer);
+    if brokers.mock().is_some() {
+        fs::remove_dir_all(&state_dir)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,333 @@
+//! Helpers shared by the lab 6 binaries.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use rdkafka::TopicPartitionList;
+use rdkafka::client::{Client, ClientContext};
+use rdkafka::config::RDKafkaLogLevel;
+use rdkafka::consumer::{BaseConsumer, Consumer, ConsumerContext, Rebalance, StreamConsumer};
+use rdkafka::error::{KafkaError, KafkaResult};
+use rdkafka::message::Message;
+use rdkafka::producer::{FutureProducer, Producer};
+use rdkafka::statistics::Statistics;
+use serde::{Deserialize, Serialize};
+use shared::context::LabContext;
+use shared::event::Event;
+use shared::read_to_end::{self, ReadToEnd};
+use shared::shutdown::FLUSH_TIMEOUT;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+/// How long to wait for metadata lookups.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for changelog watermarks and records while restoring.
+const RESTORE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Running count, sum, min and max of the `value`s of one action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Totals {
+    pub count: u64,
+    pub sum: i64,
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Totals {
+    fn new(value: i64) -> Self {
+        Self {
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        }
+    }
+
+    fn add(&mut self, value: i64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+}
+
+/// A user's totals per action, as written to the changelog topic (keyed by
+/// `user_id`) after every update.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserTotals {
+    pub user_id: String,
+    pub actions: BTreeMap<String, Totals>,
+    /// Offset of the input record that produced this version.
+    pub input_offset: i64,
+}
+
+/// Aggregation state of one input partition.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PartitionState {
+    pub users: HashMap<String, UserTotals>,
+    /// Highest input offset already applied: records up to it are skipped, so
+    /// replaying input that the changelog already reflects does not count twice.
+    pub applied: Option<i64>,
+}
+
+impl PartitionState {
+    /// Adds `evt`, read at input `offset`, to its user's totals and returns the
+    /// new version, or `None` when the record was applied before.
+    pub fn apply(&mut self, offset: i64, evt: &Event) -> Option<&UserTotals> {
+        if self.applied.is_some_and(|a| offset <= a) {
+            return None;
+        }
+        self.applied = Some(offset);
+        let user = self
+            .users
+            .entry(evt.user_id.clone())
+            .or_insert_with(|| UserTotals {
+                user_id: evt.user_id.clone(),
+                actions: BTreeMap::new(),
+                input_offset: offset,
+            });
+        user.input_offset = offset;
+        user.actions
+            .entry(evt.action.clone())
+            .and_modify(|t| t.add(evt.value))
+            .or_insert_with(|| Totals::new(evt.value));
+        Some(user)
+    }
+
+    /// Replays one changelog record; a null payload (tombstone) removes the user.
+    fn restore(&mut self, key: Option<&[u8]>, payload: Option<&[u8]>) -> Result<()> {
+        match payload {
+            Some(p) => {
+                let user: UserTotals = serde_json::from_slice(p)?;
+                self.applied = self.applied.max(Some(user.input_offset));
+                self.users.insert(user.user_id.clone(), user);
+            }
+            None => {
+                if let Some(k) = key {
+                    self.users.remove(String::from_utf8_lossy(k).as_ref());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rebuilds the state of `partitions` by reading their changelog partitions
+/// from the beginning up to the current high watermarks. `consumer` is a
+/// restore consumer outside any group: its assignment is replaced, nothing is
+/// committed.
+///
+/// Returns each partition's state and the number of changelog records read.
+pub async fn restore_partitions<C: ConsumerContext + 'static>(
+    consumer: &StreamConsumer<C>,
+    changelog: &str,
+    partitions: &[i32],
+) -> Result<BTreeMap<i32, (PartitionState, u64)>> {
+    let mut restored = BTreeMap::new();
+    for &p in partitions {
+        restored.insert(p, (PartitionState::default(), 0));
+    }
+    let ends = read_to_end::ends(consumer, changelog, partitions, RESTORE_TIMEOUT)?;
+    if ends.is_empty() {
+        return Ok(restored);
+    }
+    let mut reader = ReadToEnd::from_beginning(consumer, ends, RESTORE_TIMEOUT)?;
+    while let Some(m) = reader.next().await? {
+        let (state, records) = restored
+            .get_mut(&m.partition())
+            .expect("only requested partitions are assigned");
+        state.restore(m.key(), m.payload())?;
+        *records += 1;
+    }
+    consumer.unassign()?;
+    Ok(restored)
+}
+
+/// A user's profile, the value of the compacted `users` topic (keyed by `user_id`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub user_id: String,
+    pub name: String,
+    pub country: String,
+    pub tier: String,
+}
+
+/// An event joined with its user's profile, `None` when the table has none.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnrichedEvent {
+    #[serde(flatten)]
+    pub event: Event,
+    pub user: Option<UserProfile>,
+}
+
+/// The rows of one partition of a table topic, materialized locally.
+#[derive(Debug, Default)]
+pub struct TablePartition {
+    pub rows: HashMap<String, UserProfile>,
+    /// Offset of the next record to read.
+    pub position: Option<i64>,
+}
+
+impl TablePartition {
+    /// Applies the table record at `offset`: a profile replaces the row of its
+    /// key, a null payload (tombstone) removes it.
+    pub fn apply(&mut self, offset: i64, key: Option<&[u8]>, payload: Option<&[u8]>) -> Result<()> {
+        self.position = Some(offset + 1);
+        let Some(key) = key else {
+            bail!("table record at offset {offset} has no key");
+        };
+        let key = String::from_utf8_lossy(key).into_owned();
+        match payload {
+            Some(p) => {
+                self.rows.insert(key, serde_json::from_slice(p)?);
+            }
+            None => {
+                self.rows.remove(&key);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Partitions added to or removed from a stateful consumer's assignment.
+#[derive(Debug)]
+pub enum Rebalanced {
+    Assigned(Vec<i32>),
+    Revoked(Vec<i32>),
+}
+
+/// Work to finish before partitions are revoked, such as saving their state
+/// and committing its offsets. It runs in the rebalance callback, after the
+/// producer flush: the processing loop cannot run until the callback returns,
+/// so this is the last moment the partitions are still ours. An error stops
+/// the process.
+pub type RevokeHook = Arc<dyn Fn(&BaseConsumer<StateContext>, &[i32]) -> Result<()> + Send + Sync>;
+
+/// Consumer context of the stateful binaries: [`LabContext`] logging and
+/// metrics, plus what stateful processing needs around a rebalance.
+///
+/// Before partitions are revoked it flushes `producer` (changelog or output
+/// records), so nothing written for them is still in flight when the next owner
+/// takes over, then runs the [`RevokeHook`] if there is one. Assignment changes
+/// of the input topic are sent to the processing loop, which restores or drops
+/// state before it handles the next record.
+#[derive(Clone)]
+pub struct StateContext {
+    lab: LabContext,
+    topic: String,
+    producer: FutureProducer<LabContext>,
+    rebalances: mpsc::UnboundedSender<Rebalanced>,
+    on_revoke: Option<RevokeHook>,
+}
+
+impl StateContext {
+    /// The context, and the receiving end of its assignment changes.
+    pub fn new(
+        lab: LabContext,
+        topic: &str,
+        producer: FutureProducer<LabContext>,
+    ) -> (Self, mpsc::UnboundedReceiver<Rebalanced>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let context = Self {
+            lab,
+            topic: topic.to_string(),
+            producer,
+            rebalances: tx,
+            on_revoke: None,
+        };
+        (context, rx)
+    }
+
+    pub fn with_revoke_hook(mut self, hook: RevokeHook) -> Self {
+        self.on_revoke = Some(hook);
+        self
+    }
+
+    fn partitions(&self, tpl: &TopicPartitionList) -> Vec<i32> {
+        tpl.elements()
+            .iter()
+            .filter(|e| e.topic() == self.topic)
+            .map(|e| e.partition())
+            .collect()
+    }
+}
+
+impl ClientContext for StateContext {
+    fn log(&self, level: RDKafkaLogLevel, fac: &str, log_message: &str) {
+        self.lab.log(level, fac, log_message);
+    }
+
+    fn stats(&self, statistics: Statistics) {
+        self.lab.stats(statistics);
+    }
+
+    fn error(&self, error: KafkaError, reason: &str) {
+        self.lab.error(error, reason);
+    }
+}
+
+impl ConsumerContext for StateContext {
+    fn pre_rebalance(&self, base_consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        let Rebalance::Revoke(revoked) = rebalance else {
+            return;
+        };
+        let partitions = self.partitions(revoked);
+        if partitions.is_empty() {
+            return;
+        }
+        // Blocks the poll loop, like Kafka Streams flushing its producer in onPartitionsRevoked.
+        match self.producer.flush(FLUSH_TIMEOUT) {
+            Ok(()) => info!(partitions = ?partitions, "📤 Producer flushed before revocation"),
+            Err(e) => warn!(error = %e, "Producer flush before revocation did not complete"),
+        }
+        if let Some(hook) = &self.on_revoke
+            && let Err(e) = hook(base_consumer, &partitions)
+        {
+            // Handing the partitions over now could lose what they produced.
+            error!(partitions = ?partitions, error = %e, "❌ Revocation could not be completed, stopping");
+            std::process::exit(1);
+        }
+        // The receiver is gone only once the processing loop has stopped.
+        let _ = self.rebalances.send(Rebalanced::Revoked(partitions));
+    }
+
+    fn post_rebalance(&self, _base_consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        if let Rebalance::Assign(assigned) = rebalance {
+            self.lab.metrics.inc_rebalances();
+            let partitions = self.partitions(assigned);
+            if !partitions.is_empty() {
+                let _ = self.rebalances.send(Rebalanced::Assigned(partitions));
+            }
+        }
+    }
+
+    fn commit_callback(&self, result: KafkaResult<()>, offsets: &TopicPartitionList) {
+        self.lab.commit_callback(result, offsets);
+    }
+}
+
+/// Partition count of `topic`, failing when it does not exist.
+pub fn partition_count<C: ClientContext>(client: &Client<C>, topic: &str) -> Result<usize> {
+    let metadata = client.fetch_metadata(Some(topic), METADATA_TIMEOUT)?;
+    match metadata.topics().iter().find(|t| t.name() == topic) {
+        Some(t) if t.error().is_none() && !t.partitions().is_empty() => Ok(t.partitions().len()),
+        _ => bail!("topic `{topic}` does not exist"),
+    }
+}
+
+/// Fails unless `a` and `b` have the same number of partitions, which is what
+/// lets partition N of one be processed together with partition N of the other.
+pub fn check_copartitioned<C: ClientContext>(
+    client: &Client<C>,
+    a: &str,
+    b: &str,
+) -> Result<usize> {
+    let (na, nb) = (partition_count(client, a)?, partition_count(client, b)?);
+    if na != nb {
+        bail!("`{a}` has {na} partitions but `{b}` has {nb}; they must be co-partitioned");
+    }
+    Ok(na)
+}
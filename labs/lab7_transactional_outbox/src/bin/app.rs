@@ -0,0 +1,213 @@
+use anyhow::{Result, bail};
+use lab7_transactional_outbox::{self as outbox, ORDER_ID_HEADER};
+use rdkafka::message::{Header, OwnedHeaders};
+use shared::config::AppConfig;
+use shared::create_producer_props;
+use shared::event::Event;
+use shared::input;
+use shared::logging::{self, LogFormat};
+use shared::record::create_future_record;
+use shared::shutdown::{self, Shutdown};
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+const ACTIONS: [&str; 3] = ["order", "pay", "refund"];
+
+/// How the order service tells Kafka about a new order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteMode {
+    /// Order row and outbox row in one SQLite transaction; the relay publishes.
+    Outbox,
+    /// Commit the order row, then produce the event: two writes, no atomicity.
+    DualWrite,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab7.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut db_path = PathBuf::from("lab7-outbox.db");
+    let mut generate: u64 = 0; // 0 reads orders from stdin
+    let mut mode = WriteMode::Outbox;
+    let mut crash_before_commit: u64 = 0; // 0 disables
+    let mut crash_after_commit: u64 = 0; // 0 disables
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--db" => {
+                if let Some(p) = args.next() {
+                    db_path = PathBuf::from(p);
+                }
+            }
+            "--generate" => {
+                if let Some(n) = args.next() {
+                    generate = n.parse().unwrap_or(0);
+                }
+            }
+            "--dual-write" => mode = WriteMode::DualWrite,
+            "--crash-before-commit" => {
+                if let Some(n) = args.next() {
+                    crash_before_commit = n.parse().unwrap_or(0);
+                }
+            }
+            "--crash-after-commit" => {
+                if let Some(n) = args.next() {
+                    crash_after_commit = n.parse().unwrap_or(0);
+                }
+            }
+            _ => {}
+        }
+    }
+    if crash_after_commit > 0 && mode != WriteMode::DualWrite {
+        bail!(
+            "--crash-after-commit needs --dual-write: with the outbox, the event commits with the order"
+        );
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+
+    let mut conn = outbox::open(&db_path)?;
+    // Only the dual write talks to Kafka; with the outbox, that is the relay's job.
+    let producer = match mode {
+        WriteMode::DualWrite => Some(create_producer_props(&[
+            ("bootstrap.servers", cfg.bootstrap_servers.clone()),
+            (
+                "message.timeout.ms",
+                cfg.message_timeout_ms.unwrap_or(5000).to_string(),
+            ),
+            ("enable.idempotence", "true".to_string()),
+        ])?),
+        WriteMode::Outbox => None,
+    };
+
+    info!(
+        db = %db_path.display(),
+        topic = %cfg.topic,
+        mode = ?mode,
+        generate,
+        crash_before_commit,
+        crash_after_commit,
+        "Order service (Lab 7) started"
+    );
+    if generate == 0 {
+        info!("Enter: user_id action value (e.g. u1 order 42). Ctrl+D to exit.");
+    }
+
+    let shutdown = Shutdown::listen();
+    let mut lines = input::stdin_lines();
+    let mut placed: u64 = 0;
+    let mut failed: u64 = 0;
+
+    loop {
+        let evt = if generate > 0 {
+            if placed >= generate || shutdown.is_requested() {
+                break;
+            }
+            Event {
+                user_id: format!("u{}", placed % 10),
+                action: ACTIONS[placed as usize % ACTIONS.len()].to_string(),
+                value: (placed % 100) as i64,
+            }
+        } else {
+            let line = tokio::select! {
+                line = lines.recv() => match line {
+                    Some(line) => line?,
+                    None => break,
+                },
+                _ = shutdown.requested() => break,
+            };
+            let parts: Vec<_> = line.split_whitespace().collect();
+            if parts.len() < 3 {
+                warn!(line = %line, "Format: user_id action value");
+                continue;
+            }
+            Event {
+                user_id: parts[0].to_string(),
+                action: parts[1].to_string(),
+                value: parts[2].parse().unwrap_or(0),
+            }
+        };
+        let payload = serde_json::to_vec(&evt)?;
+
+        let tx = conn.transaction()?;
+        let order_id = outbox::insert_order(&tx, &evt)?;
+        if mode == WriteMode::Outbox {
+            outbox::insert_outbox(&tx, order_id, &cfg.topic, &evt.user_id, &payload)?;
+        }
+        if crash_before_commit > 0 && placed + 1 >= crash_before_commit {
+            error!(
+                order_id,
+                placed, "💥 CRASHING BEFORE COMMIT: the order and its event roll back together"
+            );
+            std::process::exit(1);
+        }
+        tx.commit()?;
+        placed += 1;
+
+        let Some(producer) = &producer else {
+            info!(order_id, key = %evt.user_id, action = %evt.action, "🧾 Order placed, event in the outbox");
+            continue;
+        };
+        if crash_after_commit > 0 && placed >= crash_after_commit {
+            error!(
+                order_id,
+                placed, "💥 CRASHING AFTER COMMIT: the order is saved, its event is never sent"
+            );
+            std::process::exit(1);
+        }
+        let order_header = order_id.to_string();
+        let record =
+            create_future_record(Some(&evt.user_id), &payload, &cfg.topic, cfg.partitioning)?
+                .headers(OwnedHeaders::new().insert(Header {
+                    key: ORDER_ID_HEADER,
+                    value: Some(&order_header),
+                }));
+        match producer.send(record, Duration::from_secs(0)).await {
+            Ok(d) => info!(
+                order_id,
+                partition = d.partition,
+                offset = d.offset,
+                key = %evt.user_id,
+                "🧾 Order placed, event sent"
+            ),
+            Err((e, _)) => {
+                // The order is committed: nothing will ever publish its event.
+                failed += 1;
+                error!(order_id, error = %e, "❌ Order placed, event lost");
+            }
+        }
+    }
+
+    if let Some(producer) = &producer {
+        shutdown::flush_producer(producer, shutdown::FLUSH_TIMEOUT);
+    }
+    let counts = outbox::counts(&conn)?;
+    info!(
+        placed,
+        failed,
+        orders = counts.orders,
+        pending = counts.pending,
+        "👋 Order service stopped"
+    );
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use anyhow::{Result, bail};
+use lab7_transactional_outbox::{self as outbox, ORDER_ID_HEADER, OUTBOX_ID_HEADER};
+use rdkafka::message::{Header, Headers, Message, OwnedHeaders};
+use shared::config::{AppConfig, PartitioningMode};
+use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::mock::Brokers;
+use shared::pipeline::{DeliveryPipeline, Report};
+use shared::read_to_end::{self, ReadToEnd};
+use shared::record::create_future_record;
+use shared::shutdown::{self, Shutdown};
+use shared::{create_consumer_props, create_producer_props};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Upper bound on waiting for the deliveries of a batch; librdkafka gives up
+/// on each record after `message.timeout.ms` anyway.
+const BATCH_TIMEOUT: Duration = Duration::from_secs(30);
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const ACTIONS: [&str; 3] = ["order", "pay", "refund"];
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab7.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut db_path = PathBuf::from("lab7-outbox.db");
+    let mut batch: usize = 100;
+    let mut poll = Duration::from_millis(500);
+    let mut crash_after: u64 = 0; // 0 disables
+    let mut verify = false;
+    let mut mock = false;
+    let mut partitions: i32 = 3;
+    let mut messages: u64 = 100;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--db" => {
+                if let Some(p) = args.next() {
+                    db_path = PathBuf::from(p);
+                }
+            }
+            "--batch" => {
+                if let Some(n) = args.next() {
+                    batch = n.parse().unwrap_or(100).max(1);
+                }
+            }
+            "--poll-ms" => {
+                if let Some(ms) = args.next() {
+                    poll = Duration::from_millis(ms.parse().unwrap_or(500));
+                }
+            }
+            "--crash-after" => {
+                if let Some(n) = args.next() {
+                    crash_after = n.parse().unwrap_or(0);
+                }
+            }
+            "--verify" => verify = true,
+            "--mock" => mock = true,
+            "--messages" => {
+                if let Some(n) = args.next() {
+                    messages = n.parse().unwrap_or(100);
+                }
+            }
+            "--partitions" => {
+                if let Some(n) = args.next() {
+                    partitions = n.parse().unwrap_or(3);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+
+    let brokers = Brokers::new(mock, &cfg, partitions)?;
+    let bootstrap_servers = brokers.bootstrap_servers();
+    if mock {
+        // Rows published to a throwaway cluster must not be marked sent in
+        // the real outbox: the mock run gets its own database.
+        db_path = env::temp_dir().join(format!("lab7-outbox-mock-{}.db", std::process::id()));
+    }
+
+    let mut conn = outbox::open(&db_path)?;
+    if mock {
+        seed(&mut conn, &cfg.topic, messages)?;
+    }
+    if verify {
+        return check(&conn, &bootstrap_servers, &cfg.topic, false).await;
+    }
+
+    // Idempotence keeps librdkafka's own retries from duplicating a record;
+    // the relay resending after a crash is what the consumers must absorb.
+    let producer = create_producer_props(&[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        (
+            "message.timeout.ms",
+            cfg.message_timeout_ms.unwrap_or(5000).to_string(),
+        ),
+        ("linger.ms", cfg.linger_ms.unwrap_or(5).to_string()),
+        ("enable.idempotence", "true".to_string()),
+    ])?;
+
+    let counts = outbox::counts(&conn)?;
+    info!(
+        db = %db_path.display(),
+        pending = counts.pending,
+        batch,
+        poll_ms = poll.as_millis() as u64,
+        crash_after,
+        mock,
+        "Outbox relay (Lab 7) started"
+    );
+
+    let shutdown = Shutdown::listen();
+    let mut pipeline: DeliveryPipeline<i64> = DeliveryPipeline::new(batch);
+    let mut published: u64 = 0;
+    let mut failed: u64 = 0;
+
+    while !shutdown.is_requested() {
+        let rows = outbox::pending(&conn, batch)?;
+        if rows.is_empty() {
+            if mock {
+                break;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(poll) => continue,
+                _ = shutdown.requested() => break,
+            }
+        }
+
+        let mut reports = Vec::with_capacity(rows.len());
+        for row in &rows {
+            let order_id = row.order_id.to_string();
+            let outbox_id = row.id.to_string();
+            let headers = OwnedHeaders::new()
+                .insert(Header {
+                    key: ORDER_ID_HEADER,
+                    value: Some(&order_id),
+                })
+                .insert(Header {
+                    key: OUTBOX_ID_HEADER,
+                    value: Some(&outbox_id),
+                });
+            // Keyed like the application would, so an order's events stay in order.
+            let record = create_future_record(
+                Some(&row.key),
+                &row.payload,
+                &row.topic,
+                PartitioningMode::Keyed,
+            )?
+            .headers(headers);
+            reports.extend(pipeline.send(&producer, record, row.id).await);
+        }
+        reports.extend(pipeline.drain(BATCH_TIMEOUT).await);
+
+        // Rows are marked sent only up to the first failure, so the outbox
+        // never skips an event; the ones delivered after it are sent again.
+        let mut sent = Vec::with_capacity(reports.len());
+        let mut stalled = false;
+        for Report { tag: id, result } in reports {
+            match result {
+                Ok(_) if !stalled => sent.push(id),
+                Ok(_) => {}
+                Err(e) => {
+                    failed += 1;
+                    if !stalled {
+                        error!(outbox_id = id, error = %e, "❌ Delivery failed, retrying from this row");
+                    }
+                    stalled = true;
+                }
+            }
+        }
+        if pipeline.pending() > 0 {
+            stalled = true;
+        }
+        published += sent.len() as u64;
+        if crash_after > 0 && published >= crash_after {
+            error!(
+                published,
+                unmarked = sent.len(),
+                "💥 CRASHING AFTER DELIVERY, BEFORE MARKING: these rows will be published again"
+            );
+            std::process::exit(1);
+        }
+        outbox::mark_sent(&mut conn, &sent)?;
+        info!(
+            published = sent.len(),
+            first = sent.first().copied(),
+            last = sent.last().copied(),
+            "📤 Outbox rows published"
+        );
+        if stalled {
+            tokio::select! {
+                _ = tokio::time::sleep(poll) => {}
+                _ = shutdown.requested() => break,
+            }
+        }
+    }
+
+    let undelivered = shutdown::flush_producer(&producer, shutdown::FLUSH_TIMEOUT);
+    let counts = outbox::counts(&conn)?;
+    info!(
+        published,
+        failed,
+        undelivered,
+        pending = counts.pending,
+        "👋 Outbox relay stopped"
+    );
+
+    if mock {
+        check(&conn, &bootstrap_servers, &cfg.topic, true).await?;
+        drop(conn);
+        fs::remove_file(&db_path)?;
+    }
+    Ok(())
+}
+
+/// Places `count` generated orders with their outbox rows, as `app` does, in
+/// the database of a `--mock` run.
+fn seed(conn: &mut rusqlite::Connection, topic: &str, count: u64) -> Result<()> {
+    let tx = conn.transaction()?;
+    for i in 0..count {
+        let evt = Event {
+            user_id: format!("u{}", i % 10),
+            action: ACTIONS[i as usize % ACTIONS.len()].to_string(),
+            value: (i % 100) as i64,
+        };
+        let payload = serde_json::to_vec(&evt)?;
+        let order_id = outbox::insert_order(&tx, &evt)?;
+        outbox::insert_outbox(&tx, order_id, topic, &evt.user_id, &payload)?;
+    }
+    tx.commit()?;
+    info!(orders = count, "🧪 Mock outbox seeded");
+    Ok(())
+}
+
+/// Reads `topic` to its end and compares it with the database: every order
+/// must be on the topic or still waiting in the outbox.
+async fn check(
+    conn: &rusqlite::Connection,
+    bootstrap_servers: &str,
+    topic: &str,
+    strict: bool,
+) -> Result<()> {
+    let consumer = create_consumer_props(&[
+        ("bootstrap.servers", bootstrap_servers.to_string()),
+        ("group.id", "lab7-verify".to_string()),
+        ("enable.auto.commit", "false".to_string()),
+    ])?;
+    let partitions = read_to_end::partitions(&consumer, topic, VERIFY_TIMEOUT)?;
+    let ends = read_to_end::ends(&consumer, topic, &partitions, VERIFY_TIMEOUT)?;
+    let mut reader = ReadToEnd::from_beginning(&consumer, ends, VERIFY_TIMEOUT)?;
+
+    let mut copies: HashMap<i64, u64> = HashMap::new();
+    let mut records: u64 = 0;
+    while let Some(m) = reader.next().await? {
+        records += 1;
+        if let Some(order_id) = order_id(&m) {
+            *copies.entry(order_id).or_default() += 1;
+        }
+    }
+
+    let pending: HashSet<i64> = outbox::pending_order_ids(conn)?.into_iter().collect();
+    let orders = outbox::order_ids(conn)?;
+    let lost: Vec<i64> = orders
+        .iter()
+        .filter(|id| !copies.contains_key(id) && !pending.contains(id))
+        .copied()
+        .collect();
+    let duplicates: u64 = copies.values().map(|n| n - 1).sum();
+    info!(
+        topic,
+        records,
+        orders = orders.len(),
+        published = copies.len(),
+        pending = pending.len(),
+        duplicates,
+        lost = lost.len(),
+        "🔍 Topic checked against the orders"
+    );
+    if !lost.is_empty() {
+        warn!(
+            first = ?lost.iter().take(10).collect::<Vec<_>>(),
+            "🕳️ Orders saved without their event on the topic"
+        );
+        if strict {
+            bail!("{} orders were lost", lost.len());
+        }
+    }
+    Ok(())
+}
+
+fn order_id<M: Message>(m: &M) -> Option<i64> {
+    m.headers()?
+        .iter()
+        .find(|h| h.key == ORDER_ID_HEADER)
+        .and_then(|h| std::str::from_utf8(h.value?).ok()?.parse().ok())
+}
@@ -0,0 +1,151 @@
+//! Helpers shared by the lab 7 binaries: the SQLite database of the order
+//! service, with its `orders` table and the `outbox` of events to publish.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use rusqlite::{Connection, Transaction, params};
+use shared::event::Event;
+use shared::util::now_ms;
+
+/// Header carrying the id of the order an event is about, on every published
+/// record: the check matches records to orders with it.
+pub const ORDER_ID_HEADER: &str = "order-id";
+/// Header carrying the id of the outbox row a record was published from.
+pub const OUTBOX_ID_HEADER: &str = "outbox-id";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS orders (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id    TEXT    NOT NULL,
+    action     TEXT    NOT NULL,
+    value      INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS outbox (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_id   INTEGER NOT NULL REFERENCES orders(id),
+    topic      TEXT    NOT NULL,
+    key        TEXT    NOT NULL,
+    payload    BLOB    NOT NULL,
+    created_at INTEGER NOT NULL,
+    sent_at    INTEGER
+);
+CREATE INDEX IF NOT EXISTS outbox_pending ON outbox(id) WHERE sent_at IS NULL;
+";
+
+/// Opens the database at `path`, creating the tables when missing.
+///
+/// WAL mode lets the application write while the relay reads; the busy
+/// timeout makes one wait for the other's write lock instead of failing.
+pub fn open(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+/// Inserts the business row of an order; returns its id.
+pub fn insert_order(tx: &Transaction, evt: &Event) -> Result<i64> {
+    tx.execute(
+        "INSERT INTO orders (user_id, action, value, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![evt.user_id, evt.action, evt.value, now_ms()],
+    )?;
+    Ok(tx.last_insert_rowid())
+}
+
+/// Inserts the event to publish about order `order_id`; returns its id.
+pub fn insert_outbox(
+    tx: &Transaction,
+    order_id: i64,
+    topic: &str,
+    key: &str,
+    payload: &[u8],
+) -> Result<i64> {
+    tx.execute(
+        "INSERT INTO outbox (order_id, topic, key, payload, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![order_id, topic, key, payload, now_ms()],
+    )?;
+    Ok(tx.last_insert_rowid())
+}
+
+/// An outbox row not published yet.
+#[derive(Debug, Clone)]
+pub struct OutboxRow {
+    pub id: i64,
+    pub order_id: i64,
+    pub topic: String,
+    pub key: String,
+    pub payload: Vec<u8>,
+}
+
+/// The oldest `limit` unpublished outbox rows, in insertion order.
+pub fn pending(conn: &Connection, limit: usize) -> Result<Vec<OutboxRow>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT id, order_id, topic, key, payload FROM outbox
+         WHERE sent_at IS NULL ORDER BY id LIMIT ?1",
+    )?;
+    let rows = stmt
+        .query_map([limit as i64], |r| {
+            Ok(OutboxRow {
+                id: r.get(0)?,
+                order_id: r.get(1)?,
+                topic: r.get(2)?,
+                key: r.get(3)?,
+                payload: r.get(4)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
+
+/// Marks the outbox rows `ids` as published, in one transaction.
+pub fn mark_sent(conn: &mut Connection, ids: &[i64]) -> Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached("UPDATE outbox SET sent_at = ?1 WHERE id = ?2")?;
+        let now = now_ms();
+        for id in ids {
+            stmt.execute(params![now, id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Row counts of the database.
+#[derive(Debug, Clone, Copy)]
+pub struct Counts {
+    pub orders: u64,
+    pub outbox: u64,
+    pub pending: u64,
+}
+
+pub fn counts(conn: &Connection) -> Result<Counts> {
+    let count = |sql: &str| conn.query_row(sql, [], |r| r.get::<_, i64>(0).map(|n| n as u64));
+    Ok(Counts {
+        orders: count("SELECT COUNT(*) FROM orders")?,
+        outbox: count("SELECT COUNT(*) FROM outbox")?,
+        pending: count("SELECT COUNT(*) FROM outbox WHERE sent_at IS NULL")?,
+    })
+}
+
+/// Ids of the orders whose event is still waiting in the outbox.
+pub fn pending_order_ids(conn: &Connection) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare("SELECT order_id FROM outbox WHERE sent_at IS NULL")?;
+    let ids = stmt
+        .query_map([], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(ids)
+}
+
+/// Ids of every order.
+pub fn order_ids(conn: &Connection) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare("SELECT id FROM orders ORDER BY id")?;
+    let ids = stmt
+        .query_map([], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(ids)
+}
@@ -0,0 +1,314 @@
+use anyhow::{Result, bail};
+use lab8_request_reply::{MOCK_FETCH_WAIT_MS, Reply, Workload, respond, responder_consumer};
+use rdkafka::consumer::Consumer;
+use rdkafka::message::Message;
+use shared::config::AppConfig;
+use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::mock::Brokers;
+use shared::request_reply::{RequestError, Requester};
+use shared::shutdown::Shutdown;
+use shared::{create_consumer_props, create_producer_props};
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+
+/// How long the mock responders get to join their group.
+const ASSIGNMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab8.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut reply_topic = "lab8.replies".to_string();
+    let mut requests: u64 = 20;
+    let mut concurrency: usize = 4;
+    let mut timeout = Duration::from_secs(5);
+    let mut linger = Duration::from_secs(5);
+    let mut mock = false;
+    let mut partitions: i32 = 3;
+    let mut responders: usize = 2;
+    let mut workload = Workload {
+        delay: Duration::from_millis(50),
+        slow_every: 0,
+        slow: Duration::from_secs(3),
+    };
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--reply-topic" => {
+                if let Some(t) = args.next() {
+                    reply_topic = t;
+                }
+            }
+            "--requests" => {
+                if let Some(n) = args.next() {
+                    requests = n.parse().unwrap_or(20);
+                }
+            }
+            "--concurrency" => {
+                if let Some(n) = args.next() {
+                    concurrency = n.parse().unwrap_or(4).max(1);
+                }
+            }
+            "--timeout-ms" => {
+                if let Some(ms) = args.next() {
+                    timeout = Duration::from_millis(ms.parse().unwrap_or(5000));
+                }
+            }
+            "--linger-ms" => {
+                if let Some(ms) = args.next() {
+                    linger = Duration::from_millis(ms.parse().unwrap_or(5000));
+                }
+            }
+            "--mock" => mock = true,
+            "--partitions" => {
+                if let Some(n) = args.next() {
+                    partitions = n.parse().unwrap_or(3);
+                }
+            }
+            "--responders" => {
+                if let Some(n) = args.next() {
+                    responders = n.parse().unwrap_or(2);
+                }
+            }
+            "--delay-ms" => {
+                if let Some(ms) = args.next() {
+                    workload.delay = Duration::from_millis(ms.parse().unwrap_or(50));
+                }
+            }
+            "--slow-every" => {
+                if let Some(n) = args.next() {
+                    workload.slow_every = n.parse().unwrap_or(0);
+                }
+            }
+            "--slow-ms" => {
+                if let Some(ms) = args.next() {
+                    workload.slow = Duration::from_millis(ms.parse().unwrap_or(3000));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    let shutdown = Shutdown::listen();
+
+    let fetch_wait_ms = mock.then_some(MOCK_FETCH_WAIT_MS);
+    let brokers = Brokers::new(mock, &cfg, partitions)?;
+    if let Some(cluster) = brokers.mock() {
+        cluster.create_topic(&reply_topic, partitions, 1)?;
+    }
+    let bootstrap_servers = brokers.bootstrap_servers();
+
+    // Mock mode runs the responders in this process, all in one group.
+    let mut responder_tasks = JoinSet::new();
+    if mock {
+        let group_id = cfg
+            .group_id
+            .clone()
+            .unwrap_or("lab8-responders".to_string());
+        let mut consumers = Vec::new();
+        for i in 0..responders {
+            let consumer = Arc::new(responder_consumer(
+                &cfg,
+                &bootstrap_servers,
+                &group_id,
+                fetch_wait_ms,
+            )?);
+            let producer = create_producer_props(&[
+                ("bootstrap.servers", bootstrap_servers.clone()),
+                ("linger.ms", "0".to_string()),
+            ])?;
+            let shutdown = shutdown.clone();
+            consumers.push(Arc::clone(&consumer));
+            responder_tasks.spawn(async move {
+                let name = format!("responder-{i}");
+                respond(&consumer, &producer, &shutdown, &name, workload).await
+            });
+        }
+        wait_for_assignment(&consumers, partitions as usize).await?;
+        info!(responders, workload = ?workload, "🧪 Mock responders joined the group");
+    }
+
+    let producer = create_producer_props(&[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("linger.ms", "0".to_string()),
+    ])?;
+    // Assigned, not subscribed: the requester is not a group member.
+    let mut reply_props = vec![
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("group.id", "lab8-requester".to_string()),
+        ("enable.auto.commit", "false".to_string()),
+    ];
+    if let Some(ms) = fetch_wait_ms {
+        reply_props.push(("fetch.wait.max.ms", ms.to_string()));
+    }
+    let reply_consumer = create_consumer_props(&reply_props)?;
+    let requester = Arc::new(Requester::new(producer, reply_consumer, &reply_topic)?);
+
+    info!(
+        topic = %cfg.topic,
+        reply_topic,
+        requests,
+        concurrency,
+        timeout_ms = timeout.as_millis() as u64,
+        mock,
+        "Requester (Lab 8) started"
+    );
+
+    let started = Instant::now();
+    let mut in_flight = JoinSet::new();
+    let mut sent: u64 = 0;
+    let mut replied: u64 = 0;
+    let mut timeouts: u64 = 0;
+    let mut failed: u64 = 0;
+    let mut latencies = Vec::new();
+    let mut served_by: BTreeMap<String, u64> = BTreeMap::new();
+
+    loop {
+        if sent < requests && in_flight.len() < concurrency && !shutdown.is_requested() {
+            let evt = Event {
+                user_id: format!("u{}", sent % 10),
+                action: "square".to_string(),
+                value: sent as i64,
+            };
+            let payload = serde_json::to_vec(&evt)?;
+            let requester = Arc::clone(&requester);
+            let topic = cfg.topic.clone();
+            in_flight.spawn(async move {
+                let asked = Instant::now();
+                let reply = requester
+                    .request(&topic, Some(&evt.user_id), &payload, timeout)
+                    .await;
+                (evt, asked.elapsed(), reply)
+            });
+            sent += 1;
+            continue;
+        }
+        let Some(done) = in_flight.join_next().await else {
+            break;
+        };
+        let (evt, elapsed, reply) = done?;
+        let elapsed_ms = elapsed.as_millis() as u64;
+        match reply {
+            Ok(m) => {
+                replied += 1;
+                latencies.push(elapsed_ms);
+                match m
+                    .payload()
+                    .and_then(|p| serde_json::from_slice::<Reply>(p).ok())
+                {
+                    Some(r) => {
+                        *served_by.entry(r.served_by.clone()).or_default() += 1;
+                        info!(
+                            key = %evt.user_id,
+                            value = evt.value,
+                            result = ?r.result,
+                            served_by = %r.served_by,
+                            partition = r.partition,
+                            elapsed_ms,
+                            "✅ Reply"
+                        );
+                    }
+                    None => warn!(key = %evt.user_id, elapsed_ms, "Reply is not a lab 8 reply"),
+                }
+            }
+            Err(RequestError::Timeout(t)) => {
+                timeouts += 1;
+                warn!(key = %evt.user_id, value = evt.value, timeout_ms = t.as_millis() as u64, "⏱️ TIMEOUT");
+            }
+            Err(e) => {
+                failed += 1;
+                error!(key = %evt.user_id, value = evt.value, error = %e, "❌ Request failed");
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+
+    // Requests that timed out may still be answered: wait for those replies.
+    if timeouts > 0 && !linger.is_zero() {
+        info!(
+            linger_ms = linger.as_millis() as u64,
+            "⏳ Waiting for late replies"
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(linger) => {}
+            _ = shutdown.requested() => {}
+        }
+    }
+
+    latencies.sort_unstable();
+    let percentile = |q: f64| {
+        latencies
+            .get(((latencies.len() as f64 * q).ceil() as usize).saturating_sub(1))
+            .copied()
+    };
+    let stats = requester.stats();
+    info!(
+        sent,
+        replied,
+        timeouts,
+        failed,
+        late = stats.late,
+        p50_ms = percentile(0.5),
+        p99_ms = percentile(0.99),
+        max_ms = latencies.last().copied(),
+        elapsed_ms = elapsed.as_millis() as u64,
+        per_sec = (replied as f64 / elapsed.as_secs_f64()).round(),
+        served_by = ?served_by,
+        "🏁 Requester stopped"
+    );
+    // Stop every client before the mock cluster goes away with main.
+    requester.close().await;
+    responder_tasks.abort_all();
+    while responder_tasks.join_next().await.is_some() {}
+    Ok(())
+}
+
+/// Waits until the responders share all `partitions` of the request topic,
+/// each one owning at least one when there are enough partitions: with the
+/// cooperative assignor, the first member to join owns everything for a while.
+async fn wait_for_assignment<C: Consumer<X>, X: rdkafka::consumer::ConsumerContext>(
+    consumers: &[Arc<C>],
+    partitions: usize,
+) -> Result<()> {
+    let started = Instant::now();
+    loop {
+        let mut assigned = 0;
+        let mut owners = 0;
+        for c in consumers {
+            let count = c.assignment()?.count();
+            assigned += count;
+            owners += usize::from(count > 0);
+        }
+        if assigned >= partitions && owners >= consumers.len().min(partitions) {
+            return Ok(());
+        }
+        if started.elapsed() > ASSIGNMENT_TIMEOUT {
+            bail!("the mock responders got {assigned} of {partitions} partitions");
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::Result;
+use lab8_request_reply::{Workload, respond, responder_consumer};
+use shared::config::AppConfig;
+use shared::create_producer_props;
+use shared::logging::{self, LogFormat};
+use shared::shutdown::{self, Shutdown};
+use std::env;
+use std::time::Duration;
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab8.default".to_string();
+    let mut group_override: Option<String> = None;
+    let mut log_format: Option<LogFormat> = None;
+    let mut instance = format!("responder-{}", std::process::id());
+    let mut workload = Workload {
+        delay: Duration::from_millis(50),
+        slow_every: 0,
+        slow: Duration::from_secs(3),
+    };
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--group-id" => {
+                if let Some(g) = args.next() {
+                    group_override = Some(g);
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--instance" => {
+                if let Some(i) = args.next() {
+                    instance = i;
+                }
+            }
+            "--delay-ms" => {
+                if let Some(ms) = args.next() {
+                    workload.delay = Duration::from_millis(ms.parse().unwrap_or(50));
+                }
+            }
+            "--slow-every" => {
+                if let Some(n) = args.next() {
+                    workload.slow_every = n.parse().unwrap_or(0);
+                }
+            }
+            "--slow-ms" => {
+                if let Some(ms) = args.next() {
+                    workload.slow = Duration::from_millis(ms.parse().unwrap_or(3000));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    let group_id = group_override
+        .or(cfg.group_id.clone())
+        .unwrap_or("lab8-responders".to_string());
+
+    let consumer = responder_consumer(&cfg, &cfg.bootstrap_servers, &group_id, None)?;
+    let producer = create_producer_props(&[
+        ("bootstrap.servers", cfg.bootstrap_servers.clone()),
+        ("linger.ms", "0".to_string()),
+    ])?;
+
+    info!(
+        instance,
+        group = group_id,
+        topic = %cfg.topic,
+        workload = ?workload,
+        "Responder (Lab 8) started"
+    );
+
+    let shutdown = Shutdown::listen();
+    let stats = respond(&consumer, &producer, &shutdown, &instance, workload).await?;
+    info!(
+        instance,
+        replied = stats.replied,
+        failed = stats.failed,
+        invalid = stats.invalid,
+        "🏁 Responder stopped"
+    );
+    shutdown::flush_producer(&producer, shutdown::FLUSH_TIMEOUT);
+    shutdown::close_consumer(&consumer, &group_id);
+    Ok(())
+}
@@ -0,0 +1,128 @@
+//! Helpers shared by the lab 8 binaries.
+
+use std::time::Duration;
+
+use rdkafka::client::ClientContext;
+use rdkafka::consumer::{Consumer, ConsumerContext, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::producer::FutureProducer;
+use serde::{Deserialize, Serialize};
+use shared::config::AppConfig;
+use shared::context::LabContext;
+use shared::create_consumer_props;
+use shared::event::Event;
+use shared::request_reply::{self, Request, RequestError, ServeStats};
+use shared::shutdown::Shutdown;
+use tracing::info;
+
+/// Answer to an [`Event`] request: its value squared, and who computed it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reply {
+    pub user_id: String,
+    pub value: i64,
+    /// `None` when the request was not an `Event`.
+    pub result: Option<i64>,
+    pub served_by: String,
+    /// Request partition, i.e. which responder of the group owned it.
+    pub partition: i32,
+}
+
+/// How long a responder takes per request.
+#[derive(Debug, Clone, Copy)]
+pub struct Workload {
+    pub delay: Duration,
+    /// Every Nth request takes `slow` instead; 0 disables.
+    pub slow_every: u64,
+    pub slow: Duration,
+}
+
+impl Workload {
+    fn delay(&self, n: u64) -> Duration {
+        if self.slow_every > 0 && n.is_multiple_of(self.slow_every) {
+            self.slow
+        } else {
+            self.delay
+        }
+    }
+}
+
+/// `fetch.wait.max.ms` against the mock cluster. A broker answers a pending
+/// fetch as soon as a record arrives; the mock only after the full wait, which
+/// would add up to 500 ms (the default) to each request and each reply.
+pub const MOCK_FETCH_WAIT_MS: u64 = 10;
+
+/// Consumer of a responder: a member of `group_id`, subscribed to the request
+/// topic. Offsets are stored once a request is answered.
+pub fn responder_consumer(
+    cfg: &AppConfig,
+    bootstrap_servers: &str,
+    group_id: &str,
+    fetch_wait_ms: Option<u64>,
+) -> anyhow::Result<StreamConsumer<LabContext>> {
+    let mut props = vec![
+        ("bootstrap.servers", bootstrap_servers.to_string()),
+        ("group.id", group_id.to_string()),
+        ("enable.auto.commit", cfg.enable_auto_commit.to_string()),
+        ("enable.auto.offset.store", "false".to_string()),
+        ("auto.offset.reset", cfg.auto_offset_reset.clone()),
+        (
+            "partition.assignment.strategy",
+            "cooperative-sticky".to_string(),
+        ),
+    ];
+    if let Some(ms) = fetch_wait_ms {
+        props.push(("fetch.wait.max.ms", ms.to_string()));
+    }
+    let consumer = create_consumer_props(&props)?;
+    consumer.subscribe(&[&cfg.topic])?;
+    Ok(consumer)
+}
+
+/// Serves requests with [`request_reply::serve`] until shutdown, replying
+/// after the delay of `workload`.
+pub async fn respond<X, C>(
+    consumer: &StreamConsumer<X>,
+    producer: &FutureProducer<C>,
+    shutdown: &Shutdown,
+    served_by: &str,
+    workload: Workload,
+) -> Result<ServeStats, RequestError>
+where
+    X: ConsumerContext + 'static,
+    C: ClientContext + 'static,
+{
+    let mut handled: u64 = 0;
+    request_reply::serve(consumer, producer, shutdown, |request: &Request| {
+        handled += 1;
+        let m = &request.message;
+        let event = m
+            .payload()
+            .and_then(|p| serde_json::from_slice::<Event>(p).ok());
+        let reply = Reply {
+            user_id: event
+                .as_ref()
+                .map(|e| e.user_id.clone())
+                .unwrap_or_default(),
+            value: event.as_ref().map_or(0, |e| e.value),
+            result: event.as_ref().map(|e| e.value * e.value),
+            served_by: served_by.to_string(),
+            partition: m.partition(),
+        };
+        let delay = workload.delay(handled);
+        info!(
+            served_by,
+            partition = m.partition(),
+            offset = m.offset(),
+            correlation_id = %request.correlation_id,
+            reply_to = %request.reply_to,
+            delay_ms = delay.as_millis() as u64,
+            "📨 Request"
+        );
+        let payload = serde_json::to_vec(&reply).unwrap_or_default();
+        async move {
+            tokio::time::sleep(delay).await;
+            payload
+        }
+    })
+    .await
+}
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use lab9_delayed_delivery::{Plan, schedule_events};
+use shared::config::AppConfig;
+use shared::create_producer_props;
+use shared::logging::{self, LogFormat};
+use shared::shutdown;
+use shared::util::parse_duration;
+use std::env;
+use std::time::Duration;
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab9.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut plan = Plan {
+        count: 20,
+        delay: Duration::from_secs(10),
+        spread: Duration::ZERO,
+        urgent_every: 0,
+    };
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--count" => {
+                if let Some(n) = args.next() {
+                    plan.count = n.parse().unwrap_or(20);
+                }
+            }
+            "--delay" => {
+                if let Some(d) = args.next() {
+                    plan.delay = parse_duration(&d)?;
+                }
+            }
+            "--spread" => {
+                if let Some(d) = args.next() {
+                    plan.spread = parse_duration(&d)?;
+                }
+            }
+            "--urgent-every" => {
+                if let Some(n) = args.next() {
+                    plan.urgent_every = n.parse().unwrap_or(0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+
+    let producer = create_producer_props(&[
+        ("bootstrap.servers", cfg.bootstrap_servers.clone()),
+        ("linger.ms", cfg.linger_ms.unwrap_or(5).to_string()),
+        ("enable.idempotence", "true".to_string()),
+    ])?;
+    info!(topic = %cfg.topic, plan = ?plan, "Delayed producer (Lab 9) started");
+
+    let scheduled = schedule_events(&producer, &cfg.topic, plan).await?;
+    shutdown::flush_producer(&producer, shutdown::FLUSH_TIMEOUT);
+    info!(scheduled, "👋 Delayed producer stopped");
+    Ok(())
+}
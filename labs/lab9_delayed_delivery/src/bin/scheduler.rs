@@ -0,0 +1,444 @@
+use anyhow::{Result, bail};
+use lab9_delayed_delivery::{Plan, schedule_events};
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::producer::FutureProducer;
+use rdkafka::{Offset, TopicPartitionList};
+use shared::config::AppConfig;
+use shared::context::LabContext;
+use shared::logging::{self, LogFormat};
+use shared::mock::Brokers;
+use shared::pipeline::{DeliveryPipeline, Report};
+use shared::read_to_end::{self, ReadToEnd};
+use shared::scheduler::{
+    DELIVER_AT_HEADER, DelayQueue, Held, SCHEDULED_FROM_HEADER, ScheduleError,
+};
+use shared::shutdown::{self, Shutdown};
+use shared::state::{DiskStore, MemoryStore, Offsets, StateStore};
+use shared::util::{self, parse_duration};
+use shared::{create_consumer_props, create_producer_props};
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// How often due records are released, and held ones persisted.
+const TICK: Duration = Duration::from_millis(100);
+/// Records held before they are persisted, without waiting for the tick.
+const PERSIST_EVERY: usize = 100;
+/// Upper bound on waiting for the deliveries of a release.
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(30);
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+const SEEK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the scheduler keeps the records it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StoreCli {
+    Memory,
+    Disk,
+}
+
+type Queue = DelayQueue<Box<dyn StateStore>>;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "lab9.default".to_string();
+    let mut group_override: Option<String> = None;
+    let mut log_format: Option<LogFormat> = None;
+    let mut target = "lab9.due".to_string();
+    let mut store_kind = StoreCli::Disk;
+    let mut store_path = PathBuf::from("lab9-scheduler.redb");
+    let mut bucket = Duration::from_secs(1);
+    let mut batch: usize = 500;
+    let mut crash_after: u64 = 0; // 0 disables
+    let mut verify = false;
+    let mut mock = false;
+    let mut partitions: i32 = 3;
+    let mut plan = Plan {
+        count: 100,
+        delay: Duration::from_secs(5),
+        spread: Duration::from_secs(5),
+        urgent_every: 10,
+    };
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--group-id" => {
+                if let Some(g) = args.next() {
+                    group_override = Some(g);
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--target" => {
+                if let Some(t) = args.next() {
+                    target = t;
+                }
+            }
+            "--store" => match args.next().as_deref() {
+                Some("memory") => store_kind = StoreCli::Memory,
+                Some("disk") => store_kind = StoreCli::Disk,
+                other => bail!("--store expects `memory` or `disk`, got {other:?}"),
+            },
+            "--path" => {
+                if let Some(p) = args.next() {
+                    store_path = PathBuf::from(p);
+                }
+            }
+            "--bucket" => {
+                if let Some(d) = args.next() {
+                    bucket = parse_duration(&d)?;
+                }
+            }
+            "--batch" => {
+                if let Some(n) = args.next() {
+                    batch = n.parse().unwrap_or(500).max(1);
+                }
+            }
+            "--crash-after" => {
+                if let Some(n) = args.next() {
+                    crash_after = n.parse().unwrap_or(0);
+                }
+            }
+            "--verify" => verify = true,
+            "--mock" => mock = true,
+            "--partitions" => {
+                if let Some(n) = args.next() {
+                    partitions = n.parse().unwrap_or(3);
+                }
+            }
+            "--count" => {
+                if let Some(n) = args.next() {
+                    plan.count = n.parse().unwrap_or(100);
+                }
+            }
+            "--delay" => {
+                if let Some(d) = args.next() {
+                    plan.delay = parse_duration(&d)?;
+                }
+            }
+            "--spread" => {
+                if let Some(d) = args.next() {
+                    plan.spread = parse_duration(&d)?;
+                }
+            }
+            "--urgent-every" => {
+                if let Some(n) = args.next() {
+                    plan.urgent_every = n.parse().unwrap_or(0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    let group_id = group_override
+        .or(cfg.group_id.clone())
+        .unwrap_or("lab9-scheduler".to_string());
+
+    let brokers = Brokers::new(mock, &cfg, partitions)?;
+    let bootstrap_servers = brokers.bootstrap_servers();
+    if let Some(cluster) = brokers.mock() {
+        cluster.create_topic(&target, partitions, 1)?;
+        // What an earlier mock run held would not match this cluster.
+        store_path =
+            env::temp_dir().join(format!("lab9-scheduler-mock-{}.redb", std::process::id()));
+    }
+
+    if verify {
+        return check(&bootstrap_servers, &target, None).await;
+    }
+
+    let producer = create_producer_props(&[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("linger.ms", cfg.linger_ms.unwrap_or(5).to_string()),
+        ("enable.idempotence", "true".to_string()),
+    ])?;
+    if mock {
+        schedule_events(&producer, &cfg.topic, plan).await?;
+    }
+
+    let store: Box<dyn StateStore> = match store_kind {
+        StoreCli::Memory => Box::new(MemoryStore::new()),
+        StoreCli::Disk => Box::new(DiskStore::open(&store_path)?),
+    };
+    let mut queue = DelayQueue::new(store, bucket)?;
+
+    // Offsets are committed once the records they cover are in a durable
+    // store, and never with `--store memory`.
+    let consumer = create_consumer_props(&[
+        ("bootstrap.servers", bootstrap_servers.clone()),
+        ("group.id", group_id.clone()),
+        ("enable.auto.commit", "false".to_string()),
+        ("auto.offset.reset", cfg.auto_offset_reset.clone()),
+    ])?;
+    let durable = store_kind == StoreCli::Disk;
+    consumer.subscribe(&[&cfg.topic])?;
+
+    info!(
+        topic = %cfg.topic,
+        target,
+        group = group_id,
+        store = ?store_kind,
+        path = %store_path.display(),
+        bucket_ms = bucket.as_millis() as u64,
+        held = queue.len(),
+        crash_after,
+        mock,
+        "Scheduler (Lab 9) started"
+    );
+    // A crash between a checkpoint and its commit leaves the group behind the
+    // store: the first record read from such a partition seeks past what is
+    // already held.
+    let mut resume = if durable {
+        queue.checkpointed()?
+    } else {
+        Offsets::new()
+    };
+    if !durable {
+        warn!(
+            topic = %cfg.topic,
+            "In-memory store: offsets are not committed, a restart reads the topic again from auto.offset.reset"
+        );
+    }
+
+    let shutdown = Shutdown::listen();
+    let mut tick = tokio::time::interval(TICK);
+    let mut pipeline: DeliveryPipeline<usize> = DeliveryPipeline::new(batch);
+    let mut unsaved = Offsets::new();
+    let mut unsaved_records: usize = 0;
+    let (mut held, mut released, mut failed) = (0u64, 0u64, 0u64);
+
+    loop {
+        tokio::select! {
+            msg = consumer.recv() => {
+                let m = match msg {
+                    Ok(m) => m,
+                    Err(e) => {
+                        warn!(group = group_id, error = %e, "Read error");
+                        continue;
+                    }
+                };
+                let (partition, offset) = (m.partition(), m.offset());
+                if let Some(next) = resume.remove(&(cfg.topic.clone(), partition))
+                    && offset < next
+                {
+                    info!(partition, from = offset, to = next, "♻️ Skipping records already in the store");
+                    consumer.seek(&cfg.topic, partition, Offset::Offset(next), SEEK_TIMEOUT)?;
+                    continue;
+                }
+                let now = util::now_ms();
+                let record = Held::from_message(&m, now);
+                queue.hold(&record)?;
+                unsaved.insert((record.topic.clone(), record.partition), record.offset + 1);
+                held += 1;
+                unsaved_records += 1;
+                info!(
+                    key = %String::from_utf8_lossy(m.key().unwrap_or_default()),
+                    partition = record.partition,
+                    offset = record.offset,
+                    due_in_ms = record.deliver_at - now,
+                    priority = record.priority,
+                    "📥 HELD"
+                );
+                if unsaved_records >= PERSIST_EVERY {
+                    persist(&mut queue, &consumer, &mut unsaved, durable)?;
+                    unsaved_records = 0;
+                }
+            }
+            _ = tick.tick() => {
+                persist(&mut queue, &consumer, &mut unsaved, durable)?;
+                unsaved_records = 0;
+                let (ok, ko) = release(&mut queue, &producer, &mut pipeline, &target, batch, released, crash_after).await?;
+                released += ok;
+                failed += ko;
+                if ok > 0 {
+                    persist(&mut queue, &consumer, &mut unsaved, durable)?;
+                }
+                if mock && held >= plan.count && queue.is_empty() {
+                    break;
+                }
+            }
+            _ = shutdown.requested() => break,
+        }
+    }
+
+    persist(&mut queue, &consumer, &mut unsaved, durable)?;
+    let undelivered = shutdown::flush_producer(&producer, shutdown::FLUSH_TIMEOUT);
+    info!(
+        held,
+        released,
+        failed,
+        undelivered,
+        still_held = queue.len(),
+        "🏁 Scheduler stopped"
+    );
+    shutdown::close_consumer(&consumer, &group_id);
+
+    if mock {
+        let checked = check(&bootstrap_servers, &target, Some(plan.count)).await;
+        drop(queue);
+        if durable {
+            std::fs::remove_file(&store_path)?;
+        }
+        checked?;
+    }
+    Ok(())
+}
+
+/// Makes the held records durable, then commits the offsets after them: a
+/// record is never committed before it is safe in the store. A store that is
+/// not `durable` loses its records on a restart, so nothing is committed.
+fn persist(
+    queue: &mut Queue,
+    consumer: &StreamConsumer<LabContext>,
+    unsaved: &mut Offsets,
+    durable: bool,
+) -> Result<()> {
+    queue.checkpoint(unsaved)?;
+    if !durable {
+        unsaved.clear();
+    }
+    if unsaved.is_empty() {
+        return Ok(());
+    }
+    let mut tpl = TopicPartitionList::new();
+    for ((topic, partition), next) in unsaved.iter() {
+        tpl.add_partition_offset(topic, *partition, Offset::Offset(*next))?;
+    }
+    // A failed commit only means reading these records again: holding one
+    // twice keeps a single copy.
+    if let Err(e) = consumer.commit(&tpl, CommitMode::Sync) {
+        warn!(error = %e, "Commit failed, these records will be read again");
+    }
+    unsaved.clear();
+    Ok(())
+}
+
+/// Republishes up to `batch` due records to `target` and forgets the ones
+/// delivered; returns how many were delivered and how many failed.
+async fn release(
+    queue: &mut Queue,
+    producer: &FutureProducer<LabContext>,
+    pipeline: &mut DeliveryPipeline<usize>,
+    target: &str,
+    batch: usize,
+    released_before: u64,
+    crash_after: u64,
+) -> Result<(u64, u64), ScheduleError> {
+    let now = util::now_ms();
+    let due = queue.due(now, batch)?;
+    if due.is_empty() {
+        return Ok((0, 0));
+    }
+    let mut reports = Vec::with_capacity(due.len());
+    for (i, (_, record)) in due.iter().enumerate() {
+        reports.extend(pipeline.send(producer, record.record(target), i).await);
+    }
+    reports.extend(pipeline.drain(RELEASE_TIMEOUT).await);
+
+    // Failed records stay held and are released again on the next tick.
+    let mut delivered = Vec::with_capacity(reports.len());
+    let mut failed = 0;
+    for Report { tag: i, result } in reports {
+        let (key, record) = &due[i];
+        match result {
+            Ok(d) => {
+                info!(
+                    source = %record.source(),
+                    partition = d.partition,
+                    offset = d.offset,
+                    late_ms = now - record.deliver_at,
+                    priority = record.priority,
+                    "📤 RELEASED"
+                );
+                delivered.push(key.clone());
+            }
+            Err(e) => {
+                failed += 1;
+                error!(source = %record.source(), error = %e, "❌ Release failed, kept for the next tick");
+            }
+        }
+    }
+    let released = released_before + delivered.len() as u64;
+    if crash_after > 0 && released >= crash_after {
+        error!(
+            released,
+            unforgotten = delivered.len(),
+            "💥 CRASHING AFTER RELEASE, BEFORE FORGETTING: these records will be released again"
+        );
+        std::process::exit(1);
+    }
+    queue.release(&delivered)?;
+    Ok((delivered.len() as u64, failed))
+}
+
+/// Reads `target` to its end and checks every record against its `deliver-at`
+/// header: none may be early, and each source record appears once.
+async fn check(bootstrap_servers: &str, target: &str, expected: Option<u64>) -> Result<()> {
+    let consumer = create_consumer_props(&[
+        ("bootstrap.servers", bootstrap_servers.to_string()),
+        ("group.id", "lab9-verify".to_string()),
+        ("enable.auto.commit", "false".to_string()),
+    ])?;
+    let partitions = read_to_end::partitions(&consumer, target, VERIFY_TIMEOUT)?;
+    let ends = read_to_end::ends(&consumer, target, &partitions, VERIFY_TIMEOUT)?;
+    let mut reader = ReadToEnd::from_beginning(&consumer, ends, VERIFY_TIMEOUT)?;
+
+    let mut copies: HashMap<String, u64> = HashMap::new();
+    let (mut records, mut early) = (0u64, 0u64);
+    let mut lateness = Vec::new();
+    while let Some(m) = reader.next().await? {
+        records += 1;
+        let header = |name| util::header(&m, name);
+        if let Some(source) = header(SCHEDULED_FROM_HEADER) {
+            *copies.entry(source).or_default() += 1;
+        }
+        let deliver_at = header(DELIVER_AT_HEADER).and_then(|v| v.parse::<i64>().ok());
+        if let (Some(at), Some(ts)) = (deliver_at, m.timestamp().to_millis()) {
+            if ts < at {
+                early += 1;
+            }
+            lateness.push(ts - at);
+        }
+    }
+
+    lateness.sort_unstable();
+    let duplicates: u64 = copies.values().map(|n| n - 1).sum();
+    info!(
+        target,
+        records,
+        released = copies.len(),
+        duplicates,
+        early,
+        p50_late_ms = lateness.get(lateness.len() / 2).copied(),
+        max_late_ms = lateness.last().copied(),
+        "🔍 Target checked against the deliver-at times"
+    );
+    if early > 0 {
+        bail!("{early} records were released early");
+    }
+    if let Some(expected) = expected
+        && (copies.len() as u64) < expected
+    {
+        bail!("{} of {expected} records were released", copies.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,71 @@
+//! Helpers shared by the lab 9 binaries.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use rdkafka::client::ClientContext;
+use rdkafka::producer::FutureProducer;
+use shared::config::PartitioningMode;
+use shared::event::Event;
+use shared::record::create_future_record;
+use shared::scheduler::schedule_headers;
+use shared::util::now_ms;
+use tracing::info;
+
+/// Priority of the urgent events of a [`Plan`]; the others have 0.
+pub const URGENT_PRIORITY: u8 = 9;
+
+/// Which events to schedule, and when they are due.
+#[derive(Debug, Clone, Copy)]
+pub struct Plan {
+    pub count: u64,
+    /// Delay of the first event.
+    pub delay: Duration,
+    /// The due times of the events are spread evenly over this much after
+    /// `delay`; 0 makes them all due at once.
+    pub spread: Duration,
+    /// Every Nth event is urgent; 0 disables.
+    pub urgent_every: u64,
+}
+
+/// Produces the events of `plan` to `topic`, each with the `deliver-at` and
+/// `priority` headers; returns how many were delivered.
+pub async fn schedule_events<C: ClientContext + 'static>(
+    producer: &FutureProducer<C>,
+    topic: &str,
+    plan: Plan,
+) -> Result<u64> {
+    let start = now_ms() + plan.delay.as_millis() as i64;
+    let spread = plan.spread.as_millis() as i64;
+    let mut scheduled = 0;
+    for i in 0..plan.count {
+        let deliver_at = start + spread * i as i64 / plan.count.max(1) as i64;
+        let urgent = plan.urgent_every > 0 && (i + 1).is_multiple_of(plan.urgent_every);
+        let priority = if urgent { URGENT_PRIORITY } else { 0 };
+        let evt = Event {
+            user_id: format!("u{}", i % 10),
+            action: if urgent { "remind-urgent" } else { "remind" }.to_string(),
+            value: i as i64,
+        };
+        let payload = serde_json::to_vec(&evt)?;
+        let record =
+            create_future_record(Some(&evt.user_id), &payload, topic, PartitioningMode::Keyed)?
+                .headers(schedule_headers(deliver_at, priority));
+        let (partition, offset) = producer
+            .send(record, Duration::from_secs(5))
+            .await
+            .map(|d| (d.partition, d.offset))
+            .map_err(|(e, _)| e)?;
+        scheduled += 1;
+        info!(
+            key = %evt.user_id,
+            value = evt.value,
+            partition,
+            offset,
+            due_in_ms = deliver_at - now_ms(),
+            priority,
+            "🕒 SCHEDULED"
+        );
+    }
+    Ok(scheduled)
+}
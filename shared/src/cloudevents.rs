@@ -0,0 +1,213 @@
+use chrono::{DateTime, Utc};
+use rdkafka::message::{Header, Headers, Message, OwnedHeaders};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::event::Event;
+
+pub const SPEC_VERSION: &str = "1.0";
+pub const EVENT_TYPE: &str = "dev.kafka_fundamentals.event";
+pub const DEFAULT_SOURCE: &str = "/kafka_fundamentals/producer";
+
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+pub const STRUCTURED_CONTENT_TYPE: &str = "application/cloudevents+json; charset=UTF-8";
+pub const DATA_CONTENT_TYPE: &str = "application/json";
+
+const CE_PREFIX: &str = "ce_";
+
+/// Content mode of the CloudEvents Kafka protocol binding.
+///
+/// - `Structured`: the whole event (attributes + data) is a JSON envelope in the payload.
+/// - `Binary`: the payload is the bare `Event`; attributes travel as `ce_*` headers.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CloudEventsMode {
+    Structured,
+    Binary,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CloudEventError {
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("missing required attribute '{0}'")]
+    MissingAttribute(&'static str),
+    #[error("unsupported specversion '{0}'")]
+    UnsupportedSpecVersion(String),
+    #[error("invalid time attribute '{0}'")]
+    InvalidTime(String),
+    #[error("message has no payload")]
+    EmptyPayload,
+}
+
+/// The context attributes we set on every produced event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CloudEventAttributes {
+    pub id: String,
+    pub source: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+}
+
+impl CloudEventAttributes {
+    /// Fresh attributes for `event`: random id, current time, `user_id` as subject.
+    pub fn for_event(source: &str, event: &Event) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            source: source.to_string(),
+            ty: EVENT_TYPE.to_string(),
+            time: Some(Utc::now()),
+            subject: Some(event.user_id.clone()),
+        }
+    }
+}
+
+/// JSON envelope used by structured mode.
+#[derive(Debug, Serialize, Deserialize)]
+struct StructuredEnvelope<T> {
+    specversion: String,
+    #[serde(flatten)]
+    attributes: CloudEventAttributes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    datacontenttype: Option<String>,
+    data: T,
+}
+
+/// Payload and headers ready to be attached to a `FutureRecord`.
+#[derive(Debug, Clone)]
+pub struct EncodedCloudEvent {
+    pub payload: Vec<u8>,
+    pub headers: OwnedHeaders,
+}
+
+pub fn encode(
+    mode: CloudEventsMode,
+    attributes: &CloudEventAttributes,
+    event: &Event,
+) -> Result<EncodedCloudEvent, CloudEventError> {
+    match mode {
+        CloudEventsMode::Structured => {
+            let envelope = StructuredEnvelope {
+                specversion: SPEC_VERSION.to_string(),
+                attributes: attributes.clone(),
+                datacontenttype: Some(DATA_CONTENT_TYPE.to_string()),
+                data: event,
+            };
+            let headers =
+                OwnedHeaders::new().insert(header(CONTENT_TYPE_HEADER, STRUCTURED_CONTENT_TYPE));
+            Ok(EncodedCloudEvent {
+                payload: serde_json::to_vec(&envelope)?,
+                headers,
+            })
+        }
+        CloudEventsMode::Binary => {
+            let time = attributes.time.map(|t| t.to_rfc3339());
+            let mut headers = OwnedHeaders::new()
+                .insert(header(CONTENT_TYPE_HEADER, DATA_CONTENT_TYPE))
+                .insert(header("ce_specversion", SPEC_VERSION))
+                .insert(header("ce_id", &attributes.id))
+                .insert(header("ce_source", &attributes.source))
+                .insert(header("ce_type", &attributes.ty));
+            if let Some(t) = &time {
+                headers = headers.insert(header("ce_time", t));
+            }
+            if let Some(s) = &attributes.subject {
+                headers = headers.insert(header("ce_subject", s));
+            }
+            Ok(EncodedCloudEvent {
+                payload: serde_json::to_vec(event)?,
+                headers,
+            })
+        }
+    }
+}
+
+fn header<'a>(key: &'a str, value: &'a str) -> Header<'a, &'a str> {
+    Header {
+        key,
+        value: Some(value),
+    }
+}
+
+/// An `Event` read from Kafka, with its CloudEvents attributes when it carried any.
+#[derive(Debug, Clone)]
+pub struct DecodedEvent {
+    pub mode: Option<CloudEventsMode>,
+    pub attributes: Option<CloudEventAttributes>,
+    pub event: Event,
+}
+
+/// Decodes a record produced in structured mode, binary mode or as a plain `Event`.
+///
+/// The mode is detected per record: a `content-type: application/cloudevents...` header
+/// means structured, a `ce_specversion` header means binary, anything else is plain JSON.
+pub fn decode<M: Message>(m: &M) -> Result<DecodedEvent, CloudEventError> {
+    let payload = m.payload().ok_or(CloudEventError::EmptyPayload)?;
+    let headers = m.headers();
+    let header_value = |name: &str| -> Option<String> {
+        headers?
+            .iter()
+            .find(|h| h.key.eq_ignore_ascii_case(name))
+            .and_then(|h| h.value)
+            .map(|v| String::from_utf8_lossy(v).into_owned())
+    };
+
+    let structured = header_value(CONTENT_TYPE_HEADER)
+        .is_some_and(|ct| ct.starts_with("application/cloudevents"));
+
+    if structured {
+        let envelope: StructuredEnvelope<Event> = serde_json::from_slice(payload)?;
+        check_spec_version(&envelope.specversion)?;
+        return Ok(DecodedEvent {
+            mode: Some(CloudEventsMode::Structured),
+            attributes: Some(envelope.attributes),
+            event: envelope.data,
+        });
+    }
+
+    let event: Event = serde_json::from_slice(payload)?;
+    let Some(spec_version) = header_value(&format!("{CE_PREFIX}specversion")) else {
+        return Ok(DecodedEvent {
+            mode: None,
+            attributes: None,
+            event,
+        });
+    };
+    check_spec_version(&spec_version)?;
+
+    let required = |name: &'static str| {
+        header_value(&format!("{CE_PREFIX}{name}")).ok_or(CloudEventError::MissingAttribute(name))
+    };
+    let time = header_value(&format!("{CE_PREFIX}time"))
+        .map(|t| {
+            DateTime::parse_from_rfc3339(&t)
+                .map(|dt| dt.with_timezone(&Utc))
+                .map_err(|_| CloudEventError::InvalidTime(t))
+        })
+        .transpose()?;
+
+    let attributes = CloudEventAttributes {
+        id: required("id")?,
+        source: required("source")?,
+        ty: required("type")?,
+        time,
+        subject: header_value(&format!("{CE_PREFIX}subject")),
+    };
+    Ok(DecodedEvent {
+        mode: Some(CloudEventsMode::Binary),
+        attributes: Some(attributes),
+        event,
+    })
+}
+
+fn check_spec_version(v: &str) -> Result<(), CloudEventError> {
+    if v == SPEC_VERSION {
+        Ok(())
+    } else {
+        Err(CloudEventError::UnsupportedSpecVersion(v.to_string()))
+    }
+}
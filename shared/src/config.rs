@@ -6,6 +6,9 @@ use toml::{
     from_str, to_string,
 };
 
+use crate::cloudevents::CloudEventsMode;
+use crate::logging::LogFormat;
+
 #[derive(Debug, Deserialize, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
 pub enum PartitioningMode {
@@ -20,11 +23,21 @@ pub struct AppConfig {
     pub group_id: Option<String>,
     pub compression: Option<String>,
     pub message_timeout_ms: Option<u64>,
+    pub linger_ms: Option<u64>,
+    pub max_in_flight: Option<u32>,
+    pub enable_idempotence: Option<bool>,
     pub auto_offset_reset: String,
     pub enable_auto_commit: bool,
     pub enable_auto_offset_store: Option<bool>,
     #[serde(default = "default_partitioning_mode")]
     pub partitioning: PartitioningMode,
+    pub cloudevents: Option<CloudEventsMode>,
+    pub cloudevents_source: Option<String>,
+    pub statistics_interval_ms: Option<u64>,
+    pub metrics_addr: Option<String>,
+    pub log_format: Option<LogFormat>,
+    pub otlp_endpoint: Option<String>,
+    pub trace_file: Option<String>,
 }
 
 fn default_partitioning_mode() -> PartitioningMode {
@@ -53,7 +66,7 @@ impl AppConfig {
 fn get_profile<'a>(root: &'a Value, profile_path: &str) -> Option<&'a Value> {
     profile_path
         .split('.')
-        .fold(Some(root), |acc, key| acc?.get(key))
+        .try_fold(root, |acc, key| acc.get(key))
 }
 
 // Merge two TOML tables (right overrides left)
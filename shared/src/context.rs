@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use rdkafka::TopicPartitionList;
+use rdkafka::client::ClientContext;
+use rdkafka::config::RDKafkaLogLevel;
+use rdkafka::consumer::{BaseConsumer, ConsumerContext, Rebalance};
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaErrorCode};
+use rdkafka::statistics::Statistics;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use crate::metrics::Metrics;
+use crate::offsets::SharedOffsetTracker;
+
+/// Client context used by the labs instead of rdkafka's default one.
+///
+/// The default context throws `statistics.interval.ms` callbacks away; this one
+/// feeds them into [`Metrics`], together with counts of rebalances and commit results. librdkafka's
+/// internal logs and errors are routed into `tracing` under the `librdkafka` target.
+///
+/// With an offset tracker attached, the completed offsets of revoked partitions
+/// are committed before a rebalance hands them to another consumer. With a
+/// revocation channel, the poll loop hears which partitions it lost.
+#[derive(Clone, Default)]
+pub struct LabContext {
+    pub metrics: Arc<Metrics>,
+    pub offsets: Option<SharedOffsetTracker>,
+    pub revocations: Option<mpsc::UnboundedSender<Vec<(String, i32)>>>,
+}
+
+impl LabContext {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            metrics,
+            offsets: None,
+            revocations: None,
+        }
+    }
+
+    pub fn with_offset_tracker(mut self, offsets: SharedOffsetTracker) -> Self {
+        self.offsets = Some(offsets);
+        self
+    }
+
+    /// Sends the (topic, partition) pairs of every revocation to `tx`.
+    pub fn with_revocations(mut self, tx: mpsc::UnboundedSender<Vec<(String, i32)>>) -> Self {
+        self.revocations = Some(tx);
+        self
+    }
+}
+
+impl ClientContext for LabContext {
+    fn log(&self, level: RDKafkaLogLevel, fac: &str, log_message: &str) {
+        match level {
+            RDKafkaLogLevel::Emerg
+            | RDKafkaLogLevel::Alert
+            | RDKafkaLogLevel::Critical
+            | RDKafkaLogLevel::Error => {
+                error!(target: "librdkafka", facility = fac, "{log_message}")
+            }
+            RDKafkaLogLevel::Warning => {
+                warn!(target: "librdkafka", facility = fac, "{log_message}")
+            }
+            RDKafkaLogLevel::Notice | RDKafkaLogLevel::Info => {
+                info!(target: "librdkafka", facility = fac, "{log_message}")
+            }
+            RDKafkaLogLevel::Debug => debug!(target: "librdkafka", facility = fac, "{log_message}"),
+        }
+    }
+
+    fn stats(&self, statistics: Statistics) {
+        self.metrics.record_stats(statistics);
+    }
+
+    fn error(&self, error: KafkaError, reason: &str) {
+        error!(target: "librdkafka", error = %error, "{reason}");
+    }
+}
+
+impl ConsumerContext for LabContext {
+    fn pre_rebalance(&self, base_consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        let Rebalance::Revoke(revoked) = rebalance else {
+            return;
+        };
+        if let Some(tx) = &self.revocations {
+            let _ = tx.send(
+                revoked
+                    .elements()
+                    .iter()
+                    .map(|e| (e.topic().to_string(), e.partition()))
+                    .collect(),
+            );
+        }
+        let Some(offsets) = &self.offsets else {
+            return;
+        };
+        match offsets.lock().unwrap().revoke(base_consumer, revoked) {
+            Ok(0) => {}
+            Ok(partitions) => {
+                self.metrics.inc_committed();
+                info!(partitions, "✅ COMMIT (revoke)");
+            }
+            Err(e) => error!(error = %e, "❌ Commit of revoked partitions failed"),
+        }
+    }
+
+    fn post_rebalance(&self, _base_consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        // A rebalance revokes, then assigns: count it once.
+        if matches!(rebalance, Rebalance::Assign(_)) {
+            self.metrics.inc_rebalances();
+        }
+    }
+
+    fn commit_callback(&self, result: KafkaResult<()>, _offsets: &TopicPartitionList) {
+        match result {
+            Ok(()) => self.metrics.inc_commit_result(true),
+            // Auto-commit found nothing new to commit: not a commit at all.
+            Err(KafkaError::ConsumerCommit(RDKafkaErrorCode::NoOffset)) => {}
+            Err(e) => {
+                self.metrics.inc_commit_result(false);
+                warn!(error = %e, "Offset commit failed");
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Idempotent consumer: skip messages that were already processed.
+//!
+//! At-least-once delivery redelivers a message when the consumer stops between
+//! processing it and committing its offset, and a producer that resends after
+//! an ambiguous timeout writes it twice. A [`Deduplicator`] remembers the id of
+//! every processed message for a while, so processing them again can be
+//! skipped: "effectively once" on top of at-least-once.
+
+use std::ops::Bound;
+use std::str::FromStr;
+use std::time::Duration;
+
+use rdkafka::message::{Headers, Message};
+
+use crate::state::{Offsets, StateStore, StoreError};
+use crate::util::now_ms;
+
+/// Prefix of `id -> first seen` entries.
+const BY_ID: &[u8] = b"id:";
+/// Prefix of `first seen, id -> ()` entries, the expiry index.
+const BY_TIME: &[u8] = b"ts:";
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid message id source `{0}` (expected offset, header:NAME or field:NAME)")]
+pub struct InvalidIdSource(String);
+
+/// Where the id of a message comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdSource {
+    /// `topic/partition/offset`: catches redeliveries, not a producer writing
+    /// the same message twice.
+    Offset,
+    /// A header set by the producer: catches both.
+    Header(String),
+    /// A top-level field of the JSON payload.
+    Field(String),
+}
+
+impl FromStr for IdSource {
+    type Err = InvalidIdSource;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "offset" => Ok(Self::Offset),
+            Some(("header", name)) if !name.is_empty() => Ok(Self::Header(name.to_string())),
+            Some(("field", name)) if !name.is_empty() => Ok(Self::Field(name.to_string())),
+            _ => Err(InvalidIdSource(s.to_string())),
+        }
+    }
+}
+
+impl IdSource {
+    /// The id of `m`, or `None` when it has no such header or field.
+    pub fn id_of<M: Message>(&self, m: &M) -> Option<String> {
+        match self {
+            Self::Offset => Some(format!("{}/{}/{}", m.topic(), m.partition(), m.offset())),
+            Self::Header(name) => m
+                .headers()?
+                .iter()
+                .find(|h| h.key == name)
+                .and_then(|h| h.value)
+                .map(|v| String::from_utf8_lossy(v).into_owned()),
+            Self::Field(name) => {
+                let payload: serde_json::Value = serde_json::from_slice(m.payload()?).ok()?;
+                match payload.get(name)? {
+                    serde_json::Value::String(s) => Some(s.clone()),
+                    serde_json::Value::Null => None,
+                    other => Some(other.to_string()),
+                }
+            }
+        }
+    }
+}
+
+fn id_key(id: &[u8]) -> Vec<u8> {
+    [BY_ID, id].concat()
+}
+
+fn time_key(seen: i64, id: &[u8]) -> Vec<u8> {
+    [BY_TIME, &seen.to_be_bytes()[..], id].concat()
+}
+
+/// Ids of processed messages in a [`StateStore`], each kept for `ttl` and at
+/// most `max_entries` of them: the oldest are forgotten first.
+///
+/// A redelivery older than the TTL, or pushed out by newer ids, is processed
+/// again, so both must cover the longest redelivery delay expected.
+pub struct Deduplicator<S> {
+    store: S,
+    ttl: Duration,
+    max_entries: usize,
+    len: usize,
+}
+
+impl<S: StateStore> Deduplicator<S> {
+    /// Wraps `store`, keeping what a previous run recorded in it.
+    pub fn new(store: S, ttl: Duration, max_entries: usize) -> Result<Self, StoreError> {
+        let len = store
+            .range(Bound::Included(BY_ID), Bound::Excluded(BY_TIME))?
+            .len();
+        let mut dedup = Self {
+            store,
+            ttl,
+            max_entries: max_entries.max(1),
+            len,
+        };
+        dedup.expire()?;
+        Ok(dedup)
+    }
+
+    /// Number of ids remembered.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether `id` was recorded less than the TTL ago.
+    pub fn seen(&self, id: &str) -> Result<bool, StoreError> {
+        let cutoff = now_ms() - self.ttl.as_millis() as i64;
+        Ok(self
+            .store
+            .get(&id_key(id.as_bytes()))?
+            .and_then(|v| v.try_into().ok())
+            .is_some_and(|seen: [u8; 8]| i64::from_be_bytes(seen) >= cutoff))
+    }
+
+    /// Records `id` as processed, then forgets expired ids and, beyond
+    /// `max_entries`, the oldest ones.
+    pub fn record(&mut self, id: &str) -> Result<(), StoreError> {
+        let now = now_ms();
+        let id = id.as_bytes();
+        match self.store.get(&id_key(id))? {
+            Some(previous) => {
+                if let Ok(seen) = previous.try_into() {
+                    self.store.delete(&time_key(i64::from_be_bytes(seen), id))?;
+                }
+            }
+            None => self.len += 1,
+        }
+        self.store.put(&id_key(id), &now.to_be_bytes())?;
+        self.store.put(&time_key(now, id), &[])?;
+        self.expire()?;
+        if self.len > self.max_entries {
+            // Evict down to 90%, so the index is not scanned on every record.
+            self.evict(self.len - (self.max_entries * 9 / 10).max(1))?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the ids recorded more than the TTL ago; returns how many.
+    pub fn expire(&mut self) -> Result<usize, StoreError> {
+        let cutoff = now_ms() - self.ttl.as_millis() as i64;
+        let upper = time_key(cutoff, &[]);
+        let expired = self
+            .store
+            .range(Bound::Included(BY_TIME), Bound::Excluded(&upper))?;
+        self.forget(&expired)?;
+        Ok(expired.len())
+    }
+
+    /// Makes the recorded ids durable, with the offsets of the messages they
+    /// cover: see [`StateStore::checkpoint`].
+    pub fn checkpoint(&mut self, offsets: &Offsets) -> Result<(), StoreError> {
+        self.store.checkpoint(offsets)
+    }
+
+    fn evict(&mut self, count: usize) -> Result<(), StoreError> {
+        let mut oldest = self
+            .store
+            .range(Bound::Included(BY_TIME), Bound::Unbounded)?;
+        oldest.truncate(count);
+        self.forget(&oldest)
+    }
+
+    /// Deletes the ids of expiry index entries.
+    fn forget(&mut self, index: &[(Vec<u8>, Vec<u8>)]) -> Result<(), StoreError> {
+        for (key, _) in index {
+            let id = &key[BY_TIME.len() + 8..];
+            self.store.delete(key)?;
+            self.store.delete(&id_key(id))?;
+            self.len -= 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+//! Flow control: pause the partitions a slow processing stage cannot keep up
+//! with, instead of blocking the poll loop.
+//!
+//! A consumer that awaits each record before the next `recv()` stops polling
+//! while it processes. Past `max.poll.interval.ms`, librdkafka decides it is
+//! stuck and leaves the group: its partitions move to another member, which
+//! processes their uncommitted records again. With flow control, the loop hands
+//! records to the processing stage without waiting for them. When the stage is
+//! saturated, it says so through [`Backpressure`] and [`FlowControl`] pauses the
+//! affected partitions; the loop keeps polling, paused partitions simply return
+//! nothing, so the consumer stays in the group. Once the stage has capacity
+//! again, the partitions are resumed.
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+use rdkafka::consumer::{Consumer, ConsumerContext};
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::parallel::set_paused;
+
+/// What the processing stage tells the consumer about a partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signal {
+    /// Stop fetching the partition: its records cannot be taken in.
+    Saturated { topic: String, partition: i32 },
+    /// Fetch the partition again.
+    Available { topic: String, partition: i32 },
+}
+
+/// Handle of the processing stage to signal saturation to [`FlowControl`].
+#[derive(Debug, Clone)]
+pub struct Backpressure {
+    tx: mpsc::UnboundedSender<Signal>,
+}
+
+impl Backpressure {
+    pub fn saturated(&self, topic: &str, partition: i32) {
+        let _ = self.tx.send(Signal::Saturated {
+            topic: topic.to_string(),
+            partition,
+        });
+    }
+
+    pub fn available(&self, topic: &str, partition: i32) {
+        let _ = self.tx.send(Signal::Available {
+            topic: topic.to_string(),
+            partition,
+        });
+    }
+}
+
+/// When a [`Backlog`] signals: saturated once it holds `high` records,
+/// available again once it is down to `low`.
+#[derive(Debug, Clone, Copy)]
+pub struct Watermarks {
+    pub high: usize,
+    pub low: usize,
+}
+
+#[derive(Debug, Default)]
+struct BacklogState {
+    len: usize,
+    saturated: bool,
+}
+
+/// Records of one partition handed to the processing stage and not done yet,
+/// signalling [`Backpressure`] as they cross the [`Watermarks`].
+///
+/// Clones share the count: the poll loop calls [`push`](Self::push) when it
+/// hands a record over, the stage calls [`done`](Self::done) once the record
+/// is processed.
+#[derive(Debug, Clone)]
+pub struct Backlog {
+    topic: String,
+    partition: i32,
+    watermarks: Watermarks,
+    backpressure: Backpressure,
+    state: Arc<Mutex<BacklogState>>,
+}
+
+impl Backlog {
+    pub fn new(
+        topic: &str,
+        partition: i32,
+        watermarks: Watermarks,
+        backpressure: Backpressure,
+    ) -> Self {
+        Self {
+            topic: topic.to_string(),
+            partition,
+            watermarks: Watermarks {
+                high: watermarks.high.max(1),
+                low: watermarks.low.min(watermarks.high.max(1) - 1),
+            },
+            backpressure,
+            state: Arc::default(),
+        }
+    }
+
+    /// Records handed over and not done yet.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&self) {
+        // Signals are sent under the lock, so they reach the consumer in order.
+        let mut state = self.state.lock().unwrap();
+        state.len += 1;
+        if !state.saturated && state.len >= self.watermarks.high {
+            state.saturated = true;
+            self.backpressure.saturated(&self.topic, self.partition);
+        }
+    }
+
+    pub fn done(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.len = state.len.saturating_sub(1);
+        if state.saturated && state.len <= self.watermarks.low {
+            state.saturated = false;
+            self.backpressure.available(&self.topic, self.partition);
+        }
+    }
+}
+
+/// Pauses and resumes partitions as the processing stage signals.
+///
+/// Poll [`next_signal`](Self::next_signal) next to `consumer.recv()` in a
+/// `select!` loop and [`apply`](Self::apply) what it returns. A pause drops the
+/// records librdkafka prefetched for the partition; they are fetched again
+/// after the resume, from the position of the last record received.
+pub struct FlowControl {
+    signals: mpsc::UnboundedReceiver<Signal>,
+    /// Keeps the channel open when no stage holds a [`Backpressure`].
+    tx: mpsc::UnboundedSender<Signal>,
+    paused: BTreeSet<(String, i32)>,
+    pauses: u64,
+}
+
+impl FlowControl {
+    pub fn new() -> Self {
+        let (tx, signals) = mpsc::unbounded_channel();
+        Self {
+            signals,
+            tx,
+            paused: BTreeSet::new(),
+            pauses: 0,
+        }
+    }
+
+    /// Handle to give the processing stage.
+    pub fn backpressure(&self) -> Backpressure {
+        Backpressure {
+            tx: self.tx.clone(),
+        }
+    }
+
+    /// Waits for the next signal. Cancel-safe.
+    pub async fn next_signal(&mut self) -> Option<Signal> {
+        self.signals.recv().await
+    }
+
+    /// Pauses or resumes the partition of `signal`, unless it already is.
+    pub fn apply<X, C>(&mut self, consumer: &C, signal: Signal)
+    where
+        X: ConsumerContext,
+        C: Consumer<X>,
+    {
+        match signal {
+            Signal::Saturated { topic, partition } => {
+                if self.paused.insert((topic.clone(), partition)) {
+                    self.pauses += 1;
+                    info!(topic, partition, "⏸️ PAUSED, downstream saturated");
+                    set_paused(consumer, &[(topic, partition)], true);
+                }
+            }
+            Signal::Available { topic, partition } => {
+                if self.paused.remove(&(topic.clone(), partition)) {
+                    info!(topic, partition, "▶️ RESUMED, downstream has capacity");
+                    set_paused(consumer, &[(topic, partition)], false);
+                }
+            }
+        }
+    }
+
+    /// Forgets a revoked partition, resuming it if it was paused: librdkafka
+    /// keeps the pause across a revocation, so a partition assigned again
+    /// would stay paused with no stage left to resume it.
+    pub fn forget<X, C>(&mut self, consumer: &C, topic: &str, partition: i32)
+    where
+        X: ConsumerContext,
+        C: Consumer<X>,
+    {
+        if self.paused.remove(&(topic.to_string(), partition)) {
+            set_paused(consumer, &[(topic.to_string(), partition)], false);
+        }
+    }
+
+    /// Partitions paused right now.
+    pub fn paused(&self) -> Vec<(String, i32)> {
+        self.paused.iter().cloned().collect()
+    }
+
+    /// Pauses so far.
+    pub fn pauses(&self) -> u64 {
+        self.pauses
+    }
+}
+
+impl Default for FlowControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
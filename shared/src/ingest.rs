@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::DateTime;
+use rdkafka::message::Header;
+use rdkafka::producer::FutureRecord;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::event::Event;
+
+/// Layout of the lines a producer reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// `user_id action value`, separated by whitespace.
+    Text,
+    /// One JSON object per line.
+    JsonLines,
+    /// Comma-separated values with a header row.
+    Csv,
+}
+
+impl FromStr for InputFormat {
+    type Err = IngestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "jsonl" | "json" => Ok(Self::JsonLines),
+            "csv" => Ok(Self::Csv),
+            other => Err(IngestError::UnknownFormat(other.to_string())),
+        }
+    }
+}
+
+impl InputFormat {
+    /// Guesses the format from the file extension; anything else is text.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("jsonl" | "ndjson" | "json") => Self::JsonLines,
+            Some("csv") => Self::Csv,
+            _ => Self::Text,
+        }
+    }
+}
+
+/// Which input columns (CSV header names or JSON fields) feed each part of a record.
+#[derive(Debug, Clone)]
+pub struct ColumnMapping {
+    pub user_id: String,
+    pub action: String,
+    pub value: String,
+    /// Record key; the event's `user_id` when not mapped.
+    pub key: Option<String>,
+    /// Explicit partition, overriding the partitioner.
+    pub partition: Option<String>,
+    /// Record timestamp, as epoch milliseconds or RFC 3339.
+    pub timestamp: Option<String>,
+    /// Columns copied into record headers, under the column name.
+    pub headers: Vec<String>,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self {
+            user_id: "user_id".to_string(),
+            action: "action".to_string(),
+            value: "value".to_string(),
+            key: None,
+            partition: None,
+            timestamp: None,
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl ColumnMapping {
+    /// Applies a `field=column,...` spec, e.g. `user_id=uid,key=account,header=source`.
+    /// `header` may be repeated.
+    pub fn apply(&mut self, spec: &str) -> Result<(), IngestError> {
+        for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let Some((field, column)) = pair.split_once('=') else {
+                return Err(IngestError::InvalidMapping(pair.to_string()));
+            };
+            let column = column.trim().to_string();
+            match field.trim() {
+                "user_id" => self.user_id = column,
+                "action" => self.action = column,
+                "value" => self.value = column,
+                "key" => self.key = Some(column),
+                "partition" => self.partition = Some(column),
+                "timestamp" => self.timestamp = Some(column),
+                "header" => self.headers.push(column),
+                _ => return Err(IngestError::InvalidMapping(pair.to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    /// Every column the mapping reads; a CSV header must contain the required ones.
+    fn columns(&self) -> impl Iterator<Item = &String> {
+        [&self.user_id, &self.action, &self.value]
+            .into_iter()
+            .chain(self.key.iter())
+            .chain(self.partition.iter())
+            .chain(self.timestamp.iter())
+            .chain(self.headers.iter())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum IngestError {
+    #[error("unknown input format `{0}` (expected text, jsonl or csv)")]
+    UnknownFormat(String),
+    #[error(
+        "invalid column mapping `{0}` (expected user_id, action, value, key, partition, timestamp or header = column)"
+    )]
+    InvalidMapping(String),
+    #[error("CSV header has no column `{0}`")]
+    MissingHeader(String),
+    #[error("line {line}: expected `user_id action value`")]
+    Text { line: u64 },
+    #[error("line {line}: {source}")]
+    Json {
+        line: u64,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("line {line}: not a JSON object")]
+    NotAnObject { line: u64 },
+    #[error("line {line}: {source}")]
+    Csv {
+        line: u64,
+        #[source]
+        source: csv::Error,
+    },
+    #[error("line {line}: missing `{column}`")]
+    Missing { line: u64, column: String },
+    #[error("line {line}: `{column}` = {value:?} is not a valid {expected}")]
+    Invalid {
+        line: u64,
+        column: String,
+        value: String,
+        expected: &'static str,
+    },
+    #[error("line {line}: {source}")]
+    Io {
+        line: u64,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl IngestError {
+    /// Input line the error refers to, when it refers to one.
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            Self::Text { line }
+            | Self::Json { line, .. }
+            | Self::NotAnObject { line }
+            | Self::Csv { line, .. }
+            | Self::Missing { line, .. }
+            | Self::Invalid { line, .. }
+            | Self::Io { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+}
+
+/// One validated input row.
+#[derive(Debug, Clone)]
+pub struct IngestRecord {
+    /// Line of the input the row was read from (1-based).
+    pub line: u64,
+    pub event: Event,
+    pub key: String,
+    pub partition: Option<i32>,
+    /// Epoch milliseconds.
+    pub timestamp: Option<i64>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl IngestRecord {
+    /// Sets the partition, timestamp and headers of this row on `record`,
+    /// keeping the headers it already has.
+    pub fn apply<'a>(
+        &'a self,
+        mut record: FutureRecord<'a, str, [u8]>,
+    ) -> FutureRecord<'a, str, [u8]> {
+        if let Some(p) = self.partition {
+            record = record.partition(p);
+        }
+        if let Some(ts) = self.timestamp {
+            record = record.timestamp(ts);
+        }
+        if !self.headers.is_empty() {
+            let headers = self.headers.iter().fold(
+                record.headers.take().unwrap_or_default(),
+                |headers, (key, value)| {
+                    headers.insert(Header {
+                        key,
+                        value: Some(value),
+                    })
+                },
+            );
+            record = record.headers(headers);
+        }
+        record
+    }
+}
+
+/// Parses `reader` on a background thread, sending one result per row; the
+/// channel closes at end of input. Rows that fail validation are sent as
+/// errors and reading goes on, so callers can report every bad line.
+pub fn spawn_reader<R: Read + Send + 'static>(
+    reader: R,
+    format: InputFormat,
+    mapping: ColumnMapping,
+) -> mpsc::Receiver<Result<IngestRecord, IngestError>> {
+    let (tx, rx) = mpsc::channel(1024);
+    std::thread::spawn(move || {
+        let send = |row| tx.blocking_send(row).is_ok();
+        match format {
+            InputFormat::Text | InputFormat::JsonLines => {
+                for (i, line) in BufReader::new(reader).lines().enumerate() {
+                    let line_no = i as u64 + 1;
+                    let row = match line {
+                        Ok(line) if line.trim().is_empty() => continue,
+                        Ok(line) if format == InputFormat::Text => parse_text(line_no, &line),
+                        Ok(line) => parse_json(line_no, &line, &mapping),
+                        Err(source) => Err(IngestError::Io {
+                            line: line_no,
+                            source,
+                        }),
+                    };
+                    if !send(row) {
+                        return;
+                    }
+                }
+            }
+            InputFormat::Csv => read_csv(reader, &mapping, send),
+        }
+    });
+    rx
+}
+
+fn parse_text(line: u64, text: &str) -> Result<IngestRecord, IngestError> {
+    let parts: Vec<_> = text.split_whitespace().collect();
+    if parts.len() < 3 {
+        return Err(IngestError::Text { line });
+    }
+    let event = Event {
+        user_id: parts[0].to_string(),
+        action: parts[1].to_string(),
+        value: parse_number(line, "value", parts[2])?,
+    };
+    Ok(IngestRecord {
+        line,
+        key: event.user_id.clone(),
+        event,
+        partition: None,
+        timestamp: None,
+        headers: Vec::new(),
+    })
+}
+
+fn parse_json(line: u64, text: &str, mapping: &ColumnMapping) -> Result<IngestRecord, IngestError> {
+    let value: Value =
+        serde_json::from_str(text).map_err(|source| IngestError::Json { line, source })?;
+    let Value::Object(fields) = value else {
+        return Err(IngestError::NotAnObject { line });
+    };
+    to_record(line, mapping, |column| match fields.get(column)? {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    })
+}
+
+fn read_csv<R: Read>(
+    reader: R,
+    mapping: &ColumnMapping,
+    send: impl Fn(Result<IngestRecord, IngestError>) -> bool,
+) {
+    let mut csv = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let index: HashMap<String, usize> = match csv.headers() {
+        Ok(headers) => headers
+            .iter()
+            .enumerate()
+            .map(|(i, h)| (h.to_string(), i))
+            .collect(),
+        Err(source) => {
+            send(Err(IngestError::Csv { line: 1, source }));
+            return;
+        }
+    };
+    // Optional columns may be absent from a row, but not from the header.
+    if let Some(missing) = mapping.columns().find(|c| !index.contains_key(*c)) {
+        send(Err(IngestError::MissingHeader(missing.clone())));
+        return;
+    }
+
+    for row in csv.records() {
+        let row = match row {
+            Ok(record) => {
+                let line = record.position().map_or(0, |p| p.line());
+                to_record(line, mapping, |column| {
+                    record
+                        .get(index[column])
+                        .filter(|v| !v.is_empty())
+                        .map(str::to_string)
+                })
+            }
+            Err(source) => Err(IngestError::Csv {
+                line: source.position().map_or(0, |p| p.line()),
+                source,
+            }),
+        };
+        if !send(row) {
+            return;
+        }
+    }
+}
+
+fn to_record(
+    line: u64,
+    mapping: &ColumnMapping,
+    get: impl Fn(&str) -> Option<String>,
+) -> Result<IngestRecord, IngestError> {
+    let required = |column: &String| {
+        get(column).ok_or_else(|| IngestError::Missing {
+            line,
+            column: column.clone(),
+        })
+    };
+    let event = Event {
+        user_id: required(&mapping.user_id)?,
+        action: required(&mapping.action)?,
+        value: parse_number(line, &mapping.value, &required(&mapping.value)?)?,
+    };
+    let key = match &mapping.key {
+        Some(column) => required(column)?,
+        None => event.user_id.clone(),
+    };
+    let partition = match mapping.partition.as_ref().and_then(|c| Some((c, get(c)?))) {
+        Some((column, v)) => Some(parse_number(line, column, &v)?),
+        None => None,
+    };
+    let timestamp = match mapping.timestamp.as_ref().and_then(|c| Some((c, get(c)?))) {
+        Some((column, v)) => Some(parse_timestamp(line, column, &v)?),
+        None => None,
+    };
+    let headers = mapping
+        .headers
+        .iter()
+        .filter_map(|column| Some((column.clone(), get(column)?)))
+        .collect();
+
+    Ok(IngestRecord {
+        line,
+        event,
+        key,
+        partition,
+        timestamp,
+        headers,
+    })
+}
+
+fn parse_number<T: FromStr>(line: u64, column: &str, value: &str) -> Result<T, IngestError> {
+    value.parse().map_err(|_| IngestError::Invalid {
+        line,
+        column: column.to_string(),
+        value: value.to_string(),
+        expected: "integer",
+    })
+}
+
+fn parse_timestamp(line: u64, column: &str, value: &str) -> Result<i64, IngestError> {
+    timestamp_millis(value).ok_or_else(|| IngestError::Invalid {
+        line,
+        column: column.to_string(),
+        value: value.to_string(),
+        expected: "timestamp (epoch millis or RFC 3339)",
+    })
+}
+
+/// Parses epoch milliseconds or an RFC 3339 date-time into epoch milliseconds.
+pub fn timestamp_millis(value: &str) -> Option<i64> {
+    value.parse::<i64>().ok().or_else(|| {
+        DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|t| t.timestamp_millis())
+    })
+}
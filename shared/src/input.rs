@@ -0,0 +1,28 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use tokio::sync::mpsc;
+
+/// Reads `reader` line by line on a background thread, so a binary waiting
+/// for input can still react to [`Shutdown`](crate::shutdown::Shutdown) or
+/// delivery reports. The channel closes at end of input.
+pub fn read_lines<R: BufRead + Send + 'static>(reader: R) -> mpsc::Receiver<io::Result<String>> {
+    let (tx, rx) = mpsc::channel(1024);
+    std::thread::spawn(move || {
+        for line in reader.lines() {
+            if tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+pub fn stdin_lines() -> mpsc::Receiver<io::Result<String>> {
+    read_lines(BufReader::new(io::stdin()))
+}
+
+pub fn file_lines(path: impl AsRef<Path>) -> io::Result<mpsc::Receiver<io::Result<String>>> {
+    Ok(read_lines(BufReader::new(File::open(path)?)))
+}
@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hdrhistogram::Histogram;
+use rdkafka::message::{Header, Headers, Message, Timestamp, ToBytes};
+use rdkafka::producer::FutureRecord;
+use tracing::info;
+
+/// Header carrying the producer's wall-clock send time, in microseconds since the epoch.
+pub const SENT_AT_HEADER: &str = "x-sent-at-us";
+
+/// Highest latency tracked by the histograms (1 hour, in microseconds).
+const MAX_TRACKABLE_US: u64 = 3_600_000_000;
+
+pub fn now_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or_default()
+}
+
+/// Adds the [`SENT_AT_HEADER`] to `record`, keeping any headers already set.
+///
+/// Call it right before `producer.send` so the stamp includes neither payload
+/// encoding nor record building.
+pub fn stamp<'a, K, P>(mut record: FutureRecord<'a, K, P>) -> FutureRecord<'a, K, P>
+where
+    K: ToBytes + ?Sized,
+    P: ToBytes + ?Sized,
+{
+    let sent_at = now_micros().to_string();
+    let headers = record.headers.take().unwrap_or_default().insert(Header {
+        key: SENT_AT_HEADER,
+        value: Some(&sent_at),
+    });
+    record.headers(headers)
+}
+
+/// Where a send time was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SentAtSource {
+    Header,
+    CreateTime,
+}
+
+/// Send time of `m` in microseconds: the [`SENT_AT_HEADER`] when present,
+/// otherwise the record's `CreateTime` timestamp (millisecond precision).
+pub fn sent_at<M: Message>(m: &M) -> Option<(i64, SentAtSource)> {
+    let from_header = m.headers().and_then(|hs| {
+        hs.iter()
+            .find(|h| h.key == SENT_AT_HEADER)
+            .and_then(|h| std::str::from_utf8(h.value?).ok()?.parse::<i64>().ok())
+    });
+    if let Some(us) = from_header {
+        return Some((us, SentAtSource::Header));
+    }
+    match m.timestamp() {
+        Timestamp::CreateTime(ms) => Some((ms * 1_000, SentAtSource::CreateTime)),
+        _ => None,
+    }
+}
+
+/// Per-partition latency histograms.
+///
+/// `window` is reset on every [`LatencyTracker::report_window`] so periodic
+/// reports show recent behaviour; `total` covers the whole run and is printed
+/// by [`LatencyTracker::report_total`] on exit.
+pub struct LatencyTracker {
+    window: BTreeMap<i32, Histogram<u64>>,
+    total: BTreeMap<i32, Histogram<u64>>,
+    missing: u64,
+}
+
+impl Default for LatencyTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self {
+            window: BTreeMap::new(),
+            total: BTreeMap::new(),
+            missing: 0,
+        }
+    }
+
+    /// Records the latency of `m`, measured against the local clock now.
+    /// Returns it in microseconds, or `None` if the record carries no send time.
+    pub fn record<M: Message>(&mut self, m: &M) -> Option<u64> {
+        let Some((sent_at, _)) = sent_at(m) else {
+            self.missing += 1;
+            return None;
+        };
+        // Producer and consumer clocks can disagree slightly; clamp instead of dropping.
+        let latency = (now_micros() - sent_at).max(0) as u64;
+        let latency = latency.min(MAX_TRACKABLE_US);
+        for map in [&mut self.window, &mut self.total] {
+            map.entry(m.partition())
+                .or_insert_with(new_histogram)
+                .record(latency)
+                .expect("latency is clamped to the trackable range");
+        }
+        Some(latency)
+    }
+
+    pub fn report_window(&mut self) {
+        if self.window.is_empty() {
+            return;
+        }
+        report("window", &self.window);
+        self.window.clear();
+    }
+
+    pub fn report_total(&self) {
+        report("total", &self.total);
+        if self.missing > 0 {
+            info!(
+                missing = self.missing,
+                "⏱️ records without a send time were skipped"
+            );
+        }
+    }
+}
+
+fn new_histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, MAX_TRACKABLE_US, 3).expect("valid histogram bounds")
+}
+
+fn report(scope: &str, per_partition: &BTreeMap<i32, Histogram<u64>>) {
+    let mut all = new_histogram();
+    for (partition, h) in per_partition {
+        log_histogram(scope, &partition.to_string(), h);
+        all.add(h).expect("histograms share the same bounds");
+    }
+    log_histogram(scope, "all", &all);
+}
+
+fn log_histogram(scope: &str, partition: &str, h: &Histogram<u64>) {
+    let ms = |us: u64| us as f64 / 1_000.0;
+    info!(
+        scope,
+        partition,
+        count = h.len(),
+        p50_ms = ms(h.value_at_quantile(0.50)),
+        p95_ms = ms(h.value_at_quantile(0.95)),
+        p99_ms = ms(h.value_at_quantile(0.99)),
+        max_ms = ms(h.max()),
+        "⏱️ end-to-end latency"
+    );
+}
@@ -1,31 +1,80 @@
 use anyhow::Result;
+use rdkafka::client::ClientContext;
 use rdkafka::config::ClientConfig;
+use rdkafka::consumer::ConsumerContext;
 use rdkafka::{consumer::StreamConsumer, producer::FutureProducer};
 
+use crate::context::LabContext;
+
+pub mod cloudevents;
 pub mod config;
+pub mod context;
+pub mod dedup;
 pub mod event;
+pub mod flow;
+pub mod ingest;
+pub mod input;
+pub mod latency;
+pub mod logging;
+pub mod metrics;
+pub mod mock;
+pub mod offsets;
+pub mod parallel;
+pub mod pipeline;
+pub mod read_to_end;
 pub mod record;
+pub mod request_reply;
+pub mod scheduler;
+pub mod shutdown;
+pub mod state;
+pub mod telemetry;
+pub mod util;
+pub mod windowing;
+
+pub fn create_producer_props<K, V>(props: &[(K, V)]) -> Result<FutureProducer<LabContext>>
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    create_producer_with_context(props, LabContext::default())
+}
+
+pub fn create_consumer_props<K, V>(props: &[(K, V)]) -> Result<StreamConsumer<LabContext>>
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    create_consumer_with_context(props, LabContext::default())
+}
 
-pub fn create_producer_props<K, V>(props: &[(K, V)]) -> Result<FutureProducer>
+pub fn create_producer_with_context<K, V, C>(
+    props: &[(K, V)],
+    context: C,
+) -> Result<FutureProducer<C>>
 where
     K: AsRef<str>,
     V: AsRef<str>,
+    C: ClientContext + 'static,
 {
     let mut cfg = ClientConfig::new();
     for (k, v) in props {
         cfg.set(k.as_ref(), v.as_ref());
     }
-    Ok(cfg.create()?)
+    Ok(cfg.create_with_context(context)?)
 }
 
-pub fn create_consumer_props<K, V>(props: &[(K, V)]) -> Result<StreamConsumer>
+pub fn create_consumer_with_context<K, V, C>(
+    props: &[(K, V)],
+    context: C,
+) -> Result<StreamConsumer<C>>
 where
     K: AsRef<str>,
     V: AsRef<str>,
+    C: ConsumerContext + 'static,
 {
     let mut cfg = ClientConfig::new();
     for (k, v) in props {
         cfg.set(k.as_ref(), v.as_ref());
     }
-    Ok(cfg.create()?)
+    Ok(cfg.create_with_context(context)?)
 }
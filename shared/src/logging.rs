@@ -0,0 +1,68 @@
+use std::str::FromStr;
+
+use opentelemetry_sdk::trace::SdkTracer;
+use serde::Deserialize;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, fmt};
+
+/// Output format of the `tracing` subscriber installed by [`init`].
+///
+/// - `Human`: one compact line per event, emoji markers kept in the message.
+/// - `Json`: one JSON object per event, fields flattened for log pipelines.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!(
+                "unknown log format '{other}' (expected human|json)"
+            )),
+        }
+    }
+}
+
+/// Installs the global subscriber, writing to stderr.
+///
+/// The level filter comes from `RUST_LOG` and defaults to `info`. librdkafka's
+/// own logs arrive through `LabContext::log` with the `librdkafka` target, so
+/// e.g. `RUST_LOG=info,librdkafka=debug` makes them more verbose.
+pub fn init(format: LogFormat) {
+    init_with_tracer(format, None);
+}
+
+/// Same as [`init`], additionally exporting spans through `tracer` when given
+/// (see `telemetry::init_tracer_provider`).
+pub fn init_with_tracer(format: LogFormat, tracer: Option<SdkTracer>) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt_layer = match format {
+        LogFormat::Human => fmt::layer()
+            .compact()
+            .with_target(false)
+            .with_writer(std::io::stderr)
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_writer(std::io::stderr)
+            .boxed(),
+    };
+    let otel_layer = tracer.map(|t| tracing_opentelemetry::layer().with_tracer(t));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer)
+        .with(otel_layer)
+        .init();
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rdkafka::statistics::Statistics;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+
+/// Pause after a failed accept, so a persistent error does not spin the loop.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// Metrics shared between the Kafka client context, the application loop and
+/// the `/metrics` endpoint.
+///
+/// librdkafka statistics are kept as the latest snapshot per client (keyed by
+/// the client's `name`), application counters are plain atomics.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    stats: Mutex<HashMap<String, Statistics>>,
+    processed: AtomicU64,
+    failed: AtomicU64,
+    duplicates: AtomicU64,
+    committed: AtomicU64,
+    commits_acked: AtomicU64,
+    commits_rejected: AtomicU64,
+    rebalances: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn record_stats(&self, statistics: Statistics) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.insert(statistics.name.clone(), statistics);
+    }
+
+    pub fn inc_processed(&self) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a message skipped because it was processed before.
+    pub fn inc_duplicates(&self) {
+        self.duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_committed(&self) {
+        self.committed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a commit result reported by librdkafka's offset commit callback.
+    pub fn inc_commit_result(&self, ok: bool) {
+        let counter = if ok {
+            &self.commits_acked
+        } else {
+            &self.commits_rejected
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn committed(&self) -> u64 {
+        self.committed.load(Ordering::Relaxed)
+    }
+
+    /// Commits acknowledged and rejected by the group coordinator so far.
+    pub fn commit_results(&self) -> (u64, u64) {
+        (
+            self.commits_acked.load(Ordering::Relaxed),
+            self.commits_rejected.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Counts a rebalance; call it once per assignment, not on revocations.
+    pub fn inc_rebalances(&self) {
+        self.rebalances.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Rebalances seen so far, one per partition assignment.
+    pub fn rebalances(&self) -> u64 {
+        self.rebalances.load(Ordering::Relaxed)
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        family(
+            &mut out,
+            "app_messages_processed_total",
+            "counter",
+            "Messages processed successfully by the application.",
+            [(vec![], self.processed.load(Ordering::Relaxed) as f64)],
+        );
+        family(
+            &mut out,
+            "app_messages_failed_total",
+            "counter",
+            "Messages whose processing failed.",
+            [(vec![], self.failed.load(Ordering::Relaxed) as f64)],
+        );
+        family(
+            &mut out,
+            "app_messages_duplicate_total",
+            "counter",
+            "Messages skipped because they were processed before.",
+            [(vec![], self.duplicates.load(Ordering::Relaxed) as f64)],
+        );
+        family(
+            &mut out,
+            "app_offsets_committed_total",
+            "counter",
+            "Offset commits issued by the application.",
+            [(vec![], self.committed.load(Ordering::Relaxed) as f64)],
+        );
+        family(
+            &mut out,
+            "kafka_offset_commit_results_total",
+            "counter",
+            "Offset commit results reported by librdkafka, including auto-commits.",
+            [
+                (
+                    vec![("result", "ok".to_string())],
+                    self.commits_acked.load(Ordering::Relaxed) as f64,
+                ),
+                (
+                    vec![("result", "error".to_string())],
+                    self.commits_rejected.load(Ordering::Relaxed) as f64,
+                ),
+            ],
+        );
+        family(
+            &mut out,
+            "app_rebalances_total",
+            "counter",
+            "Rebalances seen by the consumer context (one per partition assignment).",
+            [(vec![], self.rebalances.load(Ordering::Relaxed) as f64)],
+        );
+
+        let stats = self.stats.lock().unwrap();
+        let mut clients: Vec<&Statistics> = stats.values().collect();
+        clients.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let client =
+            |s: &Statistics| vec![("client", s.name.clone()), ("type", s.client_type.clone())];
+
+        family(
+            &mut out,
+            "kafka_client_tx_bytes_total",
+            "counter",
+            "Bytes transmitted to brokers.",
+            clients.iter().map(|s| (client(s), s.tx_bytes as f64)),
+        );
+        family(
+            &mut out,
+            "kafka_client_rx_bytes_total",
+            "counter",
+            "Bytes received from brokers.",
+            clients.iter().map(|s| (client(s), s.rx_bytes as f64)),
+        );
+        family(
+            &mut out,
+            "kafka_client_tx_messages_total",
+            "counter",
+            "Messages transmitted (produced) to brokers.",
+            clients.iter().map(|s| (client(s), s.txmsgs as f64)),
+        );
+        family(
+            &mut out,
+            "kafka_client_rx_messages_total",
+            "counter",
+            "Messages received (consumed) from brokers.",
+            clients.iter().map(|s| (client(s), s.rxmsgs as f64)),
+        );
+        family(
+            &mut out,
+            "kafka_client_queue_messages",
+            "gauge",
+            "Messages waiting in the producer queue.",
+            clients.iter().map(|s| (client(s), s.msg_cnt as f64)),
+        );
+        family(
+            &mut out,
+            "kafka_client_queue_bytes",
+            "gauge",
+            "Bytes waiting in the producer queue.",
+            clients.iter().map(|s| (client(s), s.msg_size as f64)),
+        );
+
+        let brokers = || {
+            clients.iter().flat_map(move |s| {
+                s.brokers.values().map(move |b| {
+                    let mut labels = client(s);
+                    labels.push(("broker", b.name.clone()));
+                    (labels, b)
+                })
+            })
+        };
+        family(
+            &mut out,
+            "kafka_broker_rtt_avg_seconds",
+            "gauge",
+            "Average broker round-trip time over the last statistics window.",
+            brokers().filter_map(|(l, b)| b.rtt.as_ref().map(|w| (l, micros(w.avg)))),
+        );
+        family(
+            &mut out,
+            "kafka_broker_rtt_p99_seconds",
+            "gauge",
+            "99th percentile broker round-trip time over the last statistics window.",
+            brokers().filter_map(|(l, b)| b.rtt.as_ref().map(|w| (l, micros(w.p99)))),
+        );
+        family(
+            &mut out,
+            "kafka_broker_outbuf_requests",
+            "gauge",
+            "Requests waiting to be sent to the broker.",
+            brokers().map(|(l, b)| (l, b.outbuf_cnt as f64)),
+        );
+        family(
+            &mut out,
+            "kafka_broker_waitresp_requests",
+            "gauge",
+            "Requests in flight, awaiting a broker response.",
+            brokers().map(|(l, b)| (l, b.waitresp_cnt as f64)),
+        );
+
+        family(
+            &mut out,
+            "kafka_consumer_lag_messages",
+            "gauge",
+            "Consumer lag per partition (high watermark minus committed offset).",
+            clients.iter().flat_map(|s| {
+                s.topics.values().flat_map(move |t| {
+                    t.partitions
+                        .values()
+                        // -1 is librdkafka's internal UA partition; negative lag means unknown.
+                        .filter(|p| p.partition >= 0 && p.consumer_lag >= 0)
+                        .map(move |p| {
+                            let mut labels = client(s);
+                            labels.push(("topic", t.topic.clone()));
+                            labels.push(("partition", p.partition.to_string()));
+                            (labels, p.consumer_lag as f64)
+                        })
+                })
+            }),
+        );
+        family(
+            &mut out,
+            "kafka_consumer_rebalances_total",
+            "counter",
+            "Rebalances of the consumer group as counted by librdkafka.",
+            clients
+                .iter()
+                .filter_map(|s| s.cgrp.as_ref().map(|g| (client(s), g.rebalance_cnt as f64))),
+        );
+
+        out
+    }
+}
+
+fn micros(v: i64) -> f64 {
+    v as f64 / 1_000_000.0
+}
+
+fn family<I>(out: &mut String, name: &str, kind: &str, help: &str, samples: I)
+where
+    I: IntoIterator<Item = (Vec<(&'static str, String)>, f64)>,
+{
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{name} {value}");
+        } else {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{k}=\"{}\"", escape_label(v)))
+                .collect();
+            let _ = writeln!(out, "{name}{{{}}} {value}", labels.join(","));
+        }
+    }
+}
+
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves `GET /metrics` on `addr`. Fails only when `addr` cannot be bound.
+///
+/// This is a deliberately tiny HTTP/1.1 responder: one request per connection,
+/// enough for Prometheus scrapes and `curl`.
+pub async fn serve(addr: &str, metrics: Arc<Metrics>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        // Accept errors (out of file descriptors, a connection reset before it
+        // was accepted) concern one connection, not the endpoint.
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!(%addr, error = %e, "Metrics connection not accepted");
+                tokio::time::sleep(ACCEPT_RETRY).await;
+                continue;
+            }
+        };
+        let metrics = Arc::clone(&metrics);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+            let (status, body) = match path {
+                "/metrics" => ("200 OK", metrics.render()),
+                _ => ("404 Not Found", "not found\n".to_string()),
+            };
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Spawns [`serve`] on the current runtime, logging if `addr` cannot be bound.
+pub fn spawn_server(addr: String, metrics: Arc<Metrics>) {
+    tokio::spawn(async move {
+        info!(%addr, "📈 Metrics endpoint on http://{addr}/metrics");
+        if let Err(e) = serve(&addr, metrics).await {
+            error!(%addr, error = %e, "❌ Metrics endpoint not started");
+        }
+    });
+}
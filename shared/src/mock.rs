@@ -0,0 +1,118 @@
+use rdkafka::message::ToBytes;
+use rdkafka::mocking::MockCluster;
+use rdkafka::producer::{DefaultProducerContext, FutureProducer, FutureRecord};
+use tracing::info;
+
+use crate::config::AppConfig;
+use crate::context::LabContext;
+use crate::create_producer_props;
+use crate::pipeline::DeliveryPipeline;
+use crate::shutdown;
+
+/// Starts an in-process librdkafka mock cluster with `topic` already created.
+///
+/// Clients only need its `bootstrap_servers()`; the cluster lives as long as
+/// the returned value, so keep it in scope for the whole run.
+pub fn start_mock_cluster(
+    brokers: i32,
+    topic: &str,
+    partitions: i32,
+) -> anyhow::Result<MockCluster<'static, DefaultProducerContext>> {
+    let cluster = MockCluster::new(brokers)?;
+    cluster.create_topic(topic, partitions, 1)?;
+    info!(
+        bootstrap_servers = %cluster.bootstrap_servers(),
+        brokers,
+        topic,
+        partitions,
+        "🧪 Mock cluster started"
+    );
+    Ok(cluster)
+}
+
+/// Where a binary with a `--mock` flag connects: an in-process mock cluster
+/// with the profile's topic when the flag is set, the profile's brokers
+/// otherwise.
+///
+/// The mock cluster lives as long as this value: keep it until the end of
+/// `main`, after every client using it is dropped.
+pub struct Brokers {
+    mock: Option<MockCluster<'static, DefaultProducerContext>>,
+    configured: String,
+}
+
+impl Brokers {
+    pub fn new(mock: bool, cfg: &AppConfig, partitions: i32) -> anyhow::Result<Self> {
+        let mock = if mock {
+            Some(start_mock_cluster(3, &cfg.topic, partitions)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            mock,
+            configured: cfg.bootstrap_servers.clone(),
+        })
+    }
+
+    pub fn bootstrap_servers(&self) -> String {
+        match &self.mock {
+            Some(cluster) => cluster.bootstrap_servers(),
+            None => self.configured.clone(),
+        }
+    }
+
+    /// The mock cluster, to create more topics on it or pre-fill it.
+    pub fn mock(&self) -> Option<&MockCluster<'static, DefaultProducerContext>> {
+        self.mock.as_ref()
+    }
+}
+
+/// Produces the records a `--mock` run starts from, with up to 1 000 in
+/// flight, and fails unless every one of them is delivered.
+///
+/// The producer is idempotent, so each partition keeps the order of `send`.
+pub struct Prefill {
+    producer: FutureProducer<LabContext>,
+    pipeline: DeliveryPipeline<()>,
+    sent: u64,
+    failed: usize,
+}
+
+impl Prefill {
+    pub fn new(bootstrap_servers: &str) -> anyhow::Result<Self> {
+        let producer = create_producer_props(&[
+            ("bootstrap.servers", bootstrap_servers),
+            ("enable.idempotence", "true"),
+        ])?;
+        Ok(Self {
+            producer,
+            pipeline: DeliveryPipeline::new(1_000),
+            sent: 0,
+            failed: 0,
+        })
+    }
+
+    pub async fn send<K, P>(&mut self, record: FutureRecord<'_, K, P>)
+    where
+        K: ToBytes + ?Sized,
+        P: ToBytes + ?Sized,
+    {
+        let reports = self.pipeline.send(&self.producer, record, ()).await;
+        self.failed += reports.iter().filter(|r| r.result.is_err()).count();
+        self.sent += 1;
+    }
+
+    /// Waits for the last deliveries; returns how many records were sent.
+    pub async fn finish(mut self) -> anyhow::Result<u64> {
+        let reports = self.pipeline.drain(shutdown::FLUSH_TIMEOUT).await;
+        self.failed += reports.iter().filter(|r| r.result.is_err()).count();
+        if self.failed > 0 || self.pipeline.pending() > 0 {
+            anyhow::bail!(
+                "pre-filling the mock cluster failed: {} failed, {} undelivered",
+                self.failed,
+                self.pipeline.pending()
+            );
+        }
+        Ok(self.sent)
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rdkafka::consumer::{CommitMode, Consumer, ConsumerContext};
+use rdkafka::error::KafkaResult;
+use rdkafka::{Offset, TopicPartitionList};
+
+/// When [`OffsetTracker::should_flush`] asks for a commit.
+#[derive(Debug, Clone, Copy)]
+pub struct FlushPolicy {
+    /// Commit after this many completed messages...
+    pub max_completed: usize,
+    /// ...or when this much time has passed since the last commit, whichever comes first.
+    pub max_interval: Duration,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self {
+            max_completed: 100,
+            max_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Offsets of one partition.
+#[derive(Debug, Default)]
+struct PartitionOffsets {
+    /// Received but not completed yet.
+    in_flight: BTreeSet<i64>,
+    highest_completed: Option<i64>,
+    /// Next offset last committed.
+    committed: Option<i64>,
+}
+
+impl PartitionOffsets {
+    /// Next offset to commit: every tracked offset below it has completed.
+    ///
+    /// The lowest offset still in flight caps it, so a slow (or failed) message
+    /// holds the commit back even if later offsets are done.
+    fn committable(&self) -> Option<i64> {
+        let highest = self.highest_completed?;
+        let next = match self.in_flight.first() {
+            Some(&lowest) => lowest.min(highest + 1),
+            None => highest + 1,
+        };
+        match self.committed {
+            Some(c) if c >= next => None,
+            _ => Some(next),
+        }
+    }
+}
+
+/// Tracks processed offsets per partition so consumers can commit in batches
+/// without ever committing past a message that is not done yet.
+///
+/// Call [`track`](Self::track) when a message is received and
+/// [`complete`](Self::complete) once it has been processed; completions may
+/// come in any order. [`flush`](Self::flush) commits the highest contiguous
+/// offset of every partition.
+///
+/// Share it with [`LabContext`](crate::context::LabContext) through
+/// [`SharedOffsetTracker`] so revoked partitions are committed during a rebalance.
+#[derive(Debug)]
+pub struct OffsetTracker {
+    partitions: BTreeMap<(String, i32), PartitionOffsets>,
+    policy: FlushPolicy,
+    completed_since_flush: usize,
+    last_flush: Instant,
+}
+
+pub type SharedOffsetTracker = Arc<Mutex<OffsetTracker>>;
+
+impl OffsetTracker {
+    pub fn new(policy: FlushPolicy) -> Self {
+        Self {
+            partitions: BTreeMap::new(),
+            policy,
+            completed_since_flush: 0,
+            last_flush: Instant::now(),
+        }
+    }
+
+    pub fn shared(policy: FlushPolicy) -> SharedOffsetTracker {
+        Arc::new(Mutex::new(Self::new(policy)))
+    }
+
+    pub fn track(&mut self, topic: &str, partition: i32, offset: i64) {
+        self.partition(topic, partition).in_flight.insert(offset);
+    }
+
+    /// Marks a tracked offset as processed. Offsets that are not tracked (e.g.
+    /// of a partition revoked while the message was being processed) are ignored.
+    pub fn complete(&mut self, topic: &str, partition: i32, offset: i64) {
+        let Some(p) = self.partitions.get_mut(&(topic.to_string(), partition)) else {
+            return;
+        };
+        if p.in_flight.remove(&offset) {
+            p.highest_completed = p.highest_completed.max(Some(offset));
+            self.completed_since_flush += 1;
+        }
+    }
+
+    /// Whether `offset` was tracked and has not completed yet.
+    pub fn is_tracked(&self, topic: &str, partition: i32, offset: i64) -> bool {
+        self.partitions
+            .get(&(topic.to_string(), partition))
+            .is_some_and(|p| p.in_flight.contains(&offset))
+    }
+
+    /// Messages received and not completed yet, over all partitions.
+    pub fn in_flight(&self) -> usize {
+        self.partitions.values().map(|p| p.in_flight.len()).sum()
+    }
+
+    pub fn should_flush(&self) -> bool {
+        self.completed_since_flush >= self.policy.max_completed
+            || (self.completed_since_flush > 0
+                && self.last_flush.elapsed() >= self.policy.max_interval)
+    }
+
+    /// Offsets that would be committed by [`flush`](Self::flush) now.
+    pub fn committable(&self) -> TopicPartitionList {
+        self.to_commit(|_, _| true)
+    }
+
+    /// Synchronously commits the committable offset of every partition.
+    /// Returns how many partitions were committed.
+    pub fn flush<X, C>(&mut self, consumer: &C) -> KafkaResult<usize>
+    where
+        X: ConsumerContext,
+        C: Consumer<X>,
+    {
+        let tpl = self.committable();
+        self.commit(consumer, &tpl)?;
+        self.completed_since_flush = 0;
+        self.last_flush = Instant::now();
+        Ok(tpl.count())
+    }
+
+    /// Commits what is committable in the `revoked` partitions and forgets them:
+    /// once the rebalance completes another consumer owns them.
+    ///
+    /// Meant for `ConsumerContext::pre_rebalance`, while the partitions are still ours.
+    pub fn revoke<X, C>(&mut self, consumer: &C, revoked: &TopicPartitionList) -> KafkaResult<usize>
+    where
+        X: ConsumerContext,
+        C: Consumer<X>,
+    {
+        let revoked: BTreeSet<(String, i32)> = revoked
+            .elements()
+            .iter()
+            .map(|e| (e.topic().to_string(), e.partition()))
+            .collect();
+        let tpl =
+            self.to_commit(|topic, partition| revoked.contains(&(topic.to_string(), partition)));
+        self.commit(consumer, &tpl)?;
+        self.partitions.retain(|key, _| !revoked.contains(key));
+        Ok(tpl.count())
+    }
+
+    fn commit<X, C>(&mut self, consumer: &C, tpl: &TopicPartitionList) -> KafkaResult<()>
+    where
+        X: ConsumerContext,
+        C: Consumer<X>,
+    {
+        if tpl.count() == 0 {
+            return Ok(());
+        }
+        consumer.commit(tpl, CommitMode::Sync)?;
+        for e in tpl.elements() {
+            if let (Some(p), Offset::Offset(next)) = (
+                self.partitions
+                    .get_mut(&(e.topic().to_string(), e.partition())),
+                e.offset(),
+            ) {
+                p.committed = Some(next);
+            }
+        }
+        Ok(())
+    }
+
+    fn to_commit(&self, include: impl Fn(&str, i32) -> bool) -> TopicPartitionList {
+        let mut tpl = TopicPartitionList::new();
+        for ((topic, partition), p) in &self.partitions {
+            if !include(topic, *partition) {
+                continue;
+            }
+            if let Some(next) = p.committable() {
+                tpl.add_partition_offset(topic, *partition, Offset::Offset(next))
+                    .expect("a concrete offset is always valid");
+            }
+        }
+        tpl
+    }
+
+    fn partition(&mut self, topic: &str, partition: i32) -> &mut PartitionOffsets {
+        self.partitions
+            .entry((topic.to_string(), partition))
+            .or_default()
+    }
+}
@@ -0,0 +1,383 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::future::Future;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+
+use rdkafka::TopicPartitionList;
+use rdkafka::consumer::{Consumer, ConsumerContext};
+use rdkafka::message::{Message, OwnedMessage};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::offsets::SharedOffsetTracker;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParallelConfig {
+    /// Number of worker tasks; each record key always goes to the same worker.
+    pub workers: usize,
+    /// Messages queued per worker; also the parked backlog a partition may build up before it is paused.
+    pub queue_capacity: usize,
+    /// Messages dispatched or parked but not completed before every partition is paused.
+    pub max_in_flight: usize,
+}
+
+impl Default for ParallelConfig {
+    fn default() -> Self {
+        Self {
+            workers: 8,
+            queue_capacity: 16,
+            max_in_flight: 256,
+        }
+    }
+}
+
+/// Result of one message handled by a worker.
+#[derive(Debug)]
+pub struct Completion {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub worker: usize,
+    pub result: anyhow::Result<()>,
+}
+
+/// Worker tasks fed by bounded queues, one per worker. Messages are routed by
+/// key so each key is handled sequentially, in offset order, by a single worker.
+pub struct KeyedWorkerPool {
+    senders: Vec<mpsc::Sender<OwnedMessage>>,
+    completions: mpsc::UnboundedReceiver<Completion>,
+    workers: Vec<JoinHandle<()>>,
+    in_flight: usize,
+}
+
+impl KeyedWorkerPool {
+    pub fn spawn<F, Fut>(workers: usize, queue_capacity: usize, handler: F) -> Self
+    where
+        F: Fn(OwnedMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let (done_tx, completions) = mpsc::unbounded_channel();
+        let mut senders = Vec::new();
+        let mut handles = Vec::new();
+
+        for worker in 0..workers.max(1) {
+            let (tx, mut rx) = mpsc::channel::<OwnedMessage>(queue_capacity.max(1));
+            let handler = Arc::clone(&handler);
+            let done_tx = done_tx.clone();
+            handles.push(tokio::spawn(async move {
+                while let Some(m) = rx.recv().await {
+                    let (topic, partition, offset) =
+                        (m.topic().to_string(), m.partition(), m.offset());
+                    let result = handler(m).await;
+                    let completion = Completion {
+                        topic,
+                        partition,
+                        offset,
+                        worker,
+                        result,
+                    };
+                    if done_tx.send(completion).is_err() {
+                        break;
+                    }
+                }
+            }));
+            senders.push(tx);
+        }
+
+        Self {
+            senders,
+            completions,
+            workers: handles,
+            in_flight: 0,
+        }
+    }
+
+    /// Worker for `m`: hashed from the key, or from the partition for keyless
+    /// records so they keep their partition order.
+    pub fn worker_for(&self, m: &OwnedMessage) -> usize {
+        let mut h = DefaultHasher::new();
+        match m.key() {
+            Some(k) => k.hash(&mut h),
+            None => m.partition().hash(&mut h),
+        }
+        (h.finish() % self.senders.len() as u64) as usize
+    }
+
+    /// Queues `m` on its worker, or hands it back if that worker's queue is full.
+    pub fn try_dispatch(&mut self, m: OwnedMessage) -> Result<(), OwnedMessage> {
+        let worker = self.worker_for(&m);
+        match self.senders[worker].try_send(m) {
+            Ok(()) => {
+                self.in_flight += 1;
+                Ok(())
+            }
+            Err(TrySendError::Full(m) | TrySendError::Closed(m)) => Err(m),
+        }
+    }
+
+    /// Messages queued or being handled.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Waits for the next message to finish. Pending forever while nothing is in flight.
+    pub async fn next_completion(&mut self) -> Option<Completion> {
+        if self.in_flight == 0 {
+            return std::future::pending().await;
+        }
+        let completion = self.completions.recv().await?;
+        self.in_flight -= 1;
+        Some(completion)
+    }
+
+    /// Stops accepting work, lets the workers finish what is queued and
+    /// returns the remaining completions.
+    pub async fn shutdown(mut self) -> Vec<Completion> {
+        self.senders.clear();
+        for handle in self.workers.drain(..) {
+            let _ = handle.await;
+        }
+        let mut rest = Vec::new();
+        while let Ok(c) = self.completions.try_recv() {
+            rest.push(c);
+        }
+        rest
+    }
+}
+
+/// Sits between `consumer.recv()` and a [`KeyedWorkerPool`]: tracks every
+/// offset in the [`OffsetTracker`](crate::offsets::OffsetTracker) and applies
+/// backpressure by pausing partitions.
+///
+/// - When a worker queue is full, the message is parked; later messages of the
+///   same partition queue up behind it to keep their order.
+/// - A partition whose parked backlog exceeds `queue_capacity` is paused, and
+///   resumed once the backlog is down to half of it. Every pause drops
+///   librdkafka's prefetched messages and resuming waits for a new fetch, so
+///   pausing on the first full queue would thrash.
+/// - When `max_in_flight` messages are outstanding, every assigned partition is
+///   paused until half of them have completed.
+///
+/// Only successful completions are marked complete, so a failed message holds
+/// back the commit of its partition (at-least-once).
+pub struct ParallelDispatcher {
+    pool: KeyedWorkerPool,
+    offsets: SharedOffsetTracker,
+    queue_capacity: usize,
+    max_in_flight: usize,
+    parked: BTreeMap<(String, i32), VecDeque<OwnedMessage>>,
+    paused: BTreeSet<(String, i32)>,
+    paused_all: bool,
+}
+
+impl ParallelDispatcher {
+    pub fn new<F, Fut>(config: ParallelConfig, offsets: SharedOffsetTracker, handler: F) -> Self
+    where
+        F: Fn(OwnedMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        Self {
+            pool: KeyedWorkerPool::spawn(config.workers, config.queue_capacity, handler),
+            offsets,
+            queue_capacity: config.queue_capacity.max(1),
+            max_in_flight: config.max_in_flight.max(1),
+            parked: BTreeMap::new(),
+            paused: BTreeSet::new(),
+            paused_all: false,
+        }
+    }
+
+    /// Messages dispatched or parked, not completed yet.
+    pub fn in_flight(&self) -> usize {
+        self.pool.in_flight() + self.parked()
+    }
+
+    /// Partitions paused because of their parked backlog.
+    pub fn paused_partitions(&self) -> usize {
+        self.paused.len()
+    }
+
+    fn parked(&self) -> usize {
+        self.parked.values().map(VecDeque::len).sum()
+    }
+
+    pub fn dispatch<X, C>(&mut self, consumer: &C, m: OwnedMessage)
+    where
+        X: ConsumerContext,
+        C: Consumer<X>,
+    {
+        self.offsets
+            .lock()
+            .unwrap()
+            .track(m.topic(), m.partition(), m.offset());
+
+        let key = (m.topic().to_string(), m.partition());
+        if let Some(backlog) = self.parked.get_mut(&key) {
+            backlog.push_back(m);
+        } else if let Err(m) = self.pool.try_dispatch(m) {
+            self.parked.insert(key.clone(), VecDeque::from([m]));
+        }
+
+        let backlog = self.parked.get(&key).map_or(0, VecDeque::len);
+        if backlog > self.queue_capacity && self.paused.insert(key.clone()) {
+            debug!(topic = %key.0, partition = key.1, backlog, "⏸️ backlog full, pausing partition");
+            set_paused(consumer, &[key], true);
+        }
+
+        if !self.paused_all && self.in_flight() >= self.max_in_flight {
+            set_paused(consumer, &assigned(consumer), true);
+            self.paused_all = true;
+            debug!(
+                in_flight = self.in_flight(),
+                "⏸️ max in-flight reached, pausing all partitions"
+            );
+        }
+    }
+
+    pub async fn next_completion(&mut self) -> Option<Completion> {
+        self.pool.next_completion().await
+    }
+
+    /// Records `c` in the offset tracker, then moves parked messages to free
+    /// worker slots and resumes partitions that no longer need to wait.
+    pub fn on_completion<X, C>(&mut self, consumer: &C, c: &Completion)
+    where
+        X: ConsumerContext,
+        C: Consumer<X>,
+    {
+        if c.result.is_ok() {
+            self.offsets
+                .lock()
+                .unwrap()
+                .complete(&c.topic, c.partition, c.offset);
+        }
+        self.retry_parked(consumer);
+
+        if self.paused_all && self.in_flight() <= self.max_in_flight / 2 {
+            let ready: Vec<(String, i32)> = assigned(consumer)
+                .into_iter()
+                .filter(|key| !self.paused.contains(key))
+                .collect();
+            set_paused(consumer, &ready, false);
+            self.paused_all = false;
+            debug!(in_flight = self.in_flight(), "▶️ resuming all partitions");
+        }
+    }
+
+    fn retry_parked<X, C>(&mut self, consumer: &C)
+    where
+        X: ConsumerContext,
+        C: Consumer<X>,
+    {
+        for backlog in self.parked.values_mut() {
+            while let Some(m) = backlog.pop_front() {
+                // Partition revoked while parked: the new owner will read it again.
+                let tracked =
+                    self.offsets
+                        .lock()
+                        .unwrap()
+                        .is_tracked(m.topic(), m.partition(), m.offset());
+                if !tracked {
+                    continue;
+                }
+                if let Err(m) = self.pool.try_dispatch(m) {
+                    backlog.push_front(m);
+                    break;
+                }
+            }
+        }
+        self.parked.retain(|_, backlog| !backlog.is_empty());
+
+        let ready: Vec<(String, i32)> = self
+            .paused
+            .iter()
+            .filter(|key| self.parked.get(*key).map_or(0, VecDeque::len) <= self.queue_capacity / 2)
+            .cloned()
+            .collect();
+        for key in ready {
+            self.paused.remove(&key);
+            if !self.paused_all {
+                debug!(topic = %key.0, partition = key.1, "▶️ backlog drained, resuming partition");
+                set_paused(consumer, &[key], false);
+            }
+        }
+    }
+
+    /// Waits for every dispatched message and records the completions. Parked
+    /// messages are dropped unprocessed: they stay uncommitted and will be
+    /// delivered again.
+    pub async fn finish(self) -> Drained {
+        let mut drained = Drained {
+            dropped: self.parked(),
+            ..Drained::default()
+        };
+        let offsets = self.offsets;
+        for c in self.pool.shutdown().await {
+            if c.result.is_ok() {
+                drained.completed += 1;
+                offsets
+                    .lock()
+                    .unwrap()
+                    .complete(&c.topic, c.partition, c.offset);
+            } else {
+                drained.failed += 1;
+            }
+        }
+        if drained.dropped > 0 {
+            info!(
+                dropped = drained.dropped,
+                "Parked messages left unprocessed"
+            );
+        }
+        drained
+    }
+}
+
+/// What [`ParallelDispatcher::finish`] found while draining the workers.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Drained {
+    pub completed: usize,
+    pub failed: usize,
+    pub dropped: usize,
+}
+
+fn assigned<X, C>(consumer: &C) -> Vec<(String, i32)>
+where
+    X: ConsumerContext,
+    C: Consumer<X>,
+{
+    consumer
+        .assignment()
+        .map(|tpl| {
+            tpl.elements()
+                .iter()
+                .map(|e| (e.topic().to_string(), e.partition()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) fn set_paused<X, C>(consumer: &C, partitions: &[(String, i32)], paused: bool)
+where
+    X: ConsumerContext,
+    C: Consumer<X>,
+{
+    if partitions.is_empty() {
+        return;
+    }
+    let mut tpl = TopicPartitionList::new();
+    for (topic, partition) in partitions {
+        tpl.add_partition(topic, *partition);
+    }
+    let result = if paused {
+        consumer.pause(&tpl)
+    } else {
+        consumer.resume(&tpl)
+    };
+    // Fails for partitions revoked in the meantime, which is harmless.
+    if let Err(e) = result {
+        warn!(error = %e, paused, "Could not change partition pause state");
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use rdkafka::client::ClientContext;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::ToBytes;
+use rdkafka::producer::future_producer::{Delivery, OwnedDeliveryResult};
+use rdkafka::producer::{DeliveryFuture, FutureProducer, FutureRecord};
+use tokio::time::Instant;
+
+/// Backoff while librdkafka's queue is full and none of our deliveries is pending.
+const MAX_QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Outcome of one record sent through a [`DeliveryPipeline`].
+#[derive(Debug)]
+pub struct Report<T> {
+    /// Whatever the caller passed to [`DeliveryPipeline::send`] to recognize the record.
+    pub tag: T,
+    pub result: Result<Delivery, KafkaError>,
+}
+
+enum Pending {
+    Sent(DeliveryFuture),
+    /// Could not be enqueued; kept in line so reports stay in send order.
+    Failed(KafkaError),
+}
+
+/// Sends records without waiting for each delivery report, keeping at most
+/// `window` of them in flight.
+///
+/// Reports are handed back in send order, even when librdkafka completes
+/// deliveries in a different order, so the caller can log them as if each
+/// send had been awaited. A window of 1 is the "send and wait" behavior.
+pub struct DeliveryPipeline<T> {
+    window: usize,
+    pending: VecDeque<(T, Pending)>,
+    queue_full: u64,
+}
+
+impl<T> DeliveryPipeline<T> {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            pending: VecDeque::new(),
+            queue_full: 0,
+        }
+    }
+
+    /// Records sent whose report has not been returned yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Times librdkafka's local queue was full (`QueueFull`) when sending.
+    pub fn queue_full(&self) -> u64 {
+        self.queue_full
+    }
+
+    /// Enqueues `record`. When the window is full, waits for the oldest
+    /// deliveries first; on `QueueFull`, waits for the oldest delivery (or
+    /// backs off when none of ours is pending) and retries.
+    ///
+    /// Returns the reports of the deliveries it waited for, in send order.
+    pub async fn send<C, K, P>(
+        &mut self,
+        producer: &FutureProducer<C>,
+        mut record: FutureRecord<'_, K, P>,
+        tag: T,
+    ) -> Vec<Report<T>>
+    where
+        C: ClientContext + 'static,
+        K: ToBytes + ?Sized,
+        P: ToBytes + ?Sized,
+    {
+        let mut reports = Vec::new();
+        while self.pending.len() >= self.window {
+            reports.extend(self.next_report().await);
+        }
+
+        let mut backoff = Duration::from_millis(10);
+        loop {
+            match producer.send_result(record) {
+                Ok(delivery) => {
+                    self.pending.push_back((tag, Pending::Sent(delivery)));
+                    return reports;
+                }
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), r)) => {
+                    self.queue_full += 1;
+                    record = r;
+                    match self.next_report().await {
+                        Some(report) => reports.push(report),
+                        None => {
+                            tokio::time::sleep(backoff).await;
+                            backoff = (backoff * 2).min(MAX_QUEUE_FULL_BACKOFF);
+                        }
+                    }
+                }
+                Err((e, _)) => {
+                    self.pending.push_back((tag, Pending::Failed(e)));
+                    return reports;
+                }
+            }
+        }
+    }
+
+    /// Waits for the oldest pending delivery; `None` when nothing is pending.
+    ///
+    /// Cancel-safe: the delivery stays pending if the future is dropped, so it
+    /// can be polled in a `select!` loop to report deliveries as they complete.
+    pub async fn next_report(&mut self) -> Option<Report<T>> {
+        let result = match &mut self.pending.front_mut()?.1 {
+            Pending::Sent(delivery) => outcome(delivery.await),
+            Pending::Failed(e) => Err(e.clone()),
+        };
+        let (tag, _) = self.pending.pop_front()?;
+        Some(Report { tag, result })
+    }
+
+    /// Reports of the oldest deliveries that already completed, without
+    /// waiting. Right after a producer flush, that is every one of them.
+    pub fn completed(&mut self) -> Vec<Report<T>> {
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut reports = Vec::new();
+        while let Some((_, pending)) = self.pending.front_mut() {
+            let result = match pending {
+                Pending::Sent(delivery) => match Pin::new(delivery).poll(&mut cx) {
+                    Poll::Ready(r) => outcome(r),
+                    Poll::Pending => break,
+                },
+                Pending::Failed(e) => Err(e.clone()),
+            };
+            if let Some((tag, _)) = self.pending.pop_front() {
+                reports.push(Report { tag, result });
+            }
+        }
+        reports
+    }
+
+    /// Waits up to `timeout` for every pending delivery. Whatever is still
+    /// pending afterwards is counted by [`pending`](Self::pending).
+    pub async fn drain(&mut self, timeout: Duration) -> Vec<Report<T>> {
+        let deadline = Instant::now() + timeout;
+        let mut reports = Vec::new();
+        while let Ok(Some(report)) = tokio::time::timeout_at(deadline, self.next_report()).await {
+            reports.push(report);
+        }
+        reports
+    }
+}
+
+/// The result of a delivery report; the error is librdkafka dropping the report.
+fn outcome<E>(delivery: Result<OwnedDeliveryResult, E>) -> Result<Delivery, KafkaError> {
+    match delivery {
+        Ok(Ok(d)) => Ok(d),
+        Ok(Err((e, _))) => Err(e),
+        Err(_) => Err(KafkaError::Canceled),
+    }
+}
@@ -0,0 +1,148 @@
+//! Reading partitions up to the end they have now: restoring state from a
+//! changelog, loading a table, checking what a demo wrote.
+//!
+//! A partition is done once the consumer's position reaches the high
+//! watermark read before it started. Waiting for a record at
+//! `high watermark - 1` is not enough: on a transactional topic the last
+//! offset is usually a commit marker, which is never delivered.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use rdkafka::consumer::{Consumer, ConsumerContext, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::{BorrowedMessage, Message};
+use rdkafka::{Offset, TopicPartitionList};
+use thiserror::Error;
+
+/// How long `recv()` waits before the positions are checked again: a
+/// partition ending with a marker gets no more records.
+const POLL: Duration = Duration::from_millis(200);
+
+/// High watermarks by (topic, partition): where reading stops.
+pub type Ends = BTreeMap<(String, i32), i64>;
+
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error(transparent)]
+    Kafka(#[from] KafkaError),
+    #[error("reading stalled before the end of {0:?}")]
+    Stalled(Vec<(String, i32)>),
+}
+
+/// Partition ids of `topic`, empty when it does not exist.
+pub fn partitions<C: ConsumerContext>(
+    consumer: &StreamConsumer<C>,
+    topic: &str,
+    timeout: Duration,
+) -> Result<Vec<i32>, KafkaError> {
+    let metadata = consumer.fetch_metadata(Some(topic), timeout)?;
+    Ok(metadata
+        .topics()
+        .iter()
+        .filter(|t| t.name() == topic && t.error().is_none())
+        .flat_map(|t| t.partitions())
+        .map(|p| p.id())
+        .collect())
+}
+
+/// Current ends of the `partitions` of `topic` that hold records.
+pub fn ends<C: ConsumerContext>(
+    consumer: &StreamConsumer<C>,
+    topic: &str,
+    partitions: &[i32],
+    timeout: Duration,
+) -> Result<Ends, KafkaError> {
+    let mut ends = Ends::new();
+    for &p in partitions {
+        let (low, high) = consumer.fetch_watermarks(topic, p, timeout)?;
+        if high > low {
+            ends.insert((topic.to_string(), p), high);
+        }
+    }
+    Ok(ends)
+}
+
+/// Hands out the records of a consumer until each partition of [`Ends`] is
+/// read to its end.
+pub struct ReadToEnd<'a, C: ConsumerContext + 'static> {
+    consumer: &'a StreamConsumer<C>,
+    ends: Ends,
+    timeout: Duration,
+}
+
+impl<'a, C: ConsumerContext + 'static> ReadToEnd<'a, C> {
+    /// Assigns the partitions of `ends` from the beginning, replacing the
+    /// assignment of `consumer`.
+    pub fn from_beginning(
+        consumer: &'a StreamConsumer<C>,
+        ends: Ends,
+        timeout: Duration,
+    ) -> Result<Self, KafkaError> {
+        let mut tpl = TopicPartitionList::new();
+        for (topic, p) in ends.keys() {
+            tpl.add_partition_offset(topic, *p, Offset::Beginning)?;
+        }
+        consumer.assign(&tpl)?;
+        Ok(Self::assigned(consumer, ends, timeout))
+    }
+
+    /// Reads with the assignment the caller made: records of assigned
+    /// partitions outside `ends` are handed out too.
+    pub fn assigned(consumer: &'a StreamConsumer<C>, ends: Ends, timeout: Duration) -> Self {
+        Self {
+            consumer,
+            ends,
+            timeout,
+        }
+    }
+
+    /// Partitions not read to their end yet.
+    pub fn remaining(&self) -> &Ends {
+        &self.ends
+    }
+
+    /// The next record, `None` once every partition reached its end. Fails
+    /// when no partition got closer to its end for the `timeout`.
+    pub async fn next(&mut self) -> Result<Option<BorrowedMessage<'a>>, ReadError> {
+        let mut waiting = Instant::now();
+        while !self.ends.is_empty() {
+            match tokio::time::timeout(POLL, self.consumer.recv()).await {
+                Ok(m) => {
+                    let m = m?;
+                    let key = (m.topic().to_string(), m.partition());
+                    if self
+                        .ends
+                        .get(&key)
+                        .is_some_and(|&end| m.offset() + 1 >= end)
+                    {
+                        self.ends.remove(&key);
+                    }
+                    return Ok(Some(m));
+                }
+                Err(_) => {
+                    if self.drop_reached()? {
+                        waiting = Instant::now();
+                    } else if waiting.elapsed() >= self.timeout {
+                        return Err(ReadError::Stalled(self.ends.keys().cloned().collect()));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Drops the partitions whose position reached their end without a
+    /// record there. Returns whether there were any.
+    fn drop_reached(&mut self) -> Result<bool, KafkaError> {
+        let position = self.consumer.position()?;
+        let before = self.ends.len();
+        self.ends.retain(|(topic, p), end| {
+            !matches!(
+                position.find_partition(topic, *p).map(|e| e.offset()),
+                Some(Offset::Offset(pos)) if pos >= *end
+            )
+        });
+        Ok(self.ends.len() < before)
+    }
+}
@@ -1,5 +1,6 @@
 use rdkafka::producer::FutureRecord;
 
+use crate::cloudevents::EncodedCloudEvent;
 use crate::config::PartitioningMode;
 
 #[derive(Debug, thiserror::Error)]
@@ -24,3 +25,14 @@ pub fn create_future_record<'a>(
     };
     Ok(record)
 }
+
+/// Same as [`create_future_record`], with the payload and headers of a CloudEvent.
+pub fn create_cloudevent_record<'a>(
+    maybe_key: Option<&'a str>,
+    cloud_event: &'a EncodedCloudEvent,
+    topic: &'a str,
+    partition_mode: PartitioningMode,
+) -> Result<FutureRecord<'a, str, [u8]>, BuildRecordError> {
+    let record = create_future_record(maybe_key, &cloud_event.payload, topic, partition_mode)?;
+    Ok(record.headers(cloud_event.headers.clone()))
+}
@@ -0,0 +1,370 @@
+//! Request-reply over Kafka.
+//!
+//! A [`Requester`] produces each request with a `reply-to` header, naming the
+//! topic to answer on, and a `correlation-id` header, then waits for the record
+//! of that topic carrying the same correlation id. [`serve`] is the other end:
+//! it consumes requests in a consumer group, calls a handler and produces the
+//! answer to the requested topic. Responders scale like any consumer group, up
+//! to the partition count of the request topic.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rdkafka::client::ClientContext;
+use rdkafka::consumer::{Consumer, ConsumerContext, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::{Header, Message, OwnedHeaders, OwnedMessage};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::{Offset, TopicPartitionList};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+
+use crate::shutdown::Shutdown;
+use crate::util::header;
+
+/// Header naming the topic a request must be answered on.
+pub const REPLY_TO_HEADER: &str = "reply-to";
+/// Header matching a reply to its request.
+pub const CORRELATION_ID_HEADER: &str = "correlation-id";
+
+/// How long to wait for the metadata and watermarks of the reply topic.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+/// Correlation ids of timed-out requests kept to recognize their late replies.
+const MAX_EXPIRED: usize = 10_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RequestError {
+    #[error("no reply within {0:?}")]
+    Timeout(Duration),
+    #[error(transparent)]
+    Kafka(#[from] KafkaError),
+    #[error("reply topic `{0}` does not exist")]
+    UnknownReplyTopic(String),
+    #[error("the reply listener stopped")]
+    ListenerStopped,
+}
+
+/// Replies read by a [`Requester`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReplyStats {
+    /// Handed to the request waiting for them.
+    pub matched: u64,
+    /// Arrived after their request timed out.
+    pub late: u64,
+    /// Not for this requester: another one shares the reply topic.
+    pub unknown: u64,
+}
+
+#[derive(Default)]
+struct Waiters {
+    pending: HashMap<String, oneshot::Sender<OwnedMessage>>,
+    expired: HashSet<String>,
+    expired_order: VecDeque<String>,
+    stats: ReplyStats,
+}
+
+impl Waiters {
+    fn expire(&mut self, id: String) {
+        if self.expired.insert(id.clone()) {
+            self.expired_order.push_back(id);
+        }
+        while self.expired_order.len() > MAX_EXPIRED {
+            if let Some(oldest) = self.expired_order.pop_front() {
+                self.expired.remove(&oldest);
+            }
+        }
+    }
+
+    fn dispatch(&mut self, m: OwnedMessage) {
+        let Some(id) = header(&m, CORRELATION_ID_HEADER) else {
+            self.stats.unknown += 1;
+            warn!(
+                partition = m.partition(),
+                offset = m.offset(),
+                "Reply without a correlation id"
+            );
+            return;
+        };
+        if let Some(waiter) = self.pending.remove(&id) {
+            self.stats.matched += 1;
+            let _ = waiter.send(m);
+        } else if self.expired.remove(&id) {
+            self.stats.late += 1;
+            warn!(correlation_id = %id, partition = m.partition(), offset = m.offset(), "⏰ Late reply, its request timed out");
+        } else {
+            self.stats.unknown += 1;
+            debug!(correlation_id = %id, "Reply for another requester");
+        }
+    }
+}
+
+/// A request waiting for its reply. Dropping it removes the request from the
+/// pending ones, also when the caller drops the future of
+/// [`Requester::request`] before it completes.
+struct Pending<'a> {
+    waiters: &'a Mutex<Waiters>,
+    id: String,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut waiters) = self.waiters.lock() {
+            waiters.pending.remove(&self.id);
+        }
+    }
+}
+
+/// Sends requests and waits for their replies.
+///
+/// A background task reads every partition of the reply topic, starting at its
+/// end when the requester is created, and hands each reply to the request with
+/// its correlation id. Several requesters can share a reply topic: each one
+/// reads all of it and ignores the replies of the others.
+pub struct Requester<C: ClientContext + 'static> {
+    producer: FutureProducer<C>,
+    reply_topic: String,
+    waiters: Arc<Mutex<Waiters>>,
+    listener: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl<C: ClientContext + 'static> Requester<C> {
+    /// Reads the replies of `reply_topic` with `consumer`, which must not
+    /// subscribe to anything: partitions are assigned directly, outside of any
+    /// consumer group.
+    pub fn new<X>(
+        producer: FutureProducer<C>,
+        consumer: StreamConsumer<X>,
+        reply_topic: &str,
+    ) -> Result<Self, RequestError>
+    where
+        X: ConsumerContext + 'static,
+    {
+        let metadata = consumer.fetch_metadata(Some(reply_topic), METADATA_TIMEOUT)?;
+        let partitions: Vec<i32> = metadata
+            .topics()
+            .iter()
+            .filter(|t| t.name() == reply_topic && t.error().is_none())
+            .flat_map(|t| t.partitions().iter().map(|p| p.id()))
+            .collect();
+        if partitions.is_empty() {
+            return Err(RequestError::UnknownReplyTopic(reply_topic.to_string()));
+        }
+        // Explicit offsets rather than `Offset::End`: a reply produced before the
+        // end is resolved would be skipped.
+        let mut tpl = TopicPartitionList::new();
+        for p in partitions {
+            let (_, high) = consumer.fetch_watermarks(reply_topic, p, METADATA_TIMEOUT)?;
+            tpl.add_partition_offset(reply_topic, p, Offset::Offset(high))?;
+        }
+        consumer.assign(&tpl)?;
+
+        let waiters = Arc::new(Mutex::new(Waiters::default()));
+        let listener = tokio::spawn({
+            let waiters = Arc::clone(&waiters);
+            async move {
+                loop {
+                    match consumer.recv().await {
+                        Ok(m) => waiters.lock().unwrap().dispatch(m.detach()),
+                        Err(e) => warn!(error = %e, "Reply read error"),
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            producer,
+            reply_topic: reply_topic.to_string(),
+            waiters,
+            listener: Mutex::new(Some(listener)),
+        })
+    }
+
+    pub fn reply_topic(&self) -> &str {
+        &self.reply_topic
+    }
+
+    /// Replies read so far.
+    pub fn stats(&self) -> ReplyStats {
+        self.waiters.lock().unwrap().stats
+    }
+
+    /// Requests still waiting for their reply.
+    pub fn in_flight(&self) -> usize {
+        self.waiters.lock().unwrap().pending.len()
+    }
+
+    /// Stops reading replies and waits until the reply consumer is dropped.
+    /// Requests still waiting fail with [`RequestError::ListenerStopped`].
+    pub async fn close(&self) {
+        let listener = self.listener.lock().unwrap().take();
+        if let Some(listener) = listener {
+            listener.abort();
+            let _ = listener.await;
+        }
+        self.waiters.lock().unwrap().pending.clear();
+    }
+
+    /// Sends `payload` to `topic` and waits up to `timeout`, delivery included,
+    /// for the reply. A reply arriving later is counted as late.
+    pub async fn request(
+        &self,
+        topic: &str,
+        key: Option<&str>,
+        payload: &[u8],
+        timeout: Duration,
+    ) -> Result<OwnedMessage, RequestError> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = oneshot::channel();
+        self.waiters.lock().unwrap().pending.insert(id.clone(), tx);
+        let _pending = Pending {
+            waiters: &self.waiters,
+            id: id.clone(),
+        };
+
+        let headers = OwnedHeaders::new()
+            .insert(Header {
+                key: REPLY_TO_HEADER,
+                value: Some(&self.reply_topic),
+            })
+            .insert(Header {
+                key: CORRELATION_ID_HEADER,
+                value: Some(&id),
+            });
+        let mut record = FutureRecord::to(topic).payload(payload).headers(headers);
+        if let Some(key) = key {
+            record = record.key(key);
+        }
+
+        let outcome = tokio::time::timeout(timeout, async {
+            self.producer
+                .send(record, Duration::from_secs(0))
+                .await
+                .map_err(|(e, _)| RequestError::Kafka(e))?;
+            rx.await.map_err(|_| RequestError::ListenerStopped)
+        })
+        .await;
+
+        match outcome {
+            Ok(result) => result,
+            Err(_) => {
+                // The reply may still come: remember the id to tell it apart.
+                let mut waiters = self.waiters.lock().unwrap();
+                waiters.pending.remove(&id);
+                waiters.expire(id);
+                Err(RequestError::Timeout(timeout))
+            }
+        }
+    }
+}
+
+impl<C: ClientContext + 'static> Drop for Requester<C> {
+    fn drop(&mut self) {
+        if let Some(listener) = self.listener.lock().unwrap().take() {
+            listener.abort();
+        }
+    }
+}
+
+/// A request read by [`serve`].
+#[derive(Debug)]
+pub struct Request {
+    pub message: OwnedMessage,
+    pub correlation_id: String,
+    pub reply_to: String,
+}
+
+/// Requests handled by [`serve`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ServeStats {
+    /// Answered, reply delivered.
+    pub replied: u64,
+    /// Answered, reply not delivered: the requester times out.
+    pub failed: u64,
+    /// Without a `reply-to` or `correlation-id` header, skipped.
+    pub invalid: u64,
+}
+
+/// Answers the requests read by `consumer` until shutdown: calls `handler` on
+/// each one and produces the payload it returns to the request's `reply-to`
+/// topic, with the same key and correlation id.
+///
+/// Requests are handled one at a time; run more responders in the same group
+/// to serve more. The offset of a request is stored once its reply is
+/// delivered or failed, so `consumer` should have `enable.auto.offset.store`
+/// off: a responder stopping mid-request leaves it to the next owner of the
+/// partition, which answers it again (the requester may have given up).
+pub async fn serve<X, C, F, Fut>(
+    consumer: &StreamConsumer<X>,
+    producer: &FutureProducer<C>,
+    shutdown: &Shutdown,
+    mut handler: F,
+) -> Result<ServeStats, RequestError>
+where
+    X: ConsumerContext + 'static,
+    C: ClientContext + 'static,
+    F: FnMut(&Request) -> Fut,
+    Fut: Future<Output = Vec<u8>>,
+{
+    let mut stats = ServeStats::default();
+    loop {
+        let m = tokio::select! {
+            m = consumer.recv() => match m {
+                Ok(m) => m.detach(),
+                Err(e) => {
+                    warn!(error = %e, "Request read error");
+                    continue;
+                }
+            },
+            _ = shutdown.requested() => break,
+        };
+        let (topic, partition, offset) = (m.topic().to_string(), m.partition(), m.offset());
+        let (Some(reply_to), Some(correlation_id)) = (
+            header(&m, REPLY_TO_HEADER),
+            header(&m, CORRELATION_ID_HEADER),
+        ) else {
+            stats.invalid += 1;
+            warn!(
+                partition,
+                offset, "Request without reply-to or correlation-id, skipped"
+            );
+            consumer.store_offset(&topic, partition, offset)?;
+            continue;
+        };
+
+        let request = Request {
+            message: m,
+            correlation_id,
+            reply_to,
+        };
+        let payload = handler(&request).await;
+
+        let headers = OwnedHeaders::new().insert(Header {
+            key: CORRELATION_ID_HEADER,
+            value: Some(&request.correlation_id),
+        });
+        let mut record = FutureRecord::to(&request.reply_to)
+            .payload(&payload)
+            .headers(headers);
+        if let Some(key) = request.message.key() {
+            record = record.key(key);
+        }
+        match producer.send(record, Duration::from_secs(0)).await {
+            Ok(_) => stats.replied += 1,
+            Err((e, _)) => {
+                stats.failed += 1;
+                error!(
+                    partition,
+                    offset,
+                    reply_to = %request.reply_to,
+                    correlation_id = %request.correlation_id,
+                    error = %e,
+                    "❌ Reply not delivered"
+                );
+            }
+        }
+        consumer.store_offset(&topic, partition, offset)?;
+    }
+    Ok(stats)
+}
@@ -0,0 +1,222 @@
+//! Delayed delivery: hold records until a time set by their producer.
+//!
+//! Kafka delivers a record as soon as it is written. A producer that wants it
+//! delivered later sets a `deliver-at` header (epoch millis) and writes it to a
+//! scheduling topic instead; a scheduler consumes that topic, keeps each record
+//! in a [`DelayQueue`] and republishes it to the target topic once it is due.
+//!
+//! The queue lives in a [`StateStore`], sorted by time bucket, then priority:
+//! releasing what is due is a range scan from the start of the store.
+
+use std::ops::Bound;
+use std::time::Duration;
+
+use rdkafka::message::{Header, Headers, Message, OwnedHeaders};
+use rdkafka::producer::FutureRecord;
+use serde::{Deserialize, Serialize};
+
+use crate::state::{Entries, Offsets, StateStore, StoreError};
+use crate::util::header;
+
+/// Header holding when a record is due, in epoch millis.
+pub const DELIVER_AT_HEADER: &str = "deliver-at";
+/// Header holding the priority of a record, `0` (default) to `255`: among the
+/// records due in the same bucket, the highest priority is released first.
+pub const PRIORITY_HEADER: &str = "priority";
+/// Header added on release: `topic/partition/offset` of the held record, to
+/// recognize the copies of a record released twice.
+pub const SCHEDULED_FROM_HEADER: &str = "scheduled-from";
+
+/// Prefix of the held records.
+const HELD: &[u8] = b"held:";
+/// First key after every held record.
+const HELD_END: &[u8] = b"held;";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScheduleError {
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error("held record: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Headers scheduling a record for `deliver_at` (epoch millis), with `priority`.
+pub fn schedule_headers(deliver_at: i64, priority: u8) -> OwnedHeaders {
+    OwnedHeaders::new()
+        .insert(Header {
+            key: DELIVER_AT_HEADER,
+            value: Some(&deliver_at.to_string()),
+        })
+        .insert(Header {
+            key: PRIORITY_HEADER,
+            value: Some(&priority.to_string()),
+        })
+}
+
+fn parsed_header<M: Message, T: std::str::FromStr>(m: &M, name: &str) -> Option<T> {
+    header(m, name)?.trim().parse().ok()
+}
+
+/// A record waiting for its time, with what it takes to republish it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Held {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub deliver_at: i64,
+    pub priority: u8,
+    pub key: Option<Vec<u8>>,
+    pub payload: Option<Vec<u8>>,
+    /// Every header of the record, `deliver-at` and `priority` included.
+    pub headers: Vec<(String, Option<Vec<u8>>)>,
+}
+
+impl Held {
+    /// Copies `m`. Without a valid `deliver-at` header, it is due at `now`.
+    pub fn from_message<M: Message>(m: &M, now: i64) -> Self {
+        let headers = m
+            .headers()
+            .map(|hs| {
+                hs.iter()
+                    .map(|h| (h.key.to_string(), h.value.map(<[u8]>::to_vec)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            topic: m.topic().to_string(),
+            partition: m.partition(),
+            offset: m.offset(),
+            deliver_at: parsed_header(m, DELIVER_AT_HEADER).unwrap_or(now),
+            priority: parsed_header(m, PRIORITY_HEADER).unwrap_or(0),
+            key: m.key().map(<[u8]>::to_vec),
+            payload: m.payload().map(<[u8]>::to_vec),
+            headers,
+        }
+    }
+
+    /// `topic/partition/offset` of the held record.
+    pub fn source(&self) -> String {
+        format!("{}/{}/{}", self.topic, self.partition, self.offset)
+    }
+
+    /// The record to republish to `topic`: same key, payload and headers, plus
+    /// a `scheduled-from` header.
+    pub fn record<'a>(&'a self, topic: &'a str) -> FutureRecord<'a, [u8], [u8]> {
+        let mut headers = OwnedHeaders::new_with_capacity(self.headers.len() + 1);
+        for (key, value) in &self.headers {
+            headers = headers.insert(Header {
+                key,
+                value: value.as_deref(),
+            });
+        }
+        let headers = headers.insert(Header {
+            key: SCHEDULED_FROM_HEADER,
+            value: Some(&self.source()),
+        });
+        let mut record = FutureRecord::to(topic).headers(headers);
+        if let Some(key) = &self.key {
+            record = record.key(key.as_slice());
+        }
+        if let Some(payload) = &self.payload {
+            record = record.payload(payload.as_slice());
+        }
+        record
+    }
+}
+
+/// Records held until they are due, in a [`StateStore`].
+///
+/// Time is cut into buckets of `bucket` and a record is released once the end
+/// of its bucket has passed: never early, at most one bucket late. Within a
+/// bucket, records come out by priority, then due time. The key of a record
+/// includes its source position, so holding a record read again after a crash
+/// overwrites the first copy instead of adding one.
+pub struct DelayQueue<S> {
+    store: S,
+    bucket_ms: i64,
+    len: usize,
+}
+
+impl<S: StateStore> DelayQueue<S> {
+    /// Wraps `store`, keeping what a previous run held in it.
+    pub fn new(store: S, bucket: Duration) -> Result<Self, StoreError> {
+        let len = store
+            .range(Bound::Included(HELD), Bound::Excluded(HELD_END))?
+            .len();
+        Ok(Self {
+            store,
+            bucket_ms: (bucket.as_millis() as i64).max(1),
+            len,
+        })
+    }
+
+    /// Number of records held.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn bucket_of(&self, at: i64) -> i64 {
+        at.max(0) / self.bucket_ms * self.bucket_ms
+    }
+
+    fn key(&self, held: &Held) -> Vec<u8> {
+        [
+            HELD,
+            &self.bucket_of(held.deliver_at).to_be_bytes()[..],
+            &[u8::MAX - held.priority],
+            &held.deliver_at.max(0).to_be_bytes()[..],
+            &held.partition.max(0).to_be_bytes()[..],
+            &held.offset.max(0).to_be_bytes()[..],
+            held.topic.as_bytes(),
+        ]
+        .concat()
+    }
+
+    /// Holds `held` until it is due.
+    pub fn hold(&mut self, held: &Held) -> Result<(), ScheduleError> {
+        let key = self.key(held);
+        if self.store.get(&key)?.is_none() {
+            self.len += 1;
+        }
+        self.store.put(&key, &serde_json::to_vec(held)?)?;
+        Ok(())
+    }
+
+    /// Up to `limit` records due at `now`, in release order, with their keys.
+    pub fn due(&self, now: i64, limit: usize) -> Result<Vec<(Vec<u8>, Held)>, ScheduleError> {
+        // Buckets starting before the current one have ended.
+        let upper = [HELD, &self.bucket_of(now).to_be_bytes()[..]].concat();
+        let mut entries: Entries = self
+            .store
+            .range(Bound::Included(HELD), Bound::Excluded(&upper))?;
+        entries.truncate(limit);
+        entries
+            .into_iter()
+            .map(|(k, v)| Ok((k, serde_json::from_slice(&v)?)))
+            .collect()
+    }
+
+    /// Forgets released records, by the keys [`due`](Self::due) returned.
+    pub fn release(&mut self, keys: &[Vec<u8>]) -> Result<(), StoreError> {
+        for key in keys {
+            self.store.delete(key)?;
+        }
+        self.len = self.len.saturating_sub(keys.len());
+        Ok(())
+    }
+
+    /// Makes the held and released records durable, with the offsets of the
+    /// records they cover: see [`StateStore::checkpoint`].
+    pub fn checkpoint(&mut self, offsets: &Offsets) -> Result<(), StoreError> {
+        self.store.checkpoint(offsets)
+    }
+
+    /// The offsets after the last records held, as of the last checkpoint.
+    pub fn checkpointed(&self) -> Result<Offsets, StoreError> {
+        self.store.checkpointed()
+    }
+}
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use rdkafka::consumer::{CommitMode, Consumer, ConsumerContext};
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::producer::{Producer, ProducerContext};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// How long producers wait for outstanding deliveries when they stop.
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Set once SIGINT (Ctrl+C) or SIGTERM is received.
+///
+/// Create it once with [`listen`](Self::listen) and `select!` on
+/// [`requested`](Self::requested) wherever the binary waits for work, so the
+/// current message is finished before stopping. A second signal exits at once,
+/// for when draining hangs.
+#[derive(Clone)]
+pub struct Shutdown {
+    rx: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn listen() -> Self {
+        let (tx, rx) = watch::channel(false);
+        tokio::spawn(async move {
+            let signal = wait_for_signal().await;
+            info!(
+                signal,
+                "🛑 Shutdown requested, finishing up (send it again to force exit)"
+            );
+            let _ = tx.send(true);
+            let signal = wait_for_signal().await;
+            warn!(signal, "🛑 Forced exit");
+            std::process::exit(130);
+        });
+        Self { rx }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Completes once shutdown has been requested. Cancel-safe, so it can be
+    /// polled in a `select!` loop.
+    pub async fn requested(&self) {
+        let mut rx = self.rx.clone();
+        let _ = rx.wait_for(|requested| *requested).await;
+    }
+}
+
+async fn wait_for_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut terminate = signal(SignalKind::terminate()).expect("cannot listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = terminate.recv() => "SIGTERM",
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "ctrl_c"
+    }
+}
+
+/// Commits the stored offsets and leaves the group, so the partitions are
+/// reassigned right away instead of after `session.timeout.ms`.
+///
+/// Only offsets the application stored are committed (with
+/// `enable.auto.offset.store=false`, what was processed), so this is safe in
+/// every commit mode.
+pub fn close_consumer<X, C>(consumer: &C, group_id: &str)
+where
+    X: ConsumerContext,
+    C: Consumer<X>,
+{
+    match consumer.commit_consumer_state(CommitMode::Sync) {
+        Ok(()) => info!(group = group_id, "✅ COMMIT (shutdown)"),
+        // Nothing stored since the last commit.
+        Err(KafkaError::ConsumerCommit(RDKafkaErrorCode::NoOffset)) => {}
+        Err(e) => warn!(group = group_id, error = %e, "Final commit failed"),
+    }
+    consumer.unsubscribe();
+    info!(group = group_id, "👋 Consumer stopped");
+}
+
+/// Waits up to `timeout` for queued messages to be delivered and returns how
+/// many were not.
+pub fn flush_producer<X, P>(producer: &P, timeout: Duration) -> usize
+where
+    X: ProducerContext,
+    P: Producer<X>,
+{
+    let queued = producer.in_flight_count();
+    if let Err(e) = producer.flush(timeout) {
+        warn!(error = %e, "Flush did not complete");
+    }
+    let undelivered = producer.in_flight_count().max(0) as usize;
+    if undelivered > 0 {
+        warn!(
+            queued,
+            undelivered,
+            timeout_ms = timeout.as_millis() as u64,
+            "📤 Messages left undelivered"
+        );
+    } else {
+        info!(queued, "📤 Producer flushed");
+    }
+    undelivered
+}
@@ -0,0 +1,435 @@
+//! Local key-value state of a stateful consumer.
+//!
+//! A [`StateStore`] keeps bytes by key, with ordered range scans, and records
+//! the input offsets its content reflects at each [`checkpoint`]. Two
+//! implementations: [`MemoryStore`], lost on exit, and [`DiskStore`], an
+//! embedded database file that survives restarts. Either one can be backed up
+//! to a compacted changelog topic and restored from it, which is how state
+//! follows a partition to another machine.
+//!
+//! [`checkpoint`]: StateStore::checkpoint
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::Path;
+use std::time::Duration;
+
+use rdkafka::client::ClientContext;
+use rdkafka::consumer::{Consumer, ConsumerContext, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::Message;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use redb::{Database, ReadableDatabase, ReadableTable, TableDefinition};
+
+use crate::pipeline::DeliveryPipeline;
+use crate::read_to_end::{self, ReadError, ReadToEnd};
+
+/// Values by key.
+const ENTRIES: TableDefinition<&[u8], &[u8]> = TableDefinition::new("entries");
+/// Checkpointed offsets by (topic, partition).
+const OFFSETS: TableDefinition<(&str, i32), i64> = TableDefinition::new("offsets");
+
+/// How long to wait for changelog deliveries, watermarks and records.
+const CHANGELOG_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("state database: {0}")]
+    Disk(#[from] redb::Error),
+    #[error(transparent)]
+    Kafka(#[from] KafkaError),
+    #[error("{failed} changelog records were not delivered")]
+    Undelivered { failed: usize },
+    #[error("restoring {topic}[{partition}] stalled before offset {end}")]
+    RestoreStalled {
+        topic: String,
+        partition: i32,
+        end: i64,
+    },
+}
+
+fn disk(e: impl Into<redb::Error>) -> StoreError {
+    StoreError::Disk(e.into())
+}
+
+/// Offsets by (topic, partition): the next record to read from each input
+/// partition, as committed to Kafka.
+pub type Offsets = BTreeMap<(String, i32), i64>;
+
+/// Key-value pairs, in key order.
+pub type Entries = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Key-value state with ordered keys and offset checkpoints.
+///
+/// Writes become durable at the next [`checkpoint`](Self::checkpoint), together
+/// with the offsets passed to it: after a crash, the store and its offsets
+/// match, so consuming from [`checkpointed`](Self::checkpointed) offsets neither
+/// skips nor repeats an update.
+pub trait StateStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError>;
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StoreError>;
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StoreError>;
+
+    /// Entries with keys between `from` and `to`, in key order.
+    fn range(&self, from: Bound<&[u8]>, to: Bound<&[u8]>) -> Result<Entries, StoreError>;
+
+    /// Makes every write so far durable, with `offsets` recorded as the input
+    /// positions they reflect. Offsets are merged per (topic, partition): a
+    /// checkpoint of one partition keeps what earlier ones recorded for the
+    /// others.
+    fn checkpoint(&mut self, offsets: &Offsets) -> Result<(), StoreError>;
+
+    /// The latest offset checkpointed for each (topic, partition).
+    fn checkpointed(&self) -> Result<Offsets, StoreError>;
+
+    /// Every entry, in key order.
+    fn entries(&self) -> Result<Entries, StoreError> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+}
+
+impl<S: StateStore + ?Sized> StateStore for Box<S> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        (**self).get(key)
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StoreError> {
+        (**self).put(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StoreError> {
+        (**self).delete(key)
+    }
+
+    fn range(&self, from: Bound<&[u8]>, to: Bound<&[u8]>) -> Result<Entries, StoreError> {
+        (**self).range(from, to)
+    }
+
+    fn checkpoint(&mut self, offsets: &Offsets) -> Result<(), StoreError> {
+        (**self).checkpoint(offsets)
+    }
+
+    fn checkpointed(&self) -> Result<Offsets, StoreError> {
+        (**self).checkpointed()
+    }
+}
+
+/// A [`StateStore`] in a `BTreeMap`: fast, and empty again after a restart
+/// unless restored from a changelog.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    offsets: Offsets,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StoreError> {
+        self.entries.insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StoreError> {
+        self.entries.remove(key);
+        Ok(())
+    }
+
+    fn range(&self, from: Bound<&[u8]>, to: Bound<&[u8]>) -> Result<Entries, StoreError> {
+        Ok(self
+            .entries
+            .range::<[u8], _>((from, to))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+
+    fn checkpoint(&mut self, offsets: &Offsets) -> Result<(), StoreError> {
+        self.offsets
+            .extend(offsets.iter().map(|(tp, offset)| (tp.clone(), *offset)));
+        Ok(())
+    }
+
+    fn checkpointed(&self) -> Result<Offsets, StoreError> {
+        Ok(self.offsets.clone())
+    }
+}
+
+/// A [`StateStore`] in an embedded database file.
+///
+/// Writes are buffered in memory and committed in one transaction, with the
+/// offsets, at each checkpoint: a crash loses the writes since the last
+/// checkpoint, and the offsets to replay them with.
+pub struct DiskStore {
+    db: Database,
+    /// Writes since the last checkpoint; `None` is a delete.
+    pending: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl DiskStore {
+    /// Opens the database at `path`, creating it when missing.
+    pub fn open(path: &Path) -> Result<Self, StoreError> {
+        let db = Database::create(path).map_err(disk)?;
+        let txn = db.begin_write().map_err(disk)?;
+        txn.open_table(ENTRIES).map_err(disk)?;
+        txn.open_table(OFFSETS).map_err(disk)?;
+        txn.commit().map_err(disk)?;
+        Ok(Self {
+            db,
+            pending: BTreeMap::new(),
+        })
+    }
+
+    /// Number of writes not checkpointed yet.
+    pub fn uncommitted(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl StateStore for DiskStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        if let Some(value) = self.pending.get(key) {
+            return Ok(value.clone());
+        }
+        let txn = self.db.begin_read().map_err(disk)?;
+        let table = txn.open_table(ENTRIES).map_err(disk)?;
+        Ok(table.get(key).map_err(disk)?.map(|v| v.value().to_vec()))
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StoreError> {
+        self.pending.insert(key.to_vec(), Some(value.to_vec()));
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StoreError> {
+        self.pending.insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    fn range(&self, from: Bound<&[u8]>, to: Bound<&[u8]>) -> Result<Entries, StoreError> {
+        let txn = self.db.begin_read().map_err(disk)?;
+        let table = txn.open_table(ENTRIES).map_err(disk)?;
+        let mut merged = BTreeMap::new();
+        for entry in table.range::<&[u8]>((from, to)).map_err(disk)? {
+            let (k, v) = entry.map_err(disk)?;
+            merged.insert(k.value().to_vec(), v.value().to_vec());
+        }
+        // Uncommitted writes win over what is on disk.
+        for (k, v) in self.pending.range::<[u8], _>((from, to)) {
+            match v {
+                Some(v) => merged.insert(k.clone(), v.clone()),
+                None => merged.remove(k),
+            };
+        }
+        Ok(merged.into_iter().collect())
+    }
+
+    fn checkpoint(&mut self, offsets: &Offsets) -> Result<(), StoreError> {
+        let txn = self.db.begin_write().map_err(disk)?;
+        {
+            let mut entries = txn.open_table(ENTRIES).map_err(disk)?;
+            for (k, v) in &self.pending {
+                match v {
+                    Some(v) => entries.insert(k.as_slice(), v.as_slice()).map_err(disk)?,
+                    None => entries.remove(k.as_slice()).map_err(disk)?,
+                };
+            }
+            let mut stored = txn.open_table(OFFSETS).map_err(disk)?;
+            for ((topic, partition), offset) in offsets {
+                stored
+                    .insert((topic.as_str(), *partition), *offset)
+                    .map_err(disk)?;
+            }
+        }
+        txn.commit().map_err(disk)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn checkpointed(&self) -> Result<Offsets, StoreError> {
+        let txn = self.db.begin_read().map_err(disk)?;
+        let table = txn.open_table(OFFSETS).map_err(disk)?;
+        let mut offsets = Offsets::new();
+        for entry in table.iter().map_err(disk)? {
+            let (k, v) = entry.map_err(disk)?;
+            let (topic, partition) = k.value();
+            offsets.insert((topic.to_string(), partition), v.value());
+        }
+        Ok(offsets)
+    }
+}
+
+/// One write to log to a changelog topic; `value: None` is a tombstone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// Wraps a store and remembers its writes, so the caller can produce them to
+/// the changelog topic with [`take_changes`](Self::take_changes).
+pub struct Logged<S> {
+    inner: S,
+    changes: Vec<Change>,
+}
+
+impl<S: StateStore> Logged<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            changes: Vec::new(),
+        }
+    }
+
+    /// The writes since the last call, in order.
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changes)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: StateStore> StateStore for Logged<S> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        self.inner.get(key)
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), StoreError> {
+        self.inner.put(key, value)?;
+        self.changes.push(Change {
+            key: key.to_vec(),
+            value: Some(value.to_vec()),
+        });
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), StoreError> {
+        self.inner.delete(key)?;
+        self.changes.push(Change {
+            key: key.to_vec(),
+            value: None,
+        });
+        Ok(())
+    }
+
+    fn range(&self, from: Bound<&[u8]>, to: Bound<&[u8]>) -> Result<Entries, StoreError> {
+        self.inner.range(from, to)
+    }
+
+    fn checkpoint(&mut self, offsets: &Offsets) -> Result<(), StoreError> {
+        self.inner.checkpoint(offsets)
+    }
+
+    fn checkpointed(&self) -> Result<Offsets, StoreError> {
+        self.inner.checkpointed()
+    }
+}
+
+/// Produces `changes` to partition `partition` of the changelog `topic` and
+/// waits for them to be delivered.
+pub async fn log_changes<C: ClientContext + 'static>(
+    producer: &FutureProducer<C>,
+    topic: &str,
+    partition: i32,
+    changes: &[Change],
+) -> Result<(), StoreError> {
+    let mut pipeline: DeliveryPipeline<()> = DeliveryPipeline::new(1_000);
+    let mut failed = 0;
+    for change in changes {
+        let mut record: FutureRecord<[u8], [u8]> = FutureRecord::to(topic)
+            .partition(partition)
+            .key(change.key.as_slice());
+        if let Some(value) = &change.value {
+            record = record.payload(value.as_slice());
+        }
+        for report in pipeline.send(producer, record, ()).await {
+            failed += usize::from(report.result.is_err());
+        }
+    }
+    for report in pipeline.drain(CHANGELOG_TIMEOUT).await {
+        failed += usize::from(report.result.is_err());
+    }
+    failed += pipeline.pending();
+    if failed > 0 {
+        return Err(StoreError::Undelivered { failed });
+    }
+    Ok(())
+}
+
+/// Writes every entry of `store` to partition `partition` of the changelog
+/// `topic`, e.g. to seed a changelog for a store that was not logged.
+///
+/// Returns the number of entries written. Keys deleted since an earlier backup
+/// are not removed from the changelog: use [`Logged`] to log deletes too.
+pub async fn backup<C: ClientContext + 'static>(
+    store: &dyn StateStore,
+    producer: &FutureProducer<C>,
+    topic: &str,
+    partition: i32,
+) -> Result<usize, StoreError> {
+    let changes: Vec<Change> = store
+        .entries()?
+        .into_iter()
+        .map(|(key, value)| Change {
+            key,
+            value: Some(value),
+        })
+        .collect();
+    log_changes(producer, topic, partition, &changes).await?;
+    Ok(changes.len())
+}
+
+/// Replays partition `partition` of the changelog `topic` into `store`, from
+/// the beginning up to the current high watermark: records are written,
+/// tombstones delete. `consumer` must not be in use by a group subscription:
+/// its assignment is replaced, then removed.
+///
+/// Returns the number of changelog records read.
+pub async fn restore<C: ConsumerContext + 'static>(
+    store: &mut dyn StateStore,
+    consumer: &StreamConsumer<C>,
+    topic: &str,
+    partition: i32,
+) -> Result<u64, StoreError> {
+    let ends = read_to_end::ends(consumer, topic, &[partition], CHANGELOG_TIMEOUT)?;
+    let Some(&end) = ends.values().next() else {
+        return Ok(0);
+    };
+    let mut reader = ReadToEnd::from_beginning(consumer, ends, CHANGELOG_TIMEOUT)?;
+
+    let mut records = 0;
+    loop {
+        let m = match reader.next().await {
+            Ok(Some(m)) => m,
+            Ok(None) => break,
+            Err(ReadError::Kafka(e)) => return Err(e.into()),
+            Err(ReadError::Stalled(_)) => {
+                return Err(StoreError::RestoreStalled {
+                    topic: topic.to_string(),
+                    partition,
+                    end,
+                });
+            }
+        };
+        records += 1;
+        if let Some(key) = m.key() {
+            match m.payload() {
+                Some(value) => store.put(key, value)?,
+                None => store.delete(key)?,
+            }
+        }
+    }
+    consumer.unassign()?;
+    Ok(records)
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider, SpanData, SpanExporter};
+use rdkafka::message::{Header, Headers, Message, OwnedHeaders};
+use serde_json::{Map, Value, json};
+
+pub const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4318/v1/traces";
+
+/// Where finished spans are sent.
+#[derive(Debug, Clone)]
+pub enum TraceExport {
+    /// OTLP over HTTP/protobuf, e.g. to the `jaeger` service in docker-compose.
+    Otlp(String),
+    /// One JSON object per span, appended to a local file.
+    File(String),
+}
+
+impl TraceExport {
+    /// `--trace-file` wins over `--otlp-endpoint`; `None` disables tracing export.
+    pub fn from_options(otlp_endpoint: Option<String>, trace_file: Option<String>) -> Option<Self> {
+        match (trace_file, otlp_endpoint) {
+            (Some(path), _) => Some(TraceExport::File(path)),
+            (None, Some(endpoint)) => Some(TraceExport::Otlp(endpoint)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Builds the tracer provider for `service_name` and installs the W3C
+/// trace-context propagator used by [`inject_context`] / [`extract_context`].
+///
+/// Keep the returned provider alive and call `shutdown()` before exiting so the
+/// last batch of spans is flushed.
+pub fn init_tracer_provider(
+    service_name: &str,
+    export: &TraceExport,
+) -> anyhow::Result<SdkTracerProvider> {
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let resource = Resource::builder()
+        .with_service_name(service_name.to_string())
+        .build();
+    let builder = SdkTracerProvider::builder().with_resource(resource);
+
+    let provider = match export {
+        TraceExport::Otlp(endpoint) => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()?;
+            builder.with_batch_exporter(exporter).build()
+        }
+        TraceExport::File(path) => {
+            let exporter = JsonFileExporter::create(path, service_name)?;
+            builder.with_simple_exporter(exporter).build()
+        }
+    };
+    Ok(provider)
+}
+
+pub fn tracer(provider: &SdkTracerProvider) -> SdkTracer {
+    provider.tracer("kafka_fundamentals")
+}
+
+/// Adds `traceparent` (and `tracestate` when non-empty) for `cx` to `headers`.
+pub fn inject_context(cx: &Context, headers: OwnedHeaders) -> OwnedHeaders {
+    let mut carrier: HashMap<String, String> = HashMap::new();
+    TraceContextPropagator::new().inject_context(cx, &mut carrier);
+    carrier.iter().fold(headers, |h, (key, value)| {
+        h.insert(Header {
+            key,
+            value: Some(value),
+        })
+    })
+}
+
+/// Reads the remote parent context from the message's `traceparent`/`tracestate`
+/// headers. Messages without them yield an empty context (a new trace starts).
+pub fn extract_context<M: Message>(m: &M) -> Context {
+    let carrier = HeaderExtractor::from_message(m);
+    TraceContextPropagator::new().extract(&carrier)
+}
+
+struct HeaderExtractor(HashMap<String, String>);
+
+impl HeaderExtractor {
+    fn from_message<M: Message>(m: &M) -> Self {
+        let map = m
+            .headers()
+            .map(|hs| {
+                hs.iter()
+                    .filter_map(|h| {
+                        let v = std::str::from_utf8(h.value?).ok()?;
+                        Some((h.key.to_ascii_lowercase(), v.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self(map)
+    }
+}
+
+impl Extractor for HeaderExtractor {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(&key.to_ascii_lowercase()).map(String::as_str)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(String::as_str).collect()
+    }
+}
+
+/// Span exporter writing one JSON line per span. Stand-in for a collector when
+/// none is running: `jq` over the file is enough to follow a trace.
+#[derive(Debug)]
+pub struct JsonFileExporter {
+    service_name: String,
+    out: Mutex<BufWriter<File>>,
+}
+
+impl JsonFileExporter {
+    pub fn create(path: &str, service_name: &str) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            service_name: service_name.to_string(),
+            out: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    fn to_json(&self, span: &SpanData) -> Value {
+        let attributes: Map<String, Value> = span
+            .attributes
+            .iter()
+            .map(|KeyValue { key, value, .. }| (key.to_string(), json!(value.to_string())))
+            .collect();
+        let parent = (span.parent_span_id != opentelemetry::trace::SpanId::INVALID)
+            .then(|| span.parent_span_id.to_string());
+        let start = unix_nanos(span.start_time);
+        let end = unix_nanos(span.end_time);
+        json!({
+            "service": self.service_name,
+            "trace_id": span.span_context.trace_id().to_string(),
+            "span_id": span.span_context.span_id().to_string(),
+            "parent_span_id": parent,
+            "parent_is_remote": span.parent_span_is_remote,
+            "name": span.name,
+            "kind": format!("{:?}", span.span_kind),
+            "start_unix_nano": start,
+            "end_unix_nano": end,
+            "duration_ms": end.saturating_sub(start) as f64 / 1_000_000.0,
+            "status": format!("{:?}", span.status),
+            "attributes": attributes,
+        })
+    }
+}
+
+impl SpanExporter for JsonFileExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let mut out = self.out.lock().unwrap();
+        for span in &batch {
+            writeln!(out, "{}", self.to_json(span))
+                .map_err(|e| OTelSdkError::InternalFailure(e.to_string()))?;
+        }
+        out.flush()
+            .map_err(|e| OTelSdkError::InternalFailure(e.to_string()))
+    }
+}
+
+fn unix_nanos(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,50 @@
+//! Small helpers used across the labs: the wall clock, durations given on the
+//! command line and header values.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rdkafka::message::{Headers, Message};
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid duration `{0}` (expected e.g. 500ms, 10s, 5m or 1h)")]
+pub struct InvalidDuration(pub String);
+
+/// Milliseconds since the epoch.
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+/// Parses `500ms`, `10s`, `5m`, `1h` or a plain number of milliseconds; a
+/// duration too long for an `i64` of milliseconds is invalid.
+pub fn parse_duration_ms(value: &str) -> Result<i64, InvalidDuration> {
+    let invalid = || InvalidDuration(value.to_string());
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "ms"),
+    };
+    let n: i64 = number.parse().map_err(|_| invalid())?;
+    let factor = match unit {
+        "ms" => 1,
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        _ => return Err(invalid()),
+    };
+    n.checked_mul(factor).ok_or_else(invalid)
+}
+
+/// [`parse_duration_ms`] as a [`Duration`].
+pub fn parse_duration(value: &str) -> Result<Duration, InvalidDuration> {
+    parse_duration_ms(value).map(|ms| Duration::from_millis(ms.max(0) as u64))
+}
+
+/// Value of header `name` of `m`, when it is valid UTF-8.
+pub fn header<M: Message>(m: &M, name: &str) -> Option<String> {
+    m.headers()?
+        .iter()
+        .find(|h| h.key == name)
+        .and_then(|h| std::str::from_utf8(h.value?).ok())
+        .map(str::to_string)
+}
@@ -0,0 +1,381 @@
+//! Windowed aggregation of `Event` values by key, on record timestamps.
+//!
+//! Time moves with the records, not the wall clock: the *stream time* of a
+//! [`WindowStore`] is the largest timestamp it has seen. A window closes once
+//! stream time passes its end plus the grace period; records that arrive later
+//! for a closed window are dropped, like in Kafka Streams.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::util::{InvalidDuration, parse_duration_ms};
+
+#[derive(Debug, thiserror::Error)]
+pub enum WindowError {
+    #[error("invalid window `{0}` (expected tumbling:SIZE, hopping:SIZE,ADVANCE or session:GAP)")]
+    InvalidSpec(String),
+    #[error(transparent)]
+    InvalidDuration(#[from] InvalidDuration),
+    #[error("unknown emit mode `{0}` (expected update or close)")]
+    UnknownEmitMode(String),
+    #[error("the snapshot was written for {found}, not {expected}")]
+    SpecChanged { found: String, expected: String },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// How records are grouped into windows. Durations are in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowSpec {
+    /// Fixed-size, non-overlapping windows aligned on the epoch.
+    Tumbling { size: i64 },
+    /// Fixed-size windows starting every `advance`; a record is in
+    /// `size / advance` of them.
+    Hopping { size: i64, advance: i64 },
+    /// Per-key activity periods: a session ends once no record of its key
+    /// arrived for `gap`.
+    Session { gap: i64 },
+}
+
+impl FromStr for WindowSpec {
+    type Err = WindowError;
+
+    /// `tumbling:1m`, `hopping:1m,10s` or `session:30s`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || WindowError::InvalidSpec(s.to_string());
+        let (kind, args) = s.split_once(':').ok_or_else(invalid)?;
+        let spec = match (kind, args.split_once(',')) {
+            ("tumbling", None) => WindowSpec::Tumbling {
+                size: parse_duration_ms(args)?,
+            },
+            ("hopping", Some((size, advance))) => WindowSpec::Hopping {
+                size: parse_duration_ms(size)?,
+                advance: parse_duration_ms(advance)?,
+            },
+            ("session", None) => WindowSpec::Session {
+                gap: parse_duration_ms(args)?,
+            },
+            _ => return Err(invalid()),
+        };
+        let valid = match spec {
+            WindowSpec::Tumbling { size } => size > 0,
+            WindowSpec::Hopping { size, advance } => advance > 0 && advance <= size,
+            WindowSpec::Session { gap } => gap > 0,
+        };
+        if valid { Ok(spec) } else { Err(invalid()) }
+    }
+}
+
+/// When window results are written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitMode {
+    /// After every record, with the window's totals so far.
+    Update,
+    /// Once per window, with its final totals, when it closes.
+    Close,
+}
+
+impl FromStr for EmitMode {
+    type Err = WindowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "update" => Ok(EmitMode::Update),
+            "close" => Ok(EmitMode::Close),
+            other => Err(WindowError::UnknownEmitMode(other.to_string())),
+        }
+    }
+}
+
+/// A time range in epoch milliseconds: `[start, end)` for tumbling and hopping
+/// windows, `[start, end]` (first and last record) for sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Window {
+    pub start: i64,
+    pub end: i64,
+}
+
+/// Count, sum, min and max of the values in a window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowTotals {
+    pub count: u64,
+    pub sum: i64,
+    pub min: i64,
+    pub max: i64,
+}
+
+impl WindowTotals {
+    fn new(value: i64) -> Self {
+        Self {
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        }
+    }
+
+    fn add(&mut self, value: i64) {
+        self.merge(&Self::new(value));
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+/// The totals of one key's window, as written to the output topic.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowResult {
+    pub key: String,
+    pub window: Window,
+    pub totals: WindowTotals,
+    /// `true` for the final result of a closed window.
+    pub closed: bool,
+}
+
+/// What [`WindowStore::add`] did with a record.
+#[derive(Debug)]
+pub enum Added {
+    /// Added to its open windows, whose new totals are returned. `late` when
+    /// the record is older than the stream time, but still within the grace
+    /// period of at least one window.
+    Accepted {
+        updated: Vec<WindowResult>,
+        late: bool,
+    },
+    /// Every window the record belongs to had already closed.
+    Dropped,
+    /// The record's offset was applied before, e.g. replayed after a restart.
+    Replayed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OpenWindow {
+    window: Window,
+    totals: WindowTotals,
+}
+
+/// Open windows of the keys of one partition, with the stream time and the
+/// last applied offset of that partition. Can be saved to and loaded from a
+/// snapshot file, so the windows survive a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowStore {
+    spec: WindowSpec,
+    grace: i64,
+    stream_time: Option<i64>,
+    applied: Option<i64>,
+    windows: BTreeMap<String, Vec<OpenWindow>>,
+}
+
+impl WindowStore {
+    pub fn new(spec: WindowSpec, grace: i64) -> Self {
+        Self {
+            spec,
+            grace,
+            stream_time: None,
+            applied: None,
+            windows: BTreeMap::new(),
+        }
+    }
+
+    /// Loads the snapshot at `path`, or starts empty when there is none. Fails
+    /// when the snapshot was written with another window spec or grace period.
+    pub fn load(path: &Path, spec: WindowSpec, grace: i64) -> Result<Self, WindowError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::new(spec, grace)),
+            Err(e) => return Err(e.into()),
+        };
+        let store: Self = serde_json::from_str(&text)?;
+        if store.spec != spec || store.grace != grace {
+            return Err(WindowError::SpecChanged {
+                found: format!("{:?} with grace {}ms", store.spec, store.grace),
+                expected: format!("{spec:?} with grace {grace}ms"),
+            });
+        }
+        Ok(store)
+    }
+
+    /// Writes a snapshot to `path`, replacing the previous one atomically.
+    pub fn save(&self, path: &Path) -> Result<(), WindowError> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn stream_time(&self) -> Option<i64> {
+        self.stream_time
+    }
+
+    /// Offset of the last record added, replayed or dropped.
+    pub fn applied(&self) -> Option<i64> {
+        self.applied
+    }
+
+    pub fn open_windows(&self) -> usize {
+        self.windows.values().map(Vec::len).sum()
+    }
+
+    /// Adds the `value` of the record at `offset`, with timestamp `ts`, to the
+    /// windows of `key`.
+    pub fn add(&mut self, offset: i64, key: &str, ts: i64, value: i64) -> Added {
+        if self.applied.is_some_and(|a| offset <= a) {
+            return Added::Replayed;
+        }
+        self.applied = Some(offset);
+        let late = self.stream_time.is_some_and(|t| ts < t);
+        let stream_time = self.stream_time.map_or(ts, |t| t.max(ts));
+        self.stream_time = Some(stream_time);
+
+        let updated = match self.spec {
+            WindowSpec::Session { gap } => self.add_to_session(key, ts, value, gap, stream_time),
+            _ => self.add_to_windows(key, ts, value, stream_time),
+        };
+        if updated.is_empty() {
+            Added::Dropped
+        } else {
+            Added::Accepted { updated, late }
+        }
+    }
+
+    /// Removes the windows that closed as stream time advanced and returns
+    /// their final results, oldest first.
+    pub fn close_expired(&mut self) -> Vec<WindowResult> {
+        let Some(stream_time) = self.stream_time else {
+            return Vec::new();
+        };
+        let mut closed = Vec::new();
+        for (key, windows) in self.windows.iter_mut() {
+            windows.retain(|w| {
+                if self.spec.close_time(w.window, self.grace) > stream_time {
+                    return true;
+                }
+                closed.push(WindowResult {
+                    key: key.clone(),
+                    window: w.window,
+                    totals: w.totals,
+                    closed: true,
+                });
+                false
+            });
+        }
+        self.windows.retain(|_, windows| !windows.is_empty());
+        closed.sort_by_key(|r| (r.window, r.key.clone()));
+        closed
+    }
+
+    fn add_to_windows(
+        &mut self,
+        key: &str,
+        ts: i64,
+        value: i64,
+        stream_time: i64,
+    ) -> Vec<WindowResult> {
+        let mut updated = Vec::new();
+        for window in self.spec.windows_of(ts) {
+            if self.spec.close_time(window, self.grace) <= stream_time {
+                continue;
+            }
+            let windows = self.windows.entry(key.to_string()).or_default();
+            let open = match windows.iter_mut().find(|w| w.window == window) {
+                Some(open) => {
+                    open.totals.add(value);
+                    open
+                }
+                None => {
+                    windows.push(OpenWindow {
+                        window,
+                        totals: WindowTotals::new(value),
+                    });
+                    windows.last_mut().expect("just pushed")
+                }
+            };
+            updated.push(WindowResult {
+                key: key.to_string(),
+                window,
+                totals: open.totals,
+                closed: false,
+            });
+        }
+        updated
+    }
+
+    /// Merges the record with every open session of `key` within `gap` of it.
+    fn add_to_session(
+        &mut self,
+        key: &str,
+        ts: i64,
+        value: i64,
+        gap: i64,
+        stream_time: i64,
+    ) -> Vec<WindowResult> {
+        let mut session = OpenWindow {
+            window: Window { start: ts, end: ts },
+            totals: WindowTotals::new(value),
+        };
+        if self.spec.close_time(session.window, self.grace) <= stream_time {
+            return Vec::new();
+        }
+        let windows = self.windows.entry(key.to_string()).or_default();
+        windows.retain(|w| {
+            if ts < w.window.start - gap || ts > w.window.end + gap {
+                return true;
+            }
+            session.window.start = session.window.start.min(w.window.start);
+            session.window.end = session.window.end.max(w.window.end);
+            session.totals.merge(&w.totals);
+            false
+        });
+        let result = WindowResult {
+            key: key.to_string(),
+            window: session.window,
+            totals: session.totals,
+            closed: false,
+        };
+        windows.push(session);
+        vec![result]
+    }
+}
+
+impl WindowSpec {
+    /// The time windows containing `ts`, oldest first. Empty for sessions,
+    /// which depend on the other records of the key.
+    fn windows_of(&self, ts: i64) -> Vec<Window> {
+        let (size, advance) = match *self {
+            WindowSpec::Tumbling { size } => (size, size),
+            WindowSpec::Hopping { size, advance } => (size, advance),
+            WindowSpec::Session { .. } => return Vec::new(),
+        };
+        let mut windows = Vec::new();
+        let mut start = ts - ts.rem_euclid(advance);
+        while start > ts - size {
+            windows.push(Window {
+                start,
+                end: start + size,
+            });
+            start -= advance;
+        }
+        windows.reverse();
+        windows
+    }
+
+    /// Stream time at which `window` closes and stops accepting records.
+    fn close_time(&self, window: Window, grace: i64) -> i64 {
+        match *self {
+            WindowSpec::Session { gap } => window.end + gap + grace,
+            _ => window.end + grace,
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use anyhow::{Context, Result, bail};
+use rdkafka::consumer::{Consumer, ConsumerContext, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::{Offset, TopicPartitionList};
+use shared::config::AppConfig;
+use shared::create_consumer_props;
+use shared::ingest::timestamp_millis;
+use shared::logging::{self, LogFormat};
+use shared::shutdown::Shutdown;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tools::dump::{DumpFormat, DumpRecord, DumpWriter};
+use tools::{METADATA_TIMEOUT, parse_partitions, select_partitions};
+use tracing::{info, warn};
+
+/// Where reading a partition starts or stops.
+#[derive(Debug, Clone, Copy)]
+enum Bound {
+    Offset(i64),
+    /// Epoch milliseconds, resolved with `offsets_for_times`.
+    Time(i64),
+}
+
+impl Bound {
+    fn parse_time(s: &str) -> Result<Self> {
+        timestamp_millis(s)
+            .map(Self::Time)
+            .with_context(|| format!("invalid time `{s}` (epoch millis or RFC 3339)"))
+    }
+}
+
+/// Offsets `[start, end)` to read from each partition.
+fn resolve_range(
+    consumer: &StreamConsumer<impl ConsumerContext>,
+    topic: &str,
+    partitions: &[i32],
+    from: Option<Bound>,
+    to: Option<Bound>,
+) -> Result<BTreeMap<i32, (i64, i64)>> {
+    let mut ranges = BTreeMap::new();
+    for &p in partitions {
+        let (low, high) = consumer.fetch_watermarks(topic, p, METADATA_TIMEOUT)?;
+        ranges.insert(p, (low, high));
+    }
+    // Offset of the first record at or after `ts`, or the high watermark.
+    let for_time = |ts: i64| -> Result<BTreeMap<i32, i64>> {
+        let mut tpl = TopicPartitionList::new();
+        for &p in partitions {
+            tpl.add_partition_offset(topic, p, Offset::Offset(ts))?;
+        }
+        let found = consumer.offsets_for_times(tpl, METADATA_TIMEOUT)?;
+        Ok(found
+            .elements()
+            .iter()
+            .map(|e| {
+                let offset = match e.offset() {
+                    Offset::Offset(o) => o,
+                    _ => ranges[&e.partition()].1,
+                };
+                (e.partition(), offset)
+            })
+            .collect())
+    };
+    let from = match from {
+        Some(Bound::Time(ts)) => Some(for_time(ts)?),
+        Some(Bound::Offset(o)) => Some(partitions.iter().map(|&p| (p, o)).collect()),
+        None => None,
+    };
+    let to = match to {
+        Some(Bound::Time(ts)) => Some(for_time(ts)?),
+        Some(Bound::Offset(o)) => Some(partitions.iter().map(|&p| (p, o)).collect()),
+        None => None,
+    };
+    for (p, (start, end)) in ranges.iter_mut() {
+        if let Some(from) = &from {
+            *start = from[p].max(*start);
+        }
+        if let Some(to) = &to {
+            *end = to[p].min(*end);
+        }
+    }
+    Ok(ranges)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "tools.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut topic: Option<String> = None;
+    let mut partitions: Option<Vec<i32>> = None;
+    let mut from: Option<Bound> = None;
+    let mut to: Option<Bound> = None;
+    let mut output: Option<String> = None;
+    let mut format: Option<DumpFormat> = None;
+    let mut max_messages: u64 = 0;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--topic" => {
+                if let Some(t) = args.next() {
+                    topic = Some(t);
+                }
+            }
+            "--partitions" => {
+                if let Some(list) = args.next() {
+                    partitions = Some(parse_partitions(&list)?);
+                }
+            }
+            "--from-offset" => {
+                if let Some(o) = args.next() {
+                    from = Some(Bound::Offset(o.parse()?));
+                }
+            }
+            "--to-offset" => {
+                if let Some(o) = args.next() {
+                    to = Some(Bound::Offset(o.parse()?));
+                }
+            }
+            "--from-time" => {
+                if let Some(t) = args.next() {
+                    from = Some(Bound::parse_time(&t)?);
+                }
+            }
+            "--to-time" => {
+                if let Some(t) = args.next() {
+                    to = Some(Bound::parse_time(&t)?);
+                }
+            }
+            "--output" => {
+                if let Some(p) = args.next() {
+                    output = Some(p);
+                }
+            }
+            "--format" => {
+                if let Some(f) = args.next() {
+                    format = Some(f.parse()?);
+                }
+            }
+            "--max-messages" => {
+                if let Some(n) = args.next() {
+                    max_messages = n.parse().unwrap_or(0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    let Some(output) = output else {
+        bail!("--output <file> is required (`-` for stdout)");
+    };
+    let format = format.unwrap_or_else(|| DumpFormat::from_path(Path::new(&output)));
+    let topic = topic.unwrap_or(cfg.topic.clone());
+
+    // Partitions are assigned, not subscribed, so the group only names the
+    // client; nothing is ever committed.
+    let group_id = cfg.group_id.clone().unwrap_or("kafka-tools".to_string());
+    let props = &[
+        ("bootstrap.servers", cfg.bootstrap_servers.clone()),
+        ("group.id", group_id),
+        ("enable.auto.commit", "false".to_string()),
+        ("enable.partition.eof", "false".to_string()),
+    ];
+    let consumer = create_consumer_props(props)?;
+
+    let partitions = select_partitions(consumer.client(), &topic, partitions.as_deref())?;
+
+    let ranges = resolve_range(&consumer, &topic, &partitions, from, to)?;
+    let mut remaining: BTreeMap<i32, i64> = BTreeMap::new();
+    let mut tpl = TopicPartitionList::new();
+    for (&p, &(start, end)) in &ranges {
+        info!(
+            partition = p,
+            start,
+            end,
+            records = (end - start).max(0),
+            "📐 range"
+        );
+        if start < end {
+            tpl.add_partition_offset(&topic, p, Offset::Offset(start))?;
+            remaining.insert(p, end);
+        }
+    }
+    consumer.assign(&tpl)?;
+
+    info!(
+        topic = %topic,
+        partitions = ?partitions,
+        output = %output,
+        format = ?format,
+        "🚀 dump started"
+    );
+
+    let mut writer = DumpWriter::create(&output, format)?;
+    let shutdown = Shutdown::listen();
+    let mut progress = tokio::time::interval(Duration::from_secs(5));
+    progress.tick().await; // the first tick completes immediately
+    let started = Instant::now();
+    let mut written: u64 = 0;
+
+    while !remaining.is_empty() && (max_messages == 0 || written < max_messages) {
+        tokio::select! {
+            msg = consumer.recv() => match msg {
+                Ok(m) => {
+                    let Some(&end) = remaining.get(&m.partition()) else {
+                        continue;
+                    };
+                    if m.offset() < end {
+                        writer.write(&DumpRecord::from_message(&m))?;
+                        written += 1;
+                    }
+                    if m.offset() + 1 >= end {
+                        remaining.remove(&m.partition());
+                    }
+                }
+                Err(e) => warn!(error = %e, "Read error"),
+            },
+            _ = progress.tick() => {
+                // Compaction or transaction markers can leave no record at
+                // `end - 1`; the position tells when a partition is done.
+                let position = consumer.position()?;
+                remaining.retain(|&p, end| {
+                    !matches!(
+                        position.find_partition(&topic, p).map(|e| e.offset()),
+                        Some(Offset::Offset(pos)) if pos >= *end
+                    )
+                });
+                info!(written, partitions_left = remaining.len(), "📊 progress");
+            }
+            _ = shutdown.requested() => break,
+        }
+    }
+    writer.finish()?;
+
+    info!(
+        written,
+        elapsed_secs = started.elapsed().as_secs_f64(),
+        output = %output,
+        complete = remaining.is_empty(),
+        "🏁 dump finished"
+    );
+    Ok(())
+}
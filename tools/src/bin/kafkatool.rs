@@ -0,0 +1,433 @@
+use anyhow::{Context, Result, bail};
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::{Header, Message, OwnedHeaders};
+use rdkafka::producer::FutureRecord;
+use rdkafka::{Offset, TopicPartitionList};
+use shared::config::AppConfig;
+use shared::context::LabContext;
+use shared::ingest::timestamp_millis;
+use shared::input;
+use shared::logging::{self, LogFormat};
+use shared::pipeline::{DeliveryPipeline, Report};
+use shared::shutdown::{self, Shutdown};
+use shared::{create_consumer_props, create_producer_props};
+use std::collections::BTreeSet;
+use std::env;
+use std::io::{self, Write};
+use tools::template::Template;
+use tools::{METADATA_TIMEOUT, parse_partitions, select_partitions};
+use tracing::{error, info, warn};
+
+const USAGE: &str = "usage: kafkatool <consume|produce|metadata|offsets|query-time> [options]
+
+common:      --config <file> --profile <name> --log-format <human|json> --topic <name>
+consume:     --partitions <0,1,..> --offset <beginning|end|stored|N|-N> --count <N> --exit
+             --format <template> --group <id>
+produce:     --partition <N> --key-delimiter <sep> --header <key=value>... --window <N>
+offsets:     --partitions <0,1,..>
+query-time:  --time <epoch millis|RFC 3339> --partitions <0,1,..>";
+
+/// Options of every subcommand; each one reads the fields it needs.
+struct Options {
+    topic: String,
+    partitions: Option<Vec<i32>>,
+    offset: Offset,
+    count: u64,
+    exit: bool,
+    template: Template,
+    group: Option<String>,
+    partition: Option<i32>,
+    key_delimiter: Option<String>,
+    headers: Vec<(String, String)>,
+    window: usize,
+    time: Option<i64>,
+    /// Whether `--topic` was given (`metadata` lists every topic otherwise).
+    topic_given: bool,
+}
+
+/// `beginning`, `end`, `stored`, an absolute offset, or `-N` for the last N records.
+fn parse_offset(s: &str) -> Result<Offset> {
+    Ok(match s {
+        "beginning" => Offset::Beginning,
+        "end" => Offset::End,
+        "stored" => Offset::Stored,
+        n if n.starts_with('-') => Offset::OffsetTail(n[1..].parse()?),
+        n => Offset::Offset(n.parse()?),
+    })
+}
+
+fn consumer(cfg: &AppConfig, opts: &Options) -> Result<StreamConsumer<LabContext>> {
+    let group_id = opts
+        .group
+        .clone()
+        .or(cfg.group_id.clone())
+        .unwrap_or("kafka-tools".to_string());
+    let reset = match opts.offset {
+        Offset::Beginning => "earliest",
+        _ => "latest",
+    };
+    let props = &[
+        ("bootstrap.servers", cfg.bootstrap_servers.clone()),
+        ("group.id", group_id),
+        // Only a `--group` member commits; assigned partitions leave no trace.
+        ("enable.auto.commit", opts.group.is_some().to_string()),
+        ("auto.offset.reset", reset.to_string()),
+        ("enable.partition.eof", opts.exit.to_string()),
+    ];
+    create_consumer_props(props)
+}
+
+async fn consume(cfg: &AppConfig, opts: &Options, shutdown: &Shutdown) -> Result<()> {
+    let consumer = consumer(cfg, opts)?;
+    match &opts.group {
+        Some(group) => {
+            consumer.subscribe(&[&opts.topic])?;
+            info!(topic = %opts.topic, group = %group, "🚀 consuming as a group member");
+        }
+        None => {
+            let partitions =
+                select_partitions(consumer.client(), &opts.topic, opts.partitions.as_deref())?;
+            let mut tpl = TopicPartitionList::new();
+            for &p in &partitions {
+                tpl.add_partition_offset(&opts.topic, p, opts.offset)?;
+            }
+            consumer.assign(&tpl)?;
+            info!(topic = %opts.topic, partitions = ?partitions, offset = ?opts.offset, "🚀 consuming");
+        }
+    }
+
+    let mut consumed: u64 = 0;
+    let mut at_end: BTreeSet<i32> = BTreeSet::new();
+    let mut buf = Vec::new();
+    while opts.count == 0 || consumed < opts.count {
+        tokio::select! {
+            msg = consumer.recv() => match msg {
+                Ok(m) => {
+                    buf.clear();
+                    opts.template.render(&m, &mut buf)?;
+                    io::stdout().write_all(&buf)?;
+                    consumed += 1;
+                    at_end.remove(&m.partition());
+                }
+                Err(KafkaError::PartitionEOF(p)) => {
+                    at_end.insert(p);
+                    let assigned = consumer.assignment()?.count();
+                    if assigned > 0 && at_end.len() >= assigned {
+                        break;
+                    }
+                }
+                Err(e) => warn!(error = %e, "Read error"),
+            },
+            _ = shutdown.requested() => break,
+        }
+    }
+    io::stdout().flush()?;
+    info!(consumed, "🏁 consume finished");
+    if let Some(group) = &opts.group {
+        shutdown::close_consumer(&consumer, group);
+    }
+    Ok(())
+}
+
+async fn produce(cfg: &AppConfig, opts: &Options, shutdown: &Shutdown) -> Result<()> {
+    let props = &[
+        ("bootstrap.servers", cfg.bootstrap_servers.clone()),
+        (
+            "compression.type",
+            cfg.compression.clone().unwrap_or("lz4".to_string()),
+        ),
+        ("linger.ms", cfg.linger_ms.unwrap_or(5).to_string()),
+        ("enable.idempotence", "true".to_string()),
+    ];
+    let producer = create_producer_props(props)?;
+    let mut lines = input::stdin_lines();
+    let mut pipeline: DeliveryPipeline<u64> = DeliveryPipeline::new(opts.window);
+    let mut delivered: u64 = 0;
+    let mut failed: u64 = 0;
+    let mut tally = |report: Report<u64>| match report.result {
+        Ok(_) => delivered += 1,
+        Err(e) => {
+            failed += 1;
+            error!(line = report.tag, error = %e, "❌ Delivery failed");
+        }
+    };
+    info!(topic = %opts.topic, partition = opts.partition, "🚀 producing lines from stdin");
+
+    let mut line_no: u64 = 0;
+    loop {
+        let line = tokio::select! {
+            line = lines.recv() => match line {
+                Some(line) => line?,
+                None => break,
+            },
+            Some(report) = pipeline.next_report(), if pipeline.pending() > 0 => {
+                tally(report);
+                continue;
+            }
+            _ = shutdown.requested() => break,
+        };
+        line_no += 1;
+        let (key, payload) = match &opts.key_delimiter {
+            Some(d) => match line.split_once(d.as_str()) {
+                Some((k, v)) => (Some(k), v),
+                None => {
+                    warn!(line = line_no, delimiter = %d, "⚠️ No key delimiter, sending without key");
+                    (None, line.as_str())
+                }
+            },
+            None => (None, line.as_str()),
+        };
+
+        let mut record: FutureRecord<str, str> = FutureRecord::to(&opts.topic).payload(payload);
+        if let Some(key) = key {
+            record = record.key(key);
+        }
+        if let Some(p) = opts.partition {
+            record = record.partition(p);
+        }
+        if !opts.headers.is_empty() {
+            let headers = opts.headers.iter().fold(OwnedHeaders::new(), |hs, (k, v)| {
+                hs.insert(Header {
+                    key: k,
+                    value: Some(v),
+                })
+            });
+            record = record.headers(headers);
+        }
+        pipeline
+            .send(&producer, record, line_no)
+            .await
+            .into_iter()
+            .for_each(&mut tally);
+    }
+
+    pipeline
+        .drain(shutdown::FLUSH_TIMEOUT)
+        .await
+        .into_iter()
+        .for_each(&mut tally);
+    info!(
+        delivered,
+        failed,
+        undelivered = pipeline.pending(),
+        "👋 Producer stopped"
+    );
+    Ok(())
+}
+
+fn metadata(cfg: &AppConfig, opts: &Options) -> Result<()> {
+    let consumer = consumer(cfg, opts)?;
+    let topic = opts.topic_given.then_some(opts.topic.as_str());
+    let metadata = consumer.fetch_metadata(topic, METADATA_TIMEOUT)?;
+
+    println!("Brokers ({}):", metadata.brokers().len());
+    for b in metadata.brokers() {
+        println!("  {:>4}  {}:{}", b.id(), b.host(), b.port());
+    }
+    let mut topics: Vec<_> = metadata.topics().iter().collect();
+    topics.sort_by_key(|t| t.name());
+    for t in topics {
+        if let Some(e) = t.error() {
+            println!("Topic {}: error {e:?}", t.name());
+            continue;
+        }
+        println!("Topic {} ({} partitions):", t.name(), t.partitions().len());
+        for p in t.partitions() {
+            let isr = p.isr();
+            // Replicas that fell out of sync are the interesting part.
+            let lagging: Vec<_> = p.replicas().iter().filter(|r| !isr.contains(r)).collect();
+            println!(
+                "  partition {:>3}  leader {:>4}  replicas {:?}  isr {:?}{}{}",
+                p.id(),
+                p.leader(),
+                p.replicas(),
+                isr,
+                if lagging.is_empty() {
+                    String::new()
+                } else {
+                    format!("  out-of-sync {lagging:?}")
+                },
+                p.error()
+                    .map(|e| format!("  error {e:?}"))
+                    .unwrap_or_default(),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn offsets(cfg: &AppConfig, opts: &Options) -> Result<()> {
+    let consumer = consumer(cfg, opts)?;
+    let partitions = select_partitions(consumer.client(), &opts.topic, opts.partitions.as_deref())?;
+    println!("{}", opts.topic);
+    println!(
+        "  {:>9}  {:>12}  {:>12}  {:>12}",
+        "partition", "low", "high", "messages"
+    );
+    let mut total = 0;
+    for p in partitions {
+        let (low, high) = consumer.fetch_watermarks(&opts.topic, p, METADATA_TIMEOUT)?;
+        total += high - low;
+        println!("  {p:>9}  {low:>12}  {high:>12}  {:>12}", high - low);
+    }
+    println!("  {:>9}  {:>12}  {:>12}  {total:>12}", "total", "", "");
+    Ok(())
+}
+
+fn query_time(cfg: &AppConfig, opts: &Options) -> Result<()> {
+    let Some(time) = opts.time else {
+        bail!("query-time needs --time <epoch millis|RFC 3339>");
+    };
+    let consumer = consumer(cfg, opts)?;
+    let partitions = select_partitions(consumer.client(), &opts.topic, opts.partitions.as_deref())?;
+    let mut tpl = TopicPartitionList::new();
+    for &p in &partitions {
+        tpl.add_partition_offset(&opts.topic, p, Offset::Offset(time))?;
+    }
+    let found = consumer.offsets_for_times(tpl, METADATA_TIMEOUT)?;
+    println!("{} at {time} ms", opts.topic);
+    for e in found.elements() {
+        // No record at or after the time: reading would start at the end.
+        let offset = match e.offset() {
+            Offset::Offset(o) => o.to_string(),
+            _ => "end".to_string(),
+        };
+        println!("  partition {:>3}  offset {offset}", e.partition());
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let Some(command) = args.next() else {
+        bail!("{USAGE}");
+    };
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "tools.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut topic: Option<String> = None;
+    let mut partitions: Option<Vec<i32>> = None;
+    let mut offset = Offset::End;
+    let mut count: u64 = 0;
+    let mut exit = false;
+    let mut template = "%s\\n".to_string();
+    let mut group: Option<String> = None;
+    let mut partition: Option<i32> = None;
+    let mut key_delimiter: Option<String> = None;
+    let mut headers = Vec::new();
+    let mut window: usize = 1_000;
+    let mut time: Option<i64> = None;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--topic" => {
+                if let Some(t) = args.next() {
+                    topic = Some(t);
+                }
+            }
+            "--partitions" => {
+                if let Some(list) = args.next() {
+                    partitions = Some(parse_partitions(&list)?);
+                }
+            }
+            "--offset" => {
+                if let Some(o) = args.next() {
+                    offset = parse_offset(&o).with_context(|| format!("invalid offset `{o}`"))?;
+                }
+            }
+            "--count" => {
+                if let Some(n) = args.next() {
+                    count = n.parse().unwrap_or(0);
+                }
+            }
+            "--exit" => exit = true,
+            "--format" => {
+                if let Some(f) = args.next() {
+                    template = f;
+                }
+            }
+            "--group" => {
+                if let Some(g) = args.next() {
+                    group = Some(g);
+                }
+            }
+            "--partition" => {
+                if let Some(p) = args.next() {
+                    partition = Some(p.parse()?);
+                }
+            }
+            "--key-delimiter" => {
+                if let Some(d) = args.next() {
+                    key_delimiter = Some(d);
+                }
+            }
+            "--header" => {
+                if let Some(h) = args.next() {
+                    let Some((k, v)) = h.split_once('=') else {
+                        bail!("invalid header `{h}` (expected key=value)");
+                    };
+                    headers.push((k.to_string(), v.to_string()));
+                }
+            }
+            "--window" => {
+                if let Some(n) = args.next() {
+                    window = n.parse().unwrap_or(window);
+                }
+            }
+            "--time" => {
+                if let Some(t) = args.next() {
+                    time = Some(timestamp_millis(&t).with_context(|| {
+                        format!("invalid time `{t}` (epoch millis or RFC 3339)")
+                    })?);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    let opts = Options {
+        topic_given: topic.is_some(),
+        topic: topic.unwrap_or(cfg.topic.clone()),
+        partitions,
+        offset,
+        count,
+        exit,
+        template: Template::parse(&template)
+            .with_context(|| format!("invalid --format `{template}`"))?,
+        group,
+        partition,
+        key_delimiter,
+        headers,
+        window,
+        time,
+    };
+
+    let shutdown = Shutdown::listen();
+    match command.as_str() {
+        "consume" => consume(&cfg, &opts, &shutdown).await,
+        "produce" => produce(&cfg, &opts, &shutdown).await,
+        "metadata" => metadata(&cfg, &opts),
+        "offsets" => offsets(&cfg, &opts),
+        "query-time" => query_time(&cfg, &opts),
+        other => bail!("unknown command `{other}`\n{USAGE}"),
+    }
+}
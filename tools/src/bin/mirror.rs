@@ -0,0 +1,484 @@
+use anyhow::{Context, Result, bail};
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::message::{Header, Message, OwnedHeaders};
+use rdkafka::mocking::MockCluster;
+use rdkafka::producer::{DefaultProducerContext, FutureRecord, Producer};
+use rdkafka::{Offset, TopicPartitionList};
+use shared::config::{AppConfig, PartitioningMode};
+use shared::context::LabContext;
+use shared::event::Event;
+use shared::logging::{self, LogFormat};
+use shared::mock::{Prefill, start_mock_cluster};
+use shared::pipeline::{DeliveryPipeline, Report};
+use shared::read_to_end::{self, Ends, ReadToEnd};
+use shared::record::create_future_record;
+use shared::shutdown::{self, Shutdown};
+use shared::{create_consumer_props, create_producer_props};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tools::dump::DumpRecord;
+use tools::mirror::{OffsetSync, OffsetSyncs, RenameRules};
+use tools::{METADATA_TIMEOUT, topic_partitions};
+use tracing::{error, info, warn};
+
+/// The source record a mirrored one came from.
+struct Origin {
+    topic: String,
+    partition: i32,
+    offset: i64,
+    target_topic: String,
+}
+
+/// Target partition for each source partition of a topic: the same number
+/// when the target topic has it, otherwise wrapped around, which still keeps
+/// each source partition's records in order.
+fn target_partition(source: i32, target_partitions: i32) -> i32 {
+    source % target_partitions
+}
+
+/// Stores `origin` for the source group's next commit (librdkafka commits the
+/// offset after it) and records the offset sync. Returns `false` on a failed
+/// delivery.
+fn on_report(
+    report: Report<Origin>,
+    consumer: &StreamConsumer<LabContext>,
+    syncs: &mut OffsetSyncs,
+    mirrored: &mut u64,
+) -> Result<bool> {
+    let origin = report.tag;
+    let delivery = match report.result {
+        Ok(d) => d,
+        Err(e) => {
+            error!(
+                topic = %origin.topic,
+                partition = origin.partition,
+                offset = origin.offset,
+                error = %e,
+                "❌ Delivery to target failed, stopping (source offsets stay uncommitted)"
+            );
+            return Ok(false);
+        }
+    };
+    *mirrored += 1;
+    // Fails only when the partition was revoked meanwhile; its new owner
+    // mirrors the record again.
+    if let Err(e) = consumer.store_offset(&origin.topic, origin.partition, origin.offset) {
+        warn!(topic = %origin.topic, partition = origin.partition, error = %e, "Offset not stored");
+    }
+    syncs.observe(OffsetSync {
+        source_topic: origin.topic,
+        source_partition: origin.partition,
+        source_offset: origin.offset,
+        target_topic: origin.target_topic,
+        target_partition: delivery.partition,
+        target_offset: delivery.offset,
+    })?;
+    Ok(true)
+}
+
+/// Prints where each partition of `group` should resume on the target.
+fn translate_group(
+    cfg: &AppConfig,
+    group: &str,
+    topics: &[String],
+    syncs: &OffsetSyncs,
+) -> Result<()> {
+    let props = &[
+        ("bootstrap.servers", cfg.bootstrap_servers.clone()),
+        ("group.id", group.to_string()),
+        ("enable.auto.commit", "false".to_string()),
+    ];
+    let consumer = create_consumer_props(props)?;
+    let mut tpl = TopicPartitionList::new();
+    for topic in topics {
+        for p in topic_partitions(consumer.client(), topic)? {
+            tpl.add_partition(topic, p);
+        }
+    }
+    let committed = consumer.committed_offsets(tpl, METADATA_TIMEOUT)?;
+    println!("group {group}");
+    for e in committed.elements() {
+        let Offset::Offset(c) = e.offset() else {
+            println!("  {}[{}]  no committed offset", e.topic(), e.partition());
+            continue;
+        };
+        match syncs.translate(e.topic(), e.partition(), c) {
+            Some((topic, partition, offset)) => println!(
+                "  {}[{}] @ {c}  ->  {topic}[{partition}] @ {offset}",
+                e.topic(),
+                e.partition()
+            ),
+            None => println!(
+                "  {}[{}] @ {c}  ->  no sync yet, start from the beginning",
+                e.topic(),
+                e.partition()
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Two in-process clusters for `--mock`: the source is pre-filled with
+/// `count` events, the target has each renamed topic with `partitions` partitions.
+struct MockClusters {
+    source: MockCluster<'static, DefaultProducerContext>,
+    target: MockCluster<'static, DefaultProducerContext>,
+}
+
+async fn start_mocks(
+    topics: &[String],
+    rules: &RenameRules,
+    partitions: i32,
+    count: u64,
+) -> Result<MockClusters> {
+    let source = start_mock_cluster(3, &topics[0], partitions)?;
+    let target = start_mock_cluster(3, &rules.apply(&topics[0]), partitions)?;
+    for topic in &topics[1..] {
+        source.create_topic(topic, partitions, 1)?;
+        target.create_topic(&rules.apply(topic), partitions, 1)?;
+    }
+
+    let mut prefill = Prefill::new(&source.bootstrap_servers())?;
+    let base_ts = 1_700_000_000_000i64;
+    for i in 0..count {
+        let evt = Event {
+            user_id: format!("user-{}", i % 20),
+            action: "mirror".to_string(),
+            value: i as i64,
+        };
+        let payload = serde_json::to_vec(&evt)?;
+        let topic = &topics[i as usize % topics.len()];
+        let seq = i.to_string();
+        let record =
+            create_future_record(Some(&evt.user_id), &payload, topic, PartitioningMode::Keyed)?
+                .timestamp(base_ts + i as i64)
+                .headers(OwnedHeaders::new().insert(Header {
+                    key: "seq",
+                    value: Some(&seq),
+                }));
+        prefill.send(record).await;
+    }
+    prefill.finish().await?;
+    info!(count, topics = ?topics, "🧪 Mock source pre-filled");
+    Ok(MockClusters { source, target })
+}
+
+/// Reads every topic back from both mock clusters and compares the records,
+/// ignoring offsets and topic names.
+async fn verify(clusters: &MockClusters, topics: &[String], rules: &RenameRules) -> Result<()> {
+    async fn read_all(bootstrap: String, topics: Vec<String>) -> Result<Vec<DumpRecord>> {
+        let consumer = create_consumer_props(&[
+            ("bootstrap.servers", bootstrap),
+            ("group.id", "mirror-verify".to_string()),
+            ("enable.auto.commit", "false".to_string()),
+        ])?;
+        let mut ends = Ends::new();
+        for topic in &topics {
+            let partitions = topic_partitions(consumer.client(), topic)?;
+            ends.append(&mut read_to_end::ends(
+                &consumer,
+                topic,
+                &partitions,
+                METADATA_TIMEOUT,
+            )?);
+        }
+        let mut reader = ReadToEnd::from_beginning(&consumer, ends, Duration::from_secs(10))?;
+        let mut records = Vec::new();
+        while let Some(m) = reader.next().await? {
+            records.push(DumpRecord::from_message(&m));
+        }
+        Ok(records)
+    }
+    let key = |r: &DumpRecord| {
+        (
+            r.partition,
+            r.timestamp,
+            r.key.clone(),
+            r.payload.clone(),
+            r.headers.clone(),
+        )
+    };
+    let mut source: Vec<_> = read_all(clusters.source.bootstrap_servers(), topics.to_vec())
+        .await?
+        .iter()
+        .map(key)
+        .collect();
+    let target_topics: Vec<String> = topics.iter().map(|t| rules.apply(t)).collect();
+    let mut target: Vec<_> = read_all(clusters.target.bootstrap_servers(), target_topics)
+        .await?
+        .iter()
+        .map(key)
+        .collect();
+    source.sort();
+    target.sort();
+    let matching = source.iter().zip(&target).filter(|(s, t)| s == t).count();
+    if source.len() == target.len() && matching == source.len() {
+        info!(
+            records = matching,
+            "🔍 Verified: target matches source (keys, payloads, headers, timestamps, partitions)"
+        );
+        Ok(())
+    } else {
+        bail!(
+            "target differs from source: {} source records, {} target records, {matching} matching",
+            source.len(),
+            target.len()
+        )
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut source_profile = "mirror.source".to_string();
+    let mut target_profile = "mirror.target".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut topics: Option<Vec<String>> = None;
+    let mut rules = RenameRules::default();
+    let mut offsets_file = PathBuf::from("mirror-offsets.jsonl");
+    let mut sync_every: i64 = 100;
+    let mut window: usize = 1_000;
+    let mut translate: Option<String> = None;
+    let mut mock = false;
+    let mut mock_messages: u64 = 5_000;
+    let mut partitions: i32 = 3;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--source-profile" => {
+                if let Some(p) = args.next() {
+                    source_profile = p;
+                }
+            }
+            "--target-profile" => {
+                if let Some(p) = args.next() {
+                    target_profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--topics" => {
+                if let Some(list) = args.next() {
+                    topics = Some(list.split(',').map(|t| t.trim().to_string()).collect());
+                }
+            }
+            "--rename" => {
+                if let Some(rule) = args.next() {
+                    rules.add(&rule)?;
+                }
+            }
+            "--offsets-file" => {
+                if let Some(p) = args.next() {
+                    offsets_file = PathBuf::from(p);
+                }
+            }
+            "--sync-every" => {
+                if let Some(n) = args.next() {
+                    sync_every = n.parse().unwrap_or(sync_every);
+                }
+            }
+            "--window" => {
+                if let Some(n) = args.next() {
+                    window = n.parse().unwrap_or(window);
+                }
+            }
+            "--translate-group" => {
+                if let Some(g) = args.next() {
+                    translate = Some(g);
+                }
+            }
+            "--mock" => mock = true,
+            "--messages" => {
+                if let Some(n) = args.next() {
+                    mock_messages = n.parse().unwrap_or(mock_messages);
+                }
+            }
+            "--partitions" => {
+                if let Some(n) = args.next() {
+                    partitions = n.parse().unwrap_or(partitions);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut source_cfg = AppConfig::from_file(&cfg_path, &source_profile);
+    let mut target_cfg = AppConfig::from_file(&cfg_path, &target_profile);
+    logging::init(log_format.or(source_cfg.log_format).unwrap_or_default());
+    let topics = topics.unwrap_or(vec![source_cfg.topic.clone()]);
+
+    if let Some(group) = translate {
+        let syncs = OffsetSyncs::load(&offsets_file)?;
+        return translate_group(&source_cfg, &group, &topics, &syncs);
+    }
+
+    // Keep both clusters alive until the end of main.
+    let clusters = if mock {
+        let clusters = start_mocks(&topics, &rules, partitions, mock_messages).await?;
+        source_cfg.bootstrap_servers = clusters.source.bootstrap_servers();
+        target_cfg.bootstrap_servers = clusters.target.bootstrap_servers();
+        // Syncs of throwaway clusters must not end up in the real translation file.
+        offsets_file =
+            env::temp_dir().join(format!("mirror-offsets-mock-{}.jsonl", std::process::id()));
+        Some(clusters)
+    } else {
+        None
+    };
+    if source_cfg.bootstrap_servers == target_cfg.bootstrap_servers
+        && let Some(t) = topics.iter().find(|t| rules.apply(t) == **t)
+    {
+        bail!(
+            "`{t}` would be mirrored onto itself; add a --rename rule when both profiles point at the same cluster"
+        );
+    }
+
+    let group_id = source_cfg.group_id.clone().unwrap_or("mirror".to_string());
+    let consumer_props = &[
+        ("bootstrap.servers", source_cfg.bootstrap_servers.clone()),
+        ("group.id", group_id.clone()),
+        ("auto.offset.reset", source_cfg.auto_offset_reset.clone()),
+        // librdkafka commits only what `on_report` stored, i.e. what the target
+        // acknowledged: periodically with auto-commit, else once at shutdown.
+        (
+            "enable.auto.commit",
+            source_cfg.enable_auto_commit.to_string(),
+        ),
+        ("enable.auto.offset.store", "false".to_string()),
+    ];
+    let consumer = create_consumer_props(consumer_props)?;
+    let refs: Vec<&str> = topics.iter().map(String::as_str).collect();
+    consumer.subscribe(&refs)?;
+
+    let producer_props = &[
+        ("bootstrap.servers", target_cfg.bootstrap_servers.clone()),
+        (
+            "compression.type",
+            target_cfg.compression.clone().unwrap_or("lz4".to_string()),
+        ),
+        ("linger.ms", target_cfg.linger_ms.unwrap_or(5).to_string()),
+        // Retries must not reorder records within a partition.
+        ("enable.idempotence", "true".to_string()),
+    ];
+    let producer = create_producer_props(producer_props)?;
+
+    // Partition count of every target topic, looked up once.
+    let mut target_partitions: HashMap<String, i32> = HashMap::new();
+    for topic in &topics {
+        let target = rules.apply(topic);
+        let count = topic_partitions(producer.client(), &target)?.len() as i32;
+        if count == 0 {
+            bail!("target topic `{target}` does not exist; create it first");
+        }
+        target_partitions.insert(target, count);
+    }
+
+    let mut syncs = OffsetSyncs::open(&offsets_file, sync_every)
+        .with_context(|| format!("cannot open {}", offsets_file.display()))?;
+    info!(
+        source = %source_cfg.bootstrap_servers,
+        target = %target_cfg.bootstrap_servers,
+        topics = ?topics,
+        mapping = ?topics.iter().map(|t| (t.clone(), rules.apply(t))).collect::<BTreeMap<_, _>>(),
+        group = %group_id,
+        offsets_file = %offsets_file.display(),
+        "🚀 mirror started"
+    );
+
+    let shutdown = Shutdown::listen();
+    let mut pipeline: DeliveryPipeline<Origin> = DeliveryPipeline::new(window);
+    let mut progress = tokio::time::interval(Duration::from_secs(5));
+    progress.tick().await; // the first tick completes immediately
+    let started = Instant::now();
+    let mut mirrored: u64 = 0;
+    let mut healthy = true;
+    let limit = if mock { mock_messages } else { 0 };
+
+    while healthy && (limit == 0 || mirrored < limit) {
+        tokio::select! {
+            msg = consumer.recv() => {
+                let m = match msg {
+                    Ok(m) => m,
+                    Err(e) => {
+                        warn!(error = %e, "Read error");
+                        continue;
+                    }
+                };
+                let target_topic = rules.apply(m.topic());
+                let partition = target_partition(m.partition(), target_partitions[&target_topic]);
+                let mut record: FutureRecord<[u8], [u8]> =
+                    FutureRecord::to(&target_topic).partition(partition);
+                if let Some(key) = m.key() {
+                    record = record.key(key);
+                }
+                if let Some(payload) = m.payload() {
+                    record = record.payload(payload);
+                }
+                if let Some(ts) = m.timestamp().to_millis() {
+                    record = record.timestamp(ts);
+                }
+                if let Some(headers) = m.headers() {
+                    record = record.headers(headers.detach());
+                }
+                let origin = Origin {
+                    topic: m.topic().to_string(),
+                    partition: m.partition(),
+                    offset: m.offset(),
+                    target_topic: target_topic.clone(),
+                };
+                for report in pipeline.send(&producer, record, origin).await {
+                    if healthy {
+                        healthy = on_report(report, &consumer, &mut syncs, &mut mirrored)?;
+                    }
+                }
+            }
+            Some(report) = pipeline.next_report(), if pipeline.pending() > 0 => {
+                if healthy {
+                    healthy = on_report(report, &consumer, &mut syncs, &mut mirrored)?;
+                }
+            }
+            _ = progress.tick() => {
+                syncs.flush()?;
+                info!(mirrored, pending = pipeline.pending(), "📊 progress");
+            }
+            _ = shutdown.requested() => break,
+        }
+    }
+
+    // Offsets are stored in send order, so after a failure nothing later may be stored.
+    for report in pipeline.drain(shutdown::FLUSH_TIMEOUT).await {
+        if healthy {
+            healthy = on_report(report, &consumer, &mut syncs, &mut mirrored)?;
+        }
+    }
+    syncs.flush()?;
+    let secs = started.elapsed().as_secs_f64();
+    info!(
+        mirrored,
+        undelivered = pipeline.pending(),
+        elapsed_secs = secs,
+        msgs_per_sec = (mirrored as f64 / secs).round(),
+        "🏁 mirror stopped"
+    );
+    shutdown::close_consumer(&consumer, &group_id);
+
+    if let Some(clusters) = &clusters {
+        verify(clusters, &topics, &rules).await?;
+        translate_group(&source_cfg, &group_id, &topics, &syncs)?;
+        std::fs::remove_file(&offsets_file)?;
+    }
+    if !healthy {
+        bail!("mirroring stopped after a failed delivery");
+    }
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use anyhow::{Result, bail};
+use rdkafka::producer::{FutureRecord, Producer};
+use shared::config::AppConfig;
+use shared::create_producer_props;
+use shared::logging::{self, LogFormat};
+use shared::pipeline::{DeliveryPipeline, Report};
+use shared::shutdown::{self, Shutdown};
+use std::env;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tools::dump::{DumpFormat, DumpReader};
+use tools::topic_partitions;
+use tracing::{error, info, warn};
+
+const PROGRESS_EVERY: Duration = Duration::from_secs(5);
+
+/// Source partition and offset of a restored record.
+type Source = (i32, i64);
+
+#[derive(Default)]
+struct Tally {
+    restored: u64,
+    failed: u64,
+}
+
+impl Tally {
+    fn observe(&mut self, report: Report<Source>) {
+        match report.result {
+            Ok(_) => self.restored += 1,
+            Err(e) => {
+                self.failed += 1;
+                error!(
+                    source_partition = report.tag.0,
+                    source_offset = report.tag.1,
+                    error = %e,
+                    "❌ Delivery failed"
+                );
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = env::args();
+    let mut cfg_path = "shared/config.toml".to_string();
+    let mut profile = "tools.default".to_string();
+    let mut log_format: Option<LogFormat> = None;
+    let mut input: Option<String> = None;
+    let mut format: Option<DumpFormat> = None;
+    let mut topic: Option<String> = None;
+    let mut keep_partitions = false;
+    let mut window: usize = 1_000;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--config" => {
+                if let Some(p) = args.next() {
+                    cfg_path = p;
+                }
+            }
+            "--profile" => {
+                if let Some(p) = args.next() {
+                    profile = p;
+                }
+            }
+            "--log-format" => {
+                if let Some(f) = args.next() {
+                    log_format = f.parse().ok();
+                }
+            }
+            "--input" => {
+                if let Some(p) = args.next() {
+                    input = Some(p);
+                }
+            }
+            "--format" => {
+                if let Some(f) = args.next() {
+                    format = Some(f.parse()?);
+                }
+            }
+            "--topic" => {
+                if let Some(t) = args.next() {
+                    topic = Some(t);
+                }
+            }
+            "--keep-partitions" => keep_partitions = true,
+            "--window" => {
+                if let Some(n) = args.next() {
+                    window = n.parse().unwrap_or(window);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let cfg = AppConfig::from_file(&cfg_path, &profile);
+    logging::init(log_format.or(cfg.log_format).unwrap_or_default());
+    let Some(input) = input else {
+        bail!("--input <file> is required");
+    };
+    let format = format.unwrap_or_else(|| DumpFormat::from_path(Path::new(&input)));
+    let topic = topic.unwrap_or(cfg.topic.clone());
+
+    let props = &[
+        ("bootstrap.servers", cfg.bootstrap_servers.clone()),
+        (
+            "compression.type",
+            cfg.compression.clone().unwrap_or("lz4".to_string()),
+        ),
+        ("linger.ms", cfg.linger_ms.unwrap_or(5).to_string()),
+        // Retries must not reorder records within a partition (see lab 5's ordering demo).
+        ("enable.idempotence", "true".to_string()),
+    ];
+    let producer = create_producer_props(props)?;
+
+    // With --keep-partitions a record for a partition the target lacks can only fail.
+    let target_partitions = topic_partitions(producer.client(), &topic)?.len() as i32;
+    if target_partitions == 0 {
+        bail!(
+            "topic `{topic}` does not exist; create it first (with the source's partition count to keep partitions)"
+        );
+    }
+
+    info!(
+        input = %input,
+        format = ?format,
+        topic = %topic,
+        target_partitions,
+        keep_partitions,
+        window,
+        "🚀 restore started"
+    );
+
+    let reader = DumpReader::open(&input, format)?;
+    let shutdown = Shutdown::listen();
+    let mut pipeline: DeliveryPipeline<Source> = DeliveryPipeline::new(window);
+    let mut tally = Tally::default();
+    let mut skipped: u64 = 0;
+    let started = Instant::now();
+    let mut last_progress = started;
+
+    let mut read_error = None;
+    for record in reader {
+        // Stop reading, but still wait for what was already sent.
+        let r = match record {
+            Ok(r) => r,
+            Err(e) => {
+                read_error = Some(e);
+                break;
+            }
+        };
+        if keep_partitions && r.partition >= target_partitions {
+            skipped += 1;
+            warn!(
+                partition = r.partition,
+                offset = r.offset,
+                target_partitions,
+                "⚠️ Skipping record for a partition the target topic does not have"
+            );
+            continue;
+        }
+
+        let mut out: FutureRecord<[u8], [u8]> = FutureRecord::to(&topic);
+        if let Some(key) = &r.key {
+            out = out.key(key);
+        }
+        if let Some(payload) = &r.payload {
+            out = out.payload(payload);
+        }
+        if let Some(ts) = r.timestamp {
+            out = out.timestamp(ts);
+        }
+        if let Some(headers) = r.owned_headers() {
+            out = out.headers(headers);
+        }
+        if keep_partitions {
+            out = out.partition(r.partition);
+        }
+
+        let reports = pipeline.send(&producer, out, (r.partition, r.offset)).await;
+        reports.into_iter().for_each(|r| tally.observe(r));
+
+        if shutdown.is_requested() {
+            break;
+        }
+        if last_progress.elapsed() >= PROGRESS_EVERY {
+            info!(
+                restored = tally.restored,
+                failed = tally.failed,
+                pending = pipeline.pending(),
+                "📊 progress"
+            );
+            last_progress = Instant::now();
+        }
+    }
+
+    pipeline
+        .drain(shutdown::FLUSH_TIMEOUT)
+        .await
+        .into_iter()
+        .for_each(|r| tally.observe(r));
+    let undelivered = pipeline.pending();
+    info!(
+        restored = tally.restored,
+        failed = tally.failed,
+        skipped,
+        undelivered,
+        elapsed_secs = started.elapsed().as_secs_f64(),
+        "🏁 restore finished"
+    );
+    match read_error {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,421 @@
+//! The file format written by `dump` and read by `restore`.
+//!
+//! Two encodings of the same records:
+//! - JSON Lines, one record per line, readable and easy to edit by hand. Keys,
+//!   payloads and header values are strings when they are valid UTF-8 and
+//!   base64 (`key_b64`, `payload_b64`, `value_b64`) otherwise.
+//! - A compact binary format: the [`MAGIC`] bytes followed by length-prefixed,
+//!   big-endian records (see [`DumpWriter::write`]).
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rdkafka::message::{Header, Headers, Message, OwnedHeaders};
+use serde::{Deserialize, Serialize};
+
+/// First bytes of a binary dump.
+pub const MAGIC: &[u8; 6] = b"KDUMP\x01";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    Jsonl,
+    Binary,
+}
+
+impl FromStr for DumpFormat {
+    type Err = DumpError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" | "json" => Ok(Self::Jsonl),
+            "binary" | "bin" => Ok(Self::Binary),
+            other => Err(DumpError::UnknownFormat(other.to_string())),
+        }
+    }
+}
+
+impl DumpFormat {
+    /// `.kdump` and `.bin` files are binary, anything else JSON Lines.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("kdump" | "bin") => Self::Binary,
+            _ => Self::Jsonl,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DumpError {
+    #[error("unknown dump format `{0}` (expected jsonl or binary)")]
+    UnknownFormat(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("line {line}: {source}")]
+    Json {
+        line: u64,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("line {line}: invalid base64 in `{field}`")]
+    Base64 { line: u64, field: &'static str },
+    #[error("not a binary dump (bad magic bytes)")]
+    BadMagic,
+    #[error("record {record}: file ends in the middle of a record")]
+    Truncated { record: u64 },
+}
+
+/// One record as captured from the topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpRecord {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    /// Epoch milliseconds (`CreateTime` or `LogAppendTime`).
+    pub timestamp: Option<i64>,
+    pub key: Option<Vec<u8>>,
+    pub payload: Option<Vec<u8>>,
+    pub headers: Vec<(String, Option<Vec<u8>>)>,
+}
+
+impl DumpRecord {
+    pub fn from_message<M: Message>(m: &M) -> Self {
+        let headers = m
+            .headers()
+            .map(|hs| {
+                hs.iter()
+                    .map(|h| (h.key.to_string(), h.value.map(<[u8]>::to_vec)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            topic: m.topic().to_string(),
+            partition: m.partition(),
+            offset: m.offset(),
+            timestamp: m.timestamp().to_millis(),
+            key: m.key().map(<[u8]>::to_vec),
+            payload: m.payload().map(<[u8]>::to_vec),
+            headers,
+        }
+    }
+
+    /// The headers as librdkafka wants them for producing; `None` when there are none.
+    pub fn owned_headers(&self) -> Option<OwnedHeaders> {
+        if self.headers.is_empty() {
+            return None;
+        }
+        let headers = self.headers.iter().fold(
+            OwnedHeaders::new_with_capacity(self.headers.len()),
+            |headers, (key, value)| {
+                headers.insert(Header {
+                    key,
+                    value: value.as_deref(),
+                })
+            },
+        );
+        Some(headers)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonRecord {
+    topic: String,
+    partition: i32,
+    offset: i64,
+    timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_b64: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload_b64: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<JsonHeader>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonHeader {
+    key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value_b64: Option<String>,
+}
+
+/// Splits bytes into the (text, base64) pair of JSON fields.
+fn to_json(bytes: Option<&[u8]>) -> (Option<String>, Option<String>) {
+    match bytes.map(std::str::from_utf8) {
+        None => (None, None),
+        Some(Ok(text)) => (Some(text.to_string()), None),
+        Some(Err(_)) => (None, bytes.map(|b| BASE64.encode(b))),
+    }
+}
+
+fn from_json(
+    line: u64,
+    field: &'static str,
+    text: Option<String>,
+    b64: Option<String>,
+) -> Result<Option<Vec<u8>>, DumpError> {
+    match (text, b64) {
+        (Some(text), _) => Ok(Some(text.into_bytes())),
+        (None, Some(b64)) => BASE64
+            .decode(b64)
+            .map(Some)
+            .map_err(|_| DumpError::Base64 { line, field }),
+        (None, None) => Ok(None),
+    }
+}
+
+impl From<&DumpRecord> for JsonRecord {
+    fn from(r: &DumpRecord) -> Self {
+        let (key, key_b64) = to_json(r.key.as_deref());
+        let (payload, payload_b64) = to_json(r.payload.as_deref());
+        let headers = r
+            .headers
+            .iter()
+            .map(|(key, value)| {
+                let (value, value_b64) = to_json(value.as_deref());
+                JsonHeader {
+                    key: key.clone(),
+                    value,
+                    value_b64,
+                }
+            })
+            .collect();
+        Self {
+            topic: r.topic.clone(),
+            partition: r.partition,
+            offset: r.offset,
+            timestamp: r.timestamp,
+            key,
+            key_b64,
+            payload,
+            payload_b64,
+            headers,
+        }
+    }
+}
+
+impl JsonRecord {
+    fn into_record(self, line: u64) -> Result<DumpRecord, DumpError> {
+        let headers = self
+            .headers
+            .into_iter()
+            .map(|h| Ok((h.key, from_json(line, "value", h.value, h.value_b64)?)))
+            .collect::<Result<_, DumpError>>()?;
+        Ok(DumpRecord {
+            topic: self.topic,
+            partition: self.partition,
+            offset: self.offset,
+            timestamp: self.timestamp,
+            key: from_json(line, "key", self.key, self.key_b64)?,
+            payload: from_json(line, "payload", self.payload, self.payload_b64)?,
+            headers,
+        })
+    }
+}
+
+/// Writes records to a file, or to stdout for the path `-`.
+pub struct DumpWriter {
+    out: BufWriter<Box<dyn Write>>,
+    format: DumpFormat,
+}
+
+impl DumpWriter {
+    pub fn create(path: &str, format: DumpFormat) -> io::Result<Self> {
+        let out: Box<dyn Write> = match path {
+            "-" => Box::new(io::stdout()),
+            path => Box::new(File::create(path)?),
+        };
+        let mut out = BufWriter::new(out);
+        if format == DumpFormat::Binary {
+            out.write_all(MAGIC)?;
+        }
+        Ok(Self { out, format })
+    }
+
+    /// Binary layout of a record, all integers big-endian, byte strings
+    /// prefixed with their length (`-1` for null):
+    ///
+    /// `topic: u16 len + bytes | partition: i32 | offset: i64 |
+    /// timestamp: i64 (-1 = none) | key: i32 len + bytes |
+    /// payload: i32 len + bytes | header count: u32 |
+    /// per header: u16 len + key bytes, i32 len + value bytes`
+    pub fn write(&mut self, r: &DumpRecord) -> io::Result<()> {
+        match self.format {
+            DumpFormat::Jsonl => {
+                serde_json::to_writer(&mut self.out, &JsonRecord::from(r))?;
+                self.out.write_all(b"\n")
+            }
+            DumpFormat::Binary => {
+                write_short_str(&mut self.out, &r.topic)?;
+                self.out.write_all(&r.partition.to_be_bytes())?;
+                self.out.write_all(&r.offset.to_be_bytes())?;
+                self.out
+                    .write_all(&r.timestamp.unwrap_or(-1).to_be_bytes())?;
+                write_bytes(&mut self.out, r.key.as_deref())?;
+                write_bytes(&mut self.out, r.payload.as_deref())?;
+                self.out
+                    .write_all(&(r.headers.len() as u32).to_be_bytes())?;
+                for (key, value) in &r.headers {
+                    write_short_str(&mut self.out, key)?;
+                    write_bytes(&mut self.out, value.as_deref())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn write_short_str(out: &mut impl Write, s: &str) -> io::Result<()> {
+    let len = u16::try_from(s.len())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "string longer than 64 KiB"))?;
+    out.write_all(&len.to_be_bytes())?;
+    out.write_all(s.as_bytes())
+}
+
+fn write_bytes(out: &mut impl Write, bytes: Option<&[u8]>) -> io::Result<()> {
+    match bytes {
+        Some(b) => {
+            out.write_all(&(b.len() as i32).to_be_bytes())?;
+            out.write_all(b)
+        }
+        None => out.write_all(&(-1i32).to_be_bytes()),
+    }
+}
+
+/// Reads the records of a dump in file order.
+pub struct DumpReader {
+    input: BufReader<File>,
+    format: DumpFormat,
+    /// Lines (JSON) or records (binary) read so far.
+    position: u64,
+}
+
+impl DumpReader {
+    pub fn open(path: &str, format: DumpFormat) -> Result<Self, DumpError> {
+        let mut input = BufReader::new(File::open(path)?);
+        if format == DumpFormat::Binary {
+            let mut magic = [0u8; MAGIC.len()];
+            input
+                .read_exact(&mut magic)
+                .map_err(|_| DumpError::BadMagic)?;
+            if &magic != MAGIC {
+                return Err(DumpError::BadMagic);
+            }
+        }
+        Ok(Self {
+            input,
+            format,
+            position: 0,
+        })
+    }
+
+    fn next_json(&mut self) -> Result<Option<DumpRecord>, DumpError> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            self.position += 1;
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+        let line_no = self.position;
+        let json: JsonRecord = serde_json::from_str(&line).map_err(|source| DumpError::Json {
+            line: line_no,
+            source,
+        })?;
+        json.into_record(line_no).map(Some)
+    }
+
+    fn next_binary(&mut self) -> Result<Option<DumpRecord>, DumpError> {
+        // A clean end of file is only allowed between records.
+        if self.input.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        self.position += 1;
+        let record = self.position;
+        let truncated = |e: io::Error| match e.kind() {
+            ErrorKind::UnexpectedEof => DumpError::Truncated { record },
+            _ => DumpError::Io(e),
+        };
+        let topic = read_short_str(&mut self.input).map_err(truncated)?;
+        let partition = i32::from_be_bytes(read_array(&mut self.input).map_err(truncated)?);
+        let offset = i64::from_be_bytes(read_array(&mut self.input).map_err(truncated)?);
+        let timestamp = i64::from_be_bytes(read_array(&mut self.input).map_err(truncated)?);
+        let key = read_bytes(&mut self.input).map_err(truncated)?;
+        let payload = read_bytes(&mut self.input).map_err(truncated)?;
+        let count = u32::from_be_bytes(read_array(&mut self.input).map_err(truncated)?);
+        // Counts and lengths come from the file: nothing is allocated from
+        // them up front, so a corrupt one ends in `Truncated`, not an abort.
+        let mut headers = Vec::new();
+        for _ in 0..count {
+            let key = read_short_str(&mut self.input).map_err(truncated)?;
+            headers.push((key, read_bytes(&mut self.input).map_err(truncated)?));
+        }
+        Ok(Some(DumpRecord {
+            topic,
+            partition,
+            offset,
+            timestamp: (timestamp >= 0).then_some(timestamp),
+            key,
+            payload,
+            headers,
+        }))
+    }
+}
+
+impl Iterator for DumpReader {
+    type Item = Result<DumpRecord, DumpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.format {
+            DumpFormat::Jsonl => self.next_json(),
+            DumpFormat::Binary => self.next_binary(),
+        }
+        .transpose()
+    }
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Reads `len` bytes, growing the buffer only as they arrive.
+fn read_len(input: &mut impl Read, len: u64) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    input.take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+fn read_short_str(input: &mut impl Read) -> io::Result<String> {
+    let len = u16::from_be_bytes(read_array(input)?);
+    let buf = read_len(input, len.into())?;
+    String::from_utf8(buf).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+fn read_bytes(input: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let len = i32::from_be_bytes(read_array(input)?);
+    if len < 0 {
+        return Ok(None);
+    }
+    read_len(input, len as u64).map(Some)
+}
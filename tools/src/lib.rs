@@ -0,0 +1,54 @@
+//! Helpers shared by the tool binaries (`dump`, `restore`, `kafkatool`, `mirror`).
+
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use rdkafka::client::{Client, ClientContext};
+
+pub mod dump;
+pub mod mirror;
+pub mod template;
+
+/// How long to wait for metadata, watermark and offset lookups.
+pub const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Partition ids of `topic`, empty when the topic does not exist.
+pub fn topic_partitions<C: ClientContext>(client: &Client<C>, topic: &str) -> Result<Vec<i32>> {
+    let metadata = client.fetch_metadata(Some(topic), METADATA_TIMEOUT)?;
+    Ok(metadata
+        .topics()
+        .iter()
+        .find(|t| t.name() == topic && t.error().is_none())
+        .map(|t| t.partitions().iter().map(|p| p.id()).collect())
+        .unwrap_or_default())
+}
+
+/// The `requested` partitions of `topic` (all of them when `None`), failing
+/// when the topic or one of the partitions does not exist.
+pub fn select_partitions<C: ClientContext>(
+    client: &Client<C>,
+    topic: &str,
+    requested: Option<&[i32]>,
+) -> Result<Vec<i32>> {
+    let existing = topic_partitions(client, topic)?;
+    if existing.is_empty() {
+        bail!("topic `{topic}` does not exist or has no partitions");
+    }
+    match requested {
+        Some(ps) => {
+            if let Some(p) = ps.iter().find(|p| !existing.contains(p)) {
+                bail!("topic `{topic}` has no partition {p}");
+            }
+            Ok(ps.to_vec())
+        }
+        None => Ok(existing),
+    }
+}
+
+/// Parses a comma-separated partition list such as `0,2,5`.
+pub fn parse_partitions(list: &str) -> Result<Vec<i32>> {
+    list.split(',')
+        .map(|p| p.trim().parse())
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("invalid partition list `{list}`"))
+}
@@ -0,0 +1,186 @@
+//! Topic renaming and offset translation for the `mirror` binary.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+/// Maps source topic names to target names. Rules are `from=to`, tried in
+/// order; a trailing `*` on both sides keeps the rest of the name, so
+/// `demo.*=dc1.demo.*` maps `demo.events` to `dc1.demo.events`. Topics no rule
+/// matches keep their name.
+#[derive(Debug, Clone, Default)]
+pub struct RenameRules {
+    rules: Vec<(String, String)>,
+}
+
+impl RenameRules {
+    pub fn add(&mut self, rule: &str) -> Result<()> {
+        let Some((from, to)) = rule.split_once('=') else {
+            bail!("invalid rename rule `{rule}` (expected from=to)");
+        };
+        if from.ends_with('*') != to.ends_with('*') {
+            bail!("invalid rename rule `{rule}` (use `*` at the end of both sides or neither)");
+        }
+        self.rules.push((from.to_string(), to.to_string()));
+        Ok(())
+    }
+
+    pub fn apply(&self, topic: &str) -> String {
+        for (from, to) in &self.rules {
+            match (from.strip_suffix('*'), to.strip_suffix('*')) {
+                (Some(prefix), Some(target)) => {
+                    if let Some(rest) = topic.strip_prefix(prefix) {
+                        return format!("{target}{rest}");
+                    }
+                }
+                _ if from == topic => return to.clone(),
+                _ => {}
+            }
+        }
+        topic.to_string()
+    }
+}
+
+/// One entry of the offset translation file: `source_offset` was written to
+/// the target at `target_offset`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OffsetSync {
+    pub source_topic: String,
+    pub source_partition: i32,
+    pub source_offset: i64,
+    pub target_topic: String,
+    pub target_partition: i32,
+    pub target_offset: i64,
+}
+
+/// Offset translation table, kept in memory and appended to a JSON Lines file.
+///
+/// Like MirrorMaker 2's offset syncs it is sparse: an entry is written every
+/// `every` records of a partition, or sooner when the distance between source
+/// and target offsets changes (compaction, retries, several source partitions
+/// sharing a target one). [`flush`](Self::flush) also writes the latest
+/// sync of each partition, so translation is exact up to the last flush.
+pub struct OffsetSyncs {
+    /// Per source partition: source offset → sync.
+    table: HashMap<(String, i32), BTreeMap<i64, OffsetSync>>,
+    /// Per source partition: the last sync observed but not written yet.
+    latest: HashMap<(String, i32), OffsetSync>,
+    out: Option<BufWriter<File>>,
+    every: i64,
+}
+
+impl OffsetSyncs {
+    /// Loads the entries already in `path` and appends new ones to it.
+    pub fn open(path: &Path, every: i64) -> io::Result<Self> {
+        let mut syncs = Self::load(path)?;
+        syncs.every = every.max(1);
+        syncs.out = Some(BufWriter::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ));
+        Ok(syncs)
+    }
+
+    /// Reads the entries of `path` (none if it does not exist), read-only.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut syncs = Self {
+            table: HashMap::new(),
+            latest: HashMap::new(),
+            out: None,
+            every: 1,
+        };
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(syncs),
+            Err(e) => return Err(e),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            // A crash can leave a partial last line; the entries before it still hold.
+            if let Ok(sync) = serde_json::from_str::<OffsetSync>(&line) {
+                syncs.insert(sync);
+            }
+        }
+        Ok(syncs)
+    }
+
+    fn insert(&mut self, sync: OffsetSync) {
+        self.table
+            .entry((sync.source_topic.clone(), sync.source_partition))
+            .or_default()
+            .insert(sync.source_offset, sync);
+    }
+
+    fn write(&mut self, sync: OffsetSync) -> io::Result<()> {
+        if let Some(out) = &mut self.out {
+            serde_json::to_writer(&mut *out, &sync)?;
+            out.write_all(b"\n")?;
+        }
+        self.insert(sync);
+        Ok(())
+    }
+
+    /// Records a delivered record, writing an entry when one is due.
+    pub fn observe(&mut self, sync: OffsetSync) -> io::Result<()> {
+        let partition = (sync.source_topic.clone(), sync.source_partition);
+        let last = self
+            .table
+            .get(&partition)
+            .and_then(|t| t.last_key_value())
+            .map(|(_, s)| s);
+        let due = match last {
+            None => true,
+            Some(last) => {
+                last.target_topic != sync.target_topic
+                    || last.target_partition != sync.target_partition
+                    || sync.source_offset - last.source_offset >= self.every
+                    || sync.target_offset - last.target_offset
+                        != sync.source_offset - last.source_offset
+            }
+        };
+        if due {
+            self.latest.remove(&partition);
+            self.write(sync)
+        } else {
+            self.latest.insert(partition, sync);
+            Ok(())
+        }
+    }
+
+    /// Writes the latest sync of every partition and flushes the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        for (_, sync) in std::mem::take(&mut self.latest) {
+            self.write(sync)?;
+        }
+        match &mut self.out {
+            Some(out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Where a consumer that committed `committed` on the source should
+    /// resume on the target: right after the last sync below `committed`.
+    ///
+    /// Exact when that sync is for `committed - 1`; otherwise the consumer
+    /// re-reads the records mirrored after the sync, never skips one.
+    pub fn translate(
+        &self,
+        topic: &str,
+        partition: i32,
+        committed: i64,
+    ) -> Option<(String, i32, i64)> {
+        let (_, sync) = self
+            .table
+            .get(&(topic.to_string(), partition))?
+            .range(..committed)
+            .next_back()?;
+        Some((
+            sync.target_topic.clone(),
+            sync.target_partition,
+            sync.target_offset + 1,
+        ))
+    }
+}
@@ -0,0 +1,119 @@
+//! kcat-style output templates for `kafkatool consume`, e.g. `%p %o %k %s\n`.
+
+use std::io::{self, Write};
+
+use rdkafka::message::{Headers, Message};
+
+/// Placeholders understood by [`Template::parse`], for `--help` style listings.
+pub const PLACEHOLDERS: &str = "%t topic, %p partition, %o offset, %k key, %s payload, \
+%K key length, %S payload length, %T timestamp (ms), %h headers (k=v,...), %% a percent sign; \
+escapes \\n \\t \\\\";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(String),
+    Topic,
+    Partition,
+    Offset,
+    Key,
+    Payload,
+    KeyLen,
+    PayloadLen,
+    Timestamp,
+    Headers,
+}
+
+/// A parsed output template.
+#[derive(Debug, Clone)]
+pub struct Template {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TemplateError {
+    #[error("unknown placeholder `%{0}` (known: {PLACEHOLDERS})")]
+    UnknownPlaceholder(char),
+    #[error("template ends with a lone `{0}`")]
+    Dangling(char),
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self, TemplateError> {
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '%' => match chars.next().ok_or(TemplateError::Dangling('%'))? {
+                    't' => Token::Topic,
+                    'p' => Token::Partition,
+                    'o' => Token::Offset,
+                    'k' => Token::Key,
+                    's' => Token::Payload,
+                    'K' => Token::KeyLen,
+                    'S' => Token::PayloadLen,
+                    'T' => Token::Timestamp,
+                    'h' => Token::Headers,
+                    '%' => {
+                        literal.push('%');
+                        continue;
+                    }
+                    other => return Err(TemplateError::UnknownPlaceholder(other)),
+                },
+                '\\' => {
+                    match chars.next().ok_or(TemplateError::Dangling('\\'))? {
+                        'n' => literal.push('\n'),
+                        't' => literal.push('\t'),
+                        other => literal.push(other),
+                    }
+                    continue;
+                }
+                c => {
+                    literal.push(c);
+                    continue;
+                }
+            };
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(std::mem::take(&mut literal)));
+            }
+            tokens.push(token);
+        }
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+        }
+        Ok(Self { tokens })
+    }
+
+    /// Writes `m` as the template describes. Keys and payloads are written as
+    /// raw bytes; a null key or payload writes nothing, and its length is `-1`.
+    pub fn render<M: Message>(&self, m: &M, out: &mut impl Write) -> io::Result<()> {
+        let len = |b: Option<&[u8]>| b.map_or(-1, |b| b.len() as i64);
+        for token in &self.tokens {
+            match token {
+                Token::Literal(s) => out.write_all(s.as_bytes())?,
+                Token::Topic => out.write_all(m.topic().as_bytes())?,
+                Token::Partition => write!(out, "{}", m.partition())?,
+                Token::Offset => write!(out, "{}", m.offset())?,
+                Token::Key => out.write_all(m.key().unwrap_or_default())?,
+                Token::Payload => out.write_all(m.payload().unwrap_or_default())?,
+                Token::KeyLen => write!(out, "{}", len(m.key()))?,
+                Token::PayloadLen => write!(out, "{}", len(m.payload()))?,
+                Token::Timestamp => match m.timestamp().to_millis() {
+                    Some(ms) => write!(out, "{ms}")?,
+                    None => out.write_all(b"-1")?,
+                },
+                Token::Headers => {
+                    for (i, h) in m.headers().iter().flat_map(|hs| hs.iter()).enumerate() {
+                        if i > 0 {
+                            out.write_all(b",")?;
+                        }
+                        out.write_all(h.key.as_bytes())?;
+                        out.write_all(b"=")?;
+                        out.write_all(h.value.unwrap_or_default())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}